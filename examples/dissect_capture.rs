@@ -0,0 +1,17 @@
+// Prints the PICOBOOT commands in a usbmon capture
+
+use picoboot_rs::dissect::{dissect, read_capture};
+
+use std::fs::File;
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: dissect_capture <capture.pcap>");
+    let file = File::open(path).expect("failed to open capture");
+
+    let transfers = read_capture(file).expect("failed to read capture");
+    for entry in dissect(&transfers) {
+        println!("{}", entry);
+    }
+}
@@ -0,0 +1,618 @@
+use crate::{
+    cmd::{PicobootCmd, PicobootError, TargetID},
+    elf::ElfFile,
+    image::SparseImage,
+    info::{ChipInfo, PartitionInfo, PartitionTableInfo, SysInfo, Uf2Status},
+    progress::CancelToken,
+    transport::PicobootTransport,
+    usb::{LoadOptions, PicobootConnection},
+    Reboot2Options,
+};
+#[cfg(feature = "rusb")]
+use crate::{transport::RusbTransport, DeviceSelector};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[cfg(feature = "rusb")]
+use rusb::{DeviceDescriptor, UsbContext};
+use tokio::sync::Mutex;
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// An asynchronous connection to a PICOBOOT device
+///
+/// Wraps a [`PicobootConnection`] and runs each of its operations on the
+/// blocking thread pool of the tokio runtime, so USB transfers do not stall
+/// executor threads. Operations on one connection run in the order they are
+/// issued.
+///
+/// # Cancellation
+///
+/// Every operation can be cancelled by dropping its future, for example with
+/// `tokio::time::timeout` or `tokio::select!`. An operation that has not
+/// started yet is skipped entirely. An operation that is already talking to
+/// the device runs to completion in the background, so the PICOBOOT interface
+/// is never left in the middle of a command; the next operation on the
+/// connection waits for it to finish.
+#[derive(Debug)]
+pub struct AsyncPicobootConnection<T: PicobootTransport> {
+    inner: Arc<Mutex<PicobootConnection<T>>>,
+    target_id: TargetID,
+    chip_info: Option<ChipInfo>,
+    cancel: CancelToken,
+}
+#[cfg(feature = "rusb")]
+impl<T: UsbContext + 'static> AsyncPicobootConnection<RusbTransport<T>> {
+    /// Creates a new asynchronous PICOBOOT connection
+    ///
+    /// See [`PicobootConnection::new`].
+    ///
+    /// # Errors
+    /// - [`Error::CmdTaskFailure`]
+    /// - Any produced by [`PicobootConnection::new`]
+    pub async fn new(ctx: T, vidpid: Option<(u16, u16)>) -> Result<Self> {
+        let conn = tokio::task::spawn_blocking(move || PicobootConnection::new(ctx, vidpid))
+            .await
+            .map_err(|_| Error::CmdTaskFailure)??;
+        Ok(Self::from_connection(conn))
+    }
+
+    /// Creates a new asynchronous PICOBOOT connection to a selected device
+    ///
+    /// See [`PicobootConnection::open`].
+    ///
+    /// # Errors
+    /// - [`Error::CmdTaskFailure`]
+    /// - Any produced by [`PicobootConnection::open`]
+    pub async fn open(ctx: T, selector: DeviceSelector<DeviceDescriptor>) -> Result<Self> {
+        let conn = tokio::task::spawn_blocking(move || PicobootConnection::open(ctx, &selector))
+            .await
+            .map_err(|_| Error::CmdTaskFailure)??;
+        Ok(Self::from_connection(conn))
+    }
+}
+impl<T: PicobootTransport + Send + 'static> AsyncPicobootConnection<T> {
+    /// Creates a new asynchronous PICOBOOT connection from a blocking one.
+    pub fn from_connection(conn: PicobootConnection<T>) -> Self {
+        AsyncPicobootConnection {
+            target_id: conn.get_device_type(),
+            chip_info: conn.get_chip_info(),
+            cancel: conn.get_cancel_token(),
+            inner: Arc::new(Mutex::new(conn)),
+        }
+    }
+
+    /// Consumes the connection, returning the blocking connection.
+    ///
+    /// Waits for any cancelled operation still running in the background.
+    pub async fn into_connection(self) -> PicobootConnection<T> {
+        let mut inner = self.inner;
+        loop {
+            match Arc::try_unwrap(inner) {
+                Ok(conn) => return conn.into_inner(),
+                Err(arc) => {
+                    // a background operation holds a reference until it ends
+                    drop(arc.lock().await);
+                    tokio::task::yield_now().await;
+                    inner = arc;
+                }
+            }
+        }
+    }
+
+    /// Runs an operation on the blocking connection in the blocking thread
+    /// pool.
+    async fn run<R, F>(&mut self, f: F) -> Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut PicobootConnection<T>) -> Result<R> + Send + 'static,
+    {
+        // marks the operation cancelled if this future is dropped early
+        struct CancelOnDrop {
+            cancelled: Arc<AtomicBool>,
+            armed: bool,
+        }
+        impl Drop for CancelOnDrop {
+            fn drop(&mut self) {
+                if self.armed {
+                    self.cancelled.store(true, Ordering::SeqCst);
+                }
+            }
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut guard = CancelOnDrop {
+            cancelled: Arc::clone(&cancelled),
+            armed: true,
+        };
+
+        // lock before spawning, so operations reach the device in order
+        let mut conn = Arc::clone(&self.inner).lock_owned().await;
+        let res = tokio::task::spawn_blocking(move || {
+            if cancelled.load(Ordering::SeqCst) {
+                return Err(Error::CmdCancelled);
+            }
+            f(&mut conn)
+        })
+        .await;
+
+        guard.armed = false;
+        res.map_err(|_| Error::CmdTaskFailure)?
+    }
+
+    /// Sends a command to the device
+    ///
+    /// See [`PicobootConnection::cmd`].
+    ///
+    /// # Errors
+    /// - [`Error::CmdTaskFailure`]
+    /// - Any produced by [`PicobootConnection::cmd`]
+    pub async fn cmd(&mut self, cmd: PicobootCmd, buf: Vec<u8>) -> Result<Vec<u8>> {
+        self.run(move |c| c.cmd(cmd, &buf)).await
+    }
+
+    /// Requests non-exclusive access with the device, and does not close the
+    /// USB Mass Storage interface.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn access_not_exclusive(&mut self) -> Result<()> {
+        self.run(|c| c.access_not_exclusive()).await
+    }
+
+    /// Requests exclusive access with the device, and disables the USB Mass
+    /// Storage interface.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn access_exclusive(&mut self) -> Result<()> {
+        self.run(|c| c.access_exclusive()).await
+    }
+
+    /// Requests exclusive access with the device, and disables and ejects the
+    /// USB Mass Storage interface.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn access_exclusive_eject(&mut self) -> Result<()> {
+        self.run(|c| c.access_exclusive_eject()).await
+    }
+
+    /// Reboots the device with a specified program counter, stack pointer, and
+    /// delay in milliseconds.
+    ///
+    /// See [`PicobootConnection::reboot`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn reboot(&mut self, pc: u32, sp: u32, delay: u32) -> Result<()> {
+        self.run(move |c| c.reboot(pc, sp, delay)).await
+    }
+
+    /// Reboots the device with a delay in milliseconds. (Only for RP2350)
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - Any produced by [`Self::cmd`]
+    pub async fn reboot2_normal(&mut self, delay: u32) -> Result<()> {
+        self.run(move |c| c.reboot2_normal(delay)).await
+    }
+
+    /// Reboots the device into a boot type with options, after a delay in
+    /// milliseconds. (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::reboot2`].
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - Any produced by [`Self::cmd`]
+    pub async fn reboot2(&mut self, options: Reboot2Options, delay: u32) -> Result<()> {
+        self.run(move |c| c.reboot2(options, delay)).await
+    }
+
+    /// Erases the flash memory of the device.
+    ///
+    /// See [`PicobootConnection::flash_erase`].
+    ///
+    /// # Errors:
+    /// - [`Error::EraseInvalidAddr`]
+    /// - [`Error::EraseInvalidSize`]
+    /// - Any produced by [`Self::cmd`]
+    pub async fn flash_erase(&mut self, addr: u32, size: u32) -> Result<()> {
+        self.run(move |c| c.flash_erase(addr, size)).await
+    }
+
+    /// Writes a buffer to the flash memory of the device.
+    ///
+    /// See [`PicobootConnection::flash_write`].
+    ///
+    /// # Errors:
+    /// - [`Error::WriteInvalidAddr`]
+    /// - Any produced by [`Self::cmd`]
+    pub async fn flash_write(&mut self, addr: u32, buf: Vec<u8>) -> Result<()> {
+        self.run(move |c| c.flash_write(addr, &buf)).await
+    }
+
+    /// Reads a buffer from the flash memory of the device.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn flash_read(&mut self, addr: u32, size: u32) -> Result<Vec<u8>> {
+        self.run(move |c| c.flash_read(addr, size)).await
+    }
+
+    /// Erases a range of flash memory, one chunk at a time.
+    ///
+    /// See [`PicobootConnection::erase_range`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::erase_range`]
+    pub async fn erase_range(&mut self, addr: u32, size: u32) -> Result<()> {
+        self.run(move |c| c.erase_range(addr, size)).await
+    }
+
+    /// Reads a range of memory, one chunk at a time.
+    ///
+    /// See [`PicobootConnection::dump`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::dump`]
+    pub async fn dump(&mut self, addr: u32, size: u32) -> Result<Vec<u8>> {
+        self.run(move |c| c.dump(addr, size)).await
+    }
+
+    /// Reads the memory covered by an image back and compares it with the
+    /// image.
+    ///
+    /// See [`PicobootConnection::verify`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::verify`]
+    pub async fn verify(&mut self, image: SparseImage) -> Result<()> {
+        self.run(move |c| c.verify(&image)).await
+    }
+
+    /// Loads an image into the flash memory of the device.
+    ///
+    /// See [`PicobootConnection::load`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::load`]
+    pub async fn load(&mut self, image: SparseImage, options: LoadOptions) -> Result<()> {
+        self.run(move |c| c.load(&image, options)).await
+    }
+
+    /// Loads the loadable segments of an ELF file into the flash memory and
+    /// RAM of the device.
+    ///
+    /// See [`PicobootConnection::load_elf`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::load_elf`]
+    pub async fn load_elf(&mut self, elf: ElfFile, options: LoadOptions) -> Result<()> {
+        self.run(move |c| c.load_elf(&elf, options)).await
+    }
+
+    /// Calls a function in RAM on the device. (Only for RP2040)
+    ///
+    /// See [`PicobootConnection::exec`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::exec`]
+    pub async fn exec(&mut self, addr: u32) -> Result<()> {
+        self.run(move |c| c.exec(addr)).await
+    }
+
+    /// Copies the table of flash functions used by the bootrom into RAM.
+    /// (Only for RP2040)
+    ///
+    /// See [`PicobootConnection::vectorize_flash`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::vectorize_flash`]
+    pub async fn vectorize_flash(&mut self, addr: u32) -> Result<()> {
+        self.run(move |c| c.vectorize_flash(addr)).await
+    }
+
+    /// Reads OTP rows in raw mode. (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::otp_read_raw`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::otp_read_raw`]
+    pub async fn otp_read_raw(&mut self, row: u16, row_count: u16) -> Result<Vec<u32>> {
+        self.run(move |c| c.otp_read_raw(row, row_count)).await
+    }
+
+    /// Reads OTP rows in ECC mode. (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::otp_read_ecc`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::otp_read_ecc`]
+    pub async fn otp_read_ecc(&mut self, row: u16, row_count: u16) -> Result<Vec<u16>> {
+        self.run(move |c| c.otp_read_ecc(row, row_count)).await
+    }
+
+    /// Writes OTP rows in raw mode. (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::otp_write_raw`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::otp_write_raw`]
+    pub async fn otp_write_raw(&mut self, row: u16, values: Vec<u32>) -> Result<()> {
+        self.run(move |c| c.otp_write_raw(row, &values)).await
+    }
+
+    /// Writes OTP rows in ECC mode. (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::otp_write_ecc`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::otp_write_ecc`]
+    pub async fn otp_write_ecc(&mut self, row: u16, values: Vec<u16>) -> Result<()> {
+        self.run(move |c| c.otp_write_ecc(row, &values)).await
+    }
+
+    /// Enter Flash XIP (execute-in-place) mode.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn enter_xip(&mut self) -> Result<()> {
+        self.run(|c| c.enter_xip()).await
+    }
+
+    /// Exits Flash XIP (execute-in-place) mode.
+    ///
+    /// # Errors:
+    /// - Any produced by [`Self::cmd`]
+    pub async fn exit_xip(&mut self) -> Result<()> {
+        self.run(|c| c.exit_xip()).await
+    }
+
+    /// Resets PICOBOOT USB interface.
+    ///
+    /// See [`PicobootConnection::reset_interface`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::reset_interface`]
+    pub async fn reset_interface(&mut self) -> Result<()> {
+        self.run(|c| c.reset_interface()).await
+    }
+
+    /// Queries the identity of the device and updates the target of the
+    /// connection to match.
+    ///
+    /// See [`PicobootConnection::identify`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::identify`]
+    pub async fn identify(&mut self) -> Result<ChipInfo> {
+        let info = self.run(|c| c.identify()).await?;
+        self.target_id = info.get_target_id();
+        self.chip_info = Some(info);
+        Ok(info)
+    }
+
+    /// Queries system information with GET_INFO (INFO_SYS). (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::get_sys_info`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::get_sys_info`]
+    pub async fn get_sys_info(&mut self, flags: u32) -> Result<SysInfo> {
+        self.run(move |c| c.get_sys_info(flags)).await
+    }
+
+    /// Queries the partition table with GET_INFO (INFO_PARTITION_TABLE).
+    /// (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::get_partition_table_info`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::get_partition_table_info`]
+    pub async fn get_partition_table_info(&mut self, flags: u32) -> Result<PartitionTableInfo> {
+        self.run(move |c| c.get_partition_table_info(flags)).await
+    }
+
+    /// Queries a single partition with GET_INFO (INFO_PARTITION_TABLE).
+    /// (Only for RP2350)
+    ///
+    /// See [`PicobootConnection::get_partition_info`].
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::get_partition_info`]
+    pub async fn get_partition_info(&mut self, partition: u8, flags: u32) -> Result<PartitionInfo> {
+        self.run(move |c| c.get_partition_info(partition, flags))
+            .await
+    }
+
+    /// Queries the partition a UF2 download of a family would be written to
+    /// with GET_INFO (INFO_UF2_TARGET_PARTITION). (Only for RP2350)
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::get_uf2_target_partition`]
+    pub async fn get_uf2_target_partition(
+        &mut self,
+        family_id: u32,
+    ) -> Result<Option<PartitionInfo>> {
+        self.run(move |c| c.get_uf2_target_partition(family_id))
+            .await
+    }
+
+    /// Queries the status of the last UF2 download with GET_INFO
+    /// (INFO_UF2_STATUS). (Only for RP2350)
+    ///
+    /// # Errors:
+    /// - Any produced by [`PicobootConnection::get_uf2_status`]
+    pub async fn get_uf2_status(&mut self) -> Result<Uf2Status> {
+        self.run(|c| c.get_uf2_status()).await
+    }
+
+    /// Closes the connection
+    ///
+    /// Waits for any cancelled operation still running in the background.
+    ///
+    /// See [`PicobootConnection::close`].
+    ///
+    /// # Errors
+    /// - [`Error::CmdTaskFailure`]
+    /// - Any produced by [`PicobootConnection::close`]
+    pub async fn close(self) -> Result<()> {
+        let conn = self.into_connection().await;
+        tokio::task::spawn_blocking(move || conn.close())
+            .await
+            .map_err(|_| Error::CmdTaskFailure)?
+    }
+
+    /// Returns PICOBOOT device type.
+    pub fn get_device_type(&self) -> TargetID {
+        self.target_id
+    }
+
+    /// Returns the device identity found when the connection was opened, or
+    /// by the last call to [`Self::identify`].
+    pub fn get_chip_info(&self) -> Option<ChipInfo> {
+        self.chip_info
+    }
+
+    /// Returns the token checked between the commands of multi-command
+    /// operations, such as [`Self::load`].
+    ///
+    /// Dropping the future of an operation only skips it if it has not
+    /// started yet. Cancelling the token also stops an operation that is
+    /// already running, before its next command.
+    pub fn get_cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emu::PicobootEmulator,
+        protocol::PicobootCmdId,
+        transport::record::{RecordingTransport, Transfer},
+        FLASH_START, SECTOR_SIZE,
+    };
+
+    use std::{
+        future::Future,
+        sync::mpsc,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    };
+
+    type Emulator = RecordingTransport<PicobootEmulator>;
+
+    fn connect() -> AsyncPicobootConnection<Emulator> {
+        let transport = RecordingTransport::new(PicobootEmulator::new(TargetID::Rp2040));
+        let conn = PicobootConnection::from_transport(transport, TargetID::Rp2040);
+        AsyncPicobootConnection::from_connection(conn)
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .max_blocking_threads(1)
+            .build()
+            .unwrap()
+    }
+
+    fn noop_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        unsafe { Waker::from_raw(clone(std::ptr::null())) }
+    }
+
+    /// Returns the ids of the commands sent to the device, in order.
+    fn sent_cmds(conn: &PicobootConnection<Emulator>) -> Vec<PicobootCmdId> {
+        conn.transport()
+            .get_recording()
+            .get_transfers()
+            .iter()
+            .filter_map(|t| match t.get_transfer() {
+                Transfer::WriteBulk { data, .. } => PicobootCmd::from_bytes(data).ok(),
+                _ => None,
+            })
+            .map(|c| c.get_cmd_id().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn operations_run_in_order() {
+        runtime().block_on(async {
+            let mut conn = connect();
+            conn.access_exclusive().await.unwrap();
+            conn.exit_xip().await.unwrap();
+            conn.flash_erase(FLASH_START, SECTOR_SIZE).await.unwrap();
+            conn.flash_write(FLASH_START, vec![0x5A; 256])
+                .await
+                .unwrap();
+            assert_eq!(
+                conn.flash_read(FLASH_START, 4).await.unwrap(),
+                vec![0x5A; 4]
+            );
+
+            let conn = conn.into_connection().await;
+            assert_eq!(
+                sent_cmds(&conn),
+                [
+                    PicobootCmdId::ExclusiveAccess,
+                    PicobootCmdId::ExitXip,
+                    PicobootCmdId::FlashErase,
+                    PicobootCmdId::Write,
+                    PicobootCmdId::Read,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn dropped_operation_is_skipped() {
+        runtime().block_on(async {
+            let mut conn = connect();
+
+            // occupy the only blocking thread, so the next operation queues
+            let (started_tx, started_rx) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            let blocker = tokio::task::spawn_blocking(move || {
+                started_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+            started_rx.recv().unwrap();
+
+            // start an erase and drop it while it waits for a thread
+            {
+                let mut erase = Box::pin(conn.flash_erase(FLASH_START, SECTOR_SIZE));
+                let waker = noop_waker();
+                let mut cx = Context::from_waker(&waker);
+                assert!(matches!(erase.as_mut().poll(&mut cx), Poll::Pending));
+            }
+            release_tx.send(()).unwrap();
+            blocker.await.unwrap();
+
+            // later operations run, after the skipped one
+            assert!(!conn.get_cancel_token().is_cancelled());
+            conn.flash_write(FLASH_START, vec![0x5A; 256])
+                .await
+                .unwrap();
+            assert_eq!(
+                conn.flash_read(FLASH_START, 4).await.unwrap(),
+                vec![0x5A; 4]
+            );
+
+            let conn = conn.into_connection().await;
+            assert_eq!(
+                sent_cmds(&conn),
+                [PicobootCmdId::Write, PicobootCmdId::Read]
+            );
+        });
+    }
+}
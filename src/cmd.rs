@@ -1,7 +1,12 @@
-use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::PICOBOOT_MAGIC;
+use crate::transport::TransportError;
+
+pub use crate::protocol::{
+    PicobootCmd, PicobootCmdId, PicobootGetInfoCmd, PicobootOtpCmd, PicobootRangeCmd,
+    PicobootReboot2Cmd, PicobootRebootCmd, PicobootStatus, PicobootStatusCmd, ProtocolError,
+    TargetID,
+};
 
 /// Error type for this crate.
 #[derive(Error, Debug)]
@@ -9,9 +14,12 @@ pub enum PicobootError {
     /// USB device not found.
     #[error("usb device not found")]
     UsbDeviceNotFound,
+    /// Failed to list USB devices.
+    #[error("failed to list usb devices: {0}")]
+    UsbListDevicesFailure(TransportError),
     /// USB device found, but failed to open.
     #[error("usb device found but can't open: {0}")]
-    UsbDeviceFailedToOpen(rusb::Error),
+    UsbDeviceFailedToOpen(TransportError),
     /// Failed to get USB bulk endpoints.
     #[error("failed to get usb bulk endpoints")]
     UsbEndpointsNotFound,
@@ -20,50 +28,65 @@ pub enum PicobootError {
     UsbEndpointsUnexpected,
     /// Failed to detach USB kernel driver.
     #[error("failed to detach usb kernel driver: {0}")]
-    UsbDetachKernelDriverFailure(rusb::Error),
+    UsbDetachKernelDriverFailure(TransportError),
     /// Failed to claim USB interface.
     #[error("failed to claim usb interface: {0}")]
-    UsbClaimInterfaceFailure(rusb::Error),
+    UsbClaimInterfaceFailure(TransportError),
     /// Failed to configure alt USB setting.
     #[error("failed to set alt usb setting: {0}")]
-    UsbSetAltSettingFailure(rusb::Error),
+    UsbSetAltSettingFailure(TransportError),
+    /// Failed to release USB interface.
+    #[error("failed to release usb interface: {0}")]
+    UsbReleaseInterfaceFailure(TransportError),
+    /// Failed to reattach USB kernel driver.
+    #[error("failed to reattach usb kernel driver: {0}")]
+    UsbAttachKernelDriverFailure(TransportError),
     /// Failed to read from USB bulk endpoint.
     #[error("failed to read bulk: {0}")]
-    UsbReadBulkFailure(rusb::Error),
+    UsbReadBulkFailure(TransportError),
     /// Read data from USB does not match expected size.
     #[error("read did not match expected size")]
     UsbReadBulkMismatch,
     /// Failed to write to USB bulk endpoint.
     #[error("failed to write bulk: {0}")]
-    UsbWriteBulkFailure(rusb::Error),
+    UsbWriteBulkFailure(TransportError),
     /// Written data to USB does not match expected size.
     #[error("write did not match expected size")]
     UsbWriteBulkMismatch,
 
     /// Failed to clear USB in address halt.
     #[error("failed to clear in addr halt: {0}")]
-    UsbClearInAddrHalt(rusb::Error),
+    UsbClearInAddrHalt(TransportError),
     /// Failed to clear USB out address halt.
     #[error("failed to clear out addr halt: {0}")]
-    UsbClearOutAddrHalt(rusb::Error),
+    UsbClearOutAddrHalt(TransportError),
     /// Failed to reset USB interface.
     #[error("failed to reset interface: {0}")]
-    UsbResetInterfaceFailure(rusb::Error),
+    UsbResetInterfaceFailure(TransportError),
 
     /// Failed to get command status from device.
     #[error("failed to get command status: {0}")]
-    UsbGetCommandStatusFailure(rusb::Error),
-
-    /// Failed to serialize command for device.
-    #[error("cmd failed to binary serialize: {0}")]
-    CmdSerializeFailure(bincode::Error),
-    /// Failed to deserialize command from device.
-    #[error("cmd failed to binary deserialize: {0}")]
-    CmdDeserializeFailure(bincode::Error),
+    UsbGetCommandStatusFailure(TransportError),
+
+    /// Command or status packet does not have the expected length.
+    #[error("cmd packet length invalid: expected {expected}, got {got}")]
+    CmdPacketLengthInvalid { expected: usize, got: usize },
+    /// Command packet does not start with [`crate::PICOBOOT_MAGIC`].
+    #[error("cmd magic invalid: {0:#010x}")]
+    CmdMagicInvalid(u32),
+    /// Command ID is not a known [`PicobootCmdId`].
+    #[error("cmd id unknown: {0:#04x}")]
+    CmdUnknownId(u8),
 
     /// Command is not allowed for target device.
     #[error("cmd not allowed for target device")]
     CmdNotAllowedForTarget,
+    /// Command was cancelled before it was sent to the device.
+    #[error("cmd cancelled")]
+    CmdCancelled,
+    /// Background task running a command panicked or was shut down.
+    #[error("cmd task failed")]
+    CmdTaskFailure,
 
     /// Erase command address invalid.
     #[error("erase address invalid")]
@@ -75,310 +98,232 @@ pub enum PicobootError {
     /// Write command address invalid.
     #[error("write address invalid")]
     WriteInvalidAddr,
-}
-
-// see https://datasheets.raspberrypi.com/rp2040/rp2040-datasheet.pdf
-// section 2.8.5 for details on PICOBOOT interface
-
-/// The type of microcontroller detected as the PICOBOOT device.
-#[derive(Debug, Clone, Copy)]
-pub enum TargetID {
-    /// RP2040 MCU target.
-    Rp2040,
-    /// RP2350 MCU target.
-    Rp2350,
-}
-
-/// Command ID of commands for PICOBOOT interface.
-#[derive(Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum PicobootCmdId {
-    Unknown = 0x0,
-    ExclusiveAccess = 0x1,
-    Reboot = 0x2,
-    FlashErase = 0x3,
-    Read = 0x84, // either RAM or FLASH
-    Write = 0x5, // either RAM or FLASH (does no erase)
-    ExitXip = 0x6,
-    EnterCmdXip = 0x7,
-    Exec = 0x8,
-    VectorizeFlash = 0x9,
-    // RP2350 only below here
-    Reboot2 = 0xA,
-    GetInfo = 0x8B,
-    OtpRead = 0x8C,
-    OtpWrite = 0xD,
-    //Exec2 = 0xE, // currently unused
-}
-impl TryFrom<u8> for PicobootCmdId {
-    type Error = ();
-
-    fn try_from(x: u8) -> Result<Self, Self::Error> {
-        match x {
-            x if x == Self::Unknown as u8 => Ok(Self::Unknown),
-            x if x == Self::ExclusiveAccess as u8 => Ok(Self::ExclusiveAccess),
-            x if x == Self::Reboot as u8 => Ok(Self::Reboot),
-            x if x == Self::FlashErase as u8 => Ok(Self::FlashErase),
-            x if x == Self::Read as u8 => Ok(Self::Read),
-            x if x == Self::Write as u8 => Ok(Self::Write),
-            x if x == Self::ExitXip as u8 => Ok(Self::ExitXip),
-            x if x == Self::EnterCmdXip as u8 => Ok(Self::EnterCmdXip),
-            x if x == Self::Exec as u8 => Ok(Self::Exec),
-            x if x == Self::VectorizeFlash as u8 => Ok(Self::VectorizeFlash),
-            x if x == Self::Reboot2 as u8 => Ok(Self::Reboot2),
-            x if x == Self::GetInfo as u8 => Ok(Self::GetInfo),
-            x if x == Self::OtpRead as u8 => Ok(Self::OtpRead),
-            x if x == Self::OtpWrite as u8 => Ok(Self::OtpWrite),
-            // x if x == Self::Exec2 as u8 => Ok(Self::Exec2),
-            _ => Err(()),
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-#[repr(u32)]
-pub enum PicobootStatus {
-    Ok = 0,
-    UnknownCmd = 1,
-    InvalidCmdLength = 2,
-    InvalidTransferLength = 3,
-    InvalidAddress = 4,
-    BadAlignment = 5,
-    InterleavedWrite = 6,
-    Rebooting = 7,
-    UnknownError = 8,
-    InvalidState = 9,
-    NotPermitted = 10,
-    InvalidArg = 11,
-    BufferTooSmall = 12,
-    PreconditionNotMet = 13,
-    ModifiedData = 14,
-    InvalidData = 15,
-    NotFound = 16,
-    UnsupportedModification = 17,
-}
-impl TryFrom<u32> for PicobootStatus {
-    type Error = ();
-
-    fn try_from(x: u32) -> Result<Self, Self::Error> {
-        match x {
-            x if x == Self::Ok as u32 => Ok(Self::Ok),
-            x if x == Self::UnknownCmd as u32 => Ok(Self::UnknownCmd),
-            x if x == Self::InvalidCmdLength as u32 => Ok(Self::InvalidCmdLength),
-            x if x == Self::InvalidTransferLength as u32 => Ok(Self::InvalidTransferLength),
-            x if x == Self::InvalidAddress as u32 => Ok(Self::InvalidAddress),
-            x if x == Self::BadAlignment as u32 => Ok(Self::BadAlignment),
-            x if x == Self::InterleavedWrite as u32 => Ok(Self::InterleavedWrite),
-            x if x == Self::Rebooting as u32 => Ok(Self::Rebooting),
-            x if x == Self::UnknownError as u32 => Ok(Self::UnknownError),
-            x if x == Self::InvalidState as u32 => Ok(Self::InvalidState),
-            x if x == Self::NotPermitted as u32 => Ok(Self::NotPermitted),
-            x if x == Self::InvalidArg as u32 => Ok(Self::InvalidArg),
-            x if x == Self::BufferTooSmall as u32 => Ok(Self::BufferTooSmall),
-            x if x == Self::PreconditionNotMet as u32 => Ok(Self::PreconditionNotMet),
-            x if x == Self::ModifiedData as u32 => Ok(Self::ModifiedData),
-            x if x == Self::InvalidData as u32 => Ok(Self::InvalidData),
-            x if x == Self::NotFound as u32 => Ok(Self::NotFound),
-            x if x == Self::UnsupportedModification as u32 => Ok(Self::UnsupportedModification),
-            _ => Err(()),
-        }
-    }
-}
 
-#[derive(Serialize, Debug, Clone)]
-#[repr(C, packed)]
-struct PicobootRangeCmd {
-    addr: u32,
-    size: u32,
-    _unused: u64,
+    /// Exec command address invalid.
+    #[error("exec address invalid")]
+    ExecInvalidAddr,
+
+    /// Load image has a segment outside of flash, SRAM and XIP SRAM.
+    #[error("load address {0:#010x} invalid")]
+    LoadInvalidAddr(u32),
+
+    /// Intel HEX record is malformed, on the given line.
+    #[error("hex line {0} format invalid")]
+    HexFormatInvalid(usize),
+    /// Intel HEX record checksum is wrong, on the given line.
+    #[error("hex line {0} checksum mismatch")]
+    HexChecksumMismatch(usize),
+    /// Intel HEX file has no end of file record.
+    #[error("hex end of file record missing")]
+    HexEndMissing,
+
+    /// Motorola S-record is malformed, on the given line.
+    #[error("srec line {0} format invalid")]
+    SrecFormatInvalid(usize),
+    /// Motorola S-record checksum is wrong, on the given line.
+    #[error("srec line {0} checksum mismatch")]
+    SrecChecksumMismatch(usize),
+    /// Motorola S-record file has no termination record.
+    #[error("srec termination record missing")]
+    SrecEndMissing,
+
+    /// ELF file is truncated or malformed.
+    #[error("elf format invalid")]
+    ElfFormatInvalid,
+    /// ELF file is not a 32-bit little-endian ARM or RISC-V executable.
+    #[error("elf file unsupported")]
+    ElfUnsupported,
+    /// Data read back from the device differs from the image.
+    #[error("verify mismatch at {0:#010x}")]
+    VerifyMismatch(u32),
+
+    /// Vectorize flash command address invalid.
+    #[error("vectorize flash address invalid")]
+    VectorizeInvalidAddr,
+
+    /// OTP command row invalid.
+    #[error("otp row invalid")]
+    OtpInvalidRow,
+    /// OTP command row count invalid.
+    #[error("otp row count invalid")]
+    OtpInvalidRowCount,
+    /// OTP raw row value wider than 24 bits.
+    #[error("otp value invalid")]
+    OtpInvalidValue,
+
+    /// Bootrom identity does not match a known chip.
+    #[error("chip identity invalid")]
+    ChipIdentityInvalid,
+
+    /// GET_INFO response is malformed.
+    #[error("info response invalid")]
+    InfoResponseInvalid,
+
+    /// UF2 data is empty or not a whole number of blocks.
+    #[error("uf2 size invalid")]
+    Uf2SizeInvalid,
+    /// UF2 block has invalid magic numbers.
+    #[error("uf2 block {0} magic invalid")]
+    Uf2MagicInvalid(usize),
+    /// UF2 block has unknown or unsupported flags.
+    #[error("uf2 block {0} flags unsupported")]
+    Uf2FlagsUnsupported(usize),
+    /// UF2 block payload is larger than its data area.
+    #[error("uf2 block {0} payload size invalid")]
+    Uf2PayloadSizeInvalid(usize),
+    /// UF2 block is numbered out of sequence.
+    #[error("uf2 block {0} number invalid")]
+    Uf2BlockNumberInvalid(usize),
+
+    /// Failed to serialize a transfer recording.
+    #[error("recording failed to serialize: {0}")]
+    RecordingSerializeFailure(bincode::Error),
+    /// Failed to deserialize a transfer recording.
+    #[error("recording failed to deserialize: {0}")]
+    RecordingDeserializeFailure(bincode::Error),
+    /// Data is not a transfer recording, or has an unsupported version.
+    #[error("recording format invalid")]
+    RecordingFormatInvalid,
+
+    /// Failed to read a USB capture.
+    #[error("failed to read capture: {0}")]
+    CaptureReadFailure(std::io::Error),
+    /// Data is not a pcap or pcapng capture.
+    #[error("capture format invalid")]
+    CaptureFormatInvalid,
+    /// Capture link type is not a Linux usbmon link type.
+    #[error("capture link type {0} unsupported")]
+    CaptureLinkTypeUnsupported(u32),
+
+    /// Command status token does not match the token of the command sent.
+    #[error("status token mismatch: expected {expected}, got {got}")]
+    StatusTokenMismatch { expected: u32, got: u32 },
+    /// Command status ID does not match the ID of the command sent.
+    #[error("status cmd id mismatch: expected {expected:#04x}, got {got:#04x}")]
+    StatusCmdIdMismatch { expected: u8, got: u8 },
+    /// Command status code is not a known [`PicobootStatus`].
+    #[error("status code unknown: {0}")]
+    StatusCodeUnknown(u32),
+
+    /// Device rejected the command as unknown.
+    #[error("device rejected cmd as unknown (addr {addr:#010x}, size {size:#x})")]
+    StatusUnknownCmd { addr: u32, size: u32 },
+    /// Device rejected the command length.
+    #[error("device rejected cmd length (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidCmdLength { addr: u32, size: u32 },
+    /// Device rejected the transfer length.
+    #[error("device rejected transfer length (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidTransferLength { addr: u32, size: u32 },
+    /// Device rejected the address.
+    #[error("device rejected address (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidAddress { addr: u32, size: u32 },
+    /// Device rejected the alignment of the address or size.
+    #[error("device rejected alignment (addr {addr:#010x}, size {size:#x})")]
+    StatusBadAlignment { addr: u32, size: u32 },
+    /// Device rejected a write interleaved with a write from another interface.
+    #[error("device rejected interleaved write (addr {addr:#010x}, size {size:#x})")]
+    StatusInterleavedWrite { addr: u32, size: u32 },
+    /// Device is rebooting.
+    #[error("device is rebooting (addr {addr:#010x}, size {size:#x})")]
+    StatusRebooting { addr: u32, size: u32 },
+    /// Device reported an unknown error.
+    #[error("device reported unknown error (addr {addr:#010x}, size {size:#x})")]
+    StatusUnknownError { addr: u32, size: u32 },
+    /// Device is in an invalid state for the command.
+    #[error("device in invalid state (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidState { addr: u32, size: u32 },
+    /// Device did not permit the command.
+    #[error("device did not permit cmd (addr {addr:#010x}, size {size:#x})")]
+    StatusNotPermitted { addr: u32, size: u32 },
+    /// Device rejected a command argument.
+    #[error("device rejected cmd argument (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidArg { addr: u32, size: u32 },
+    /// Device reported the buffer is too small.
+    #[error("device reported buffer too small (addr {addr:#010x}, size {size:#x})")]
+    StatusBufferTooSmall { addr: u32, size: u32 },
+    /// Device reported a precondition was not met.
+    #[error("device reported precondition not met (addr {addr:#010x}, size {size:#x})")]
+    StatusPreconditionNotMet { addr: u32, size: u32 },
+    /// Device reported the data was modified.
+    #[error("device reported modified data (addr {addr:#010x}, size {size:#x})")]
+    StatusModifiedData { addr: u32, size: u32 },
+    /// Device rejected the data.
+    #[error("device rejected data (addr {addr:#010x}, size {size:#x})")]
+    StatusInvalidData { addr: u32, size: u32 },
+    /// Device reported something was not found.
+    #[error("device reported not found (addr {addr:#010x}, size {size:#x})")]
+    StatusNotFound { addr: u32, size: u32 },
+    /// Device rejected an unsupported modification.
+    #[error("device rejected unsupported modification (addr {addr:#010x}, size {size:#x})")]
+    StatusUnsupportedModification { addr: u32, size: u32 },
 }
-impl PicobootRangeCmd {
-    pub fn ser(addr: u32, size: u32) -> [u8; 16] {
-        let c = PicobootRangeCmd {
-            addr,
-            size,
-            _unused: 0,
+impl PicobootError {
+    /// Creates the error for a non-`Ok` status reported by the device.
+    ///
+    /// `addr` and `size` describe the memory range of the rejected command.
+    /// Returns `None` for [`PicobootStatus::Ok`].
+    pub fn from_status(status: PicobootStatus, addr: u32, size: u32) -> Option<Self> {
+        let e = match status {
+            PicobootStatus::Ok => return None,
+            PicobootStatus::UnknownCmd => Self::StatusUnknownCmd { addr, size },
+            PicobootStatus::InvalidCmdLength => Self::StatusInvalidCmdLength { addr, size },
+            PicobootStatus::InvalidTransferLength => {
+                Self::StatusInvalidTransferLength { addr, size }
+            }
+            PicobootStatus::InvalidAddress => Self::StatusInvalidAddress { addr, size },
+            PicobootStatus::BadAlignment => Self::StatusBadAlignment { addr, size },
+            PicobootStatus::InterleavedWrite => Self::StatusInterleavedWrite { addr, size },
+            PicobootStatus::Rebooting => Self::StatusRebooting { addr, size },
+            PicobootStatus::UnknownError => Self::StatusUnknownError { addr, size },
+            PicobootStatus::InvalidState => Self::StatusInvalidState { addr, size },
+            PicobootStatus::NotPermitted => Self::StatusNotPermitted { addr, size },
+            PicobootStatus::InvalidArg => Self::StatusInvalidArg { addr, size },
+            PicobootStatus::BufferTooSmall => Self::StatusBufferTooSmall { addr, size },
+            PicobootStatus::PreconditionNotMet => Self::StatusPreconditionNotMet { addr, size },
+            PicobootStatus::ModifiedData => Self::StatusModifiedData { addr, size },
+            PicobootStatus::InvalidData => Self::StatusInvalidData { addr, size },
+            PicobootStatus::NotFound => Self::StatusNotFound { addr, size },
+            PicobootStatus::UnsupportedModification => {
+                Self::StatusUnsupportedModification { addr, size }
+            }
         };
-        bincode::serialize(&c)
-            .unwrap()
-            .try_into()
-            .unwrap_or_else(|v: Vec<u8>| {
-                panic!("Expected a Vec of length {} but it was {}", 16, v.len())
-            })
+        Some(e)
     }
-}
 
-#[derive(Serialize, Debug, Clone)]
-#[repr(C, packed)]
-struct PicobootRebootCmd {
-    pc: u32,
-    sp: u32,
-    delay: u32,
-    _unused: u32,
-}
-impl PicobootRebootCmd {
-    pub fn ser(pc: u32, sp: u32, delay: u32) -> [u8; 16] {
-        let c = PicobootRebootCmd {
-            pc,
-            sp,
-            delay,
-            _unused: 0,
+    /// Returns the device status this error was created from, if any.
+    pub fn status(&self) -> Option<PicobootStatus> {
+        let status = match self {
+            Self::StatusUnknownCmd { .. } => PicobootStatus::UnknownCmd,
+            Self::StatusInvalidCmdLength { .. } => PicobootStatus::InvalidCmdLength,
+            Self::StatusInvalidTransferLength { .. } => PicobootStatus::InvalidTransferLength,
+            Self::StatusInvalidAddress { .. } => PicobootStatus::InvalidAddress,
+            Self::StatusBadAlignment { .. } => PicobootStatus::BadAlignment,
+            Self::StatusInterleavedWrite { .. } => PicobootStatus::InterleavedWrite,
+            Self::StatusRebooting { .. } => PicobootStatus::Rebooting,
+            Self::StatusUnknownError { .. } => PicobootStatus::UnknownError,
+            Self::StatusInvalidState { .. } => PicobootStatus::InvalidState,
+            Self::StatusNotPermitted { .. } => PicobootStatus::NotPermitted,
+            Self::StatusInvalidArg { .. } => PicobootStatus::InvalidArg,
+            Self::StatusBufferTooSmall { .. } => PicobootStatus::BufferTooSmall,
+            Self::StatusPreconditionNotMet { .. } => PicobootStatus::PreconditionNotMet,
+            Self::StatusModifiedData { .. } => PicobootStatus::ModifiedData,
+            Self::StatusInvalidData { .. } => PicobootStatus::InvalidData,
+            Self::StatusNotFound { .. } => PicobootStatus::NotFound,
+            Self::StatusUnsupportedModification { .. } => PicobootStatus::UnsupportedModification,
+            _ => return None,
         };
-        bincode::serialize(&c)
-            .unwrap()
-            .try_into()
-            .unwrap_or_else(|v: Vec<u8>| {
-                panic!("Expected a Vec of length {} but it was {}", 16, v.len())
-            })
+        Some(status)
     }
 }
-
-#[derive(Serialize, Debug, Clone)]
-#[repr(C, packed)]
-struct PicobootReboot2Cmd {
-    flags: u32,
-    delay: u32,
-    p0: u32,
-    p1: u32,
-}
-impl PicobootReboot2Cmd {
-    pub fn ser(flags: u32, delay: u32, p0: u32, p1: u32) -> [u8; 16] {
-        let c = PicobootReboot2Cmd {
-            flags,
-            delay,
-            p0,
-            p1,
-        };
-        bincode::serialize(&c)
-            .unwrap()
-            .try_into()
-            .unwrap_or_else(|v: Vec<u8>| {
-                panic!("Expected a Vec of length {} but it was {}", 16, v.len())
-            })
-    }
-}
-
-#[derive(Deserialize, Debug, Clone)]
-#[repr(C, packed)]
-pub struct PicobootStatusCmd {
-    token: u32,
-    status_code: u32,
-    cmd_id: u8,
-    in_progress: u8,
-    _unused: [u8; 6],
-}
-impl PicobootStatusCmd {
-    pub fn get_token(&self) -> u32 {
-        self.token
-    }
-
-    pub fn get_status_code(&self) -> PicobootStatus {
-        self.status_code.try_into().unwrap()
-    }
-
-    pub fn get_cmd_id(&self) -> u8 {
-        self.cmd_id
-    }
-
-    pub fn get_in_progress(&self) -> u8 {
-        self.in_progress
-    }
-}
-
-/// Command structure for PICOBOOT interface.
-///
-/// This structure contains shorthands for creating commands but does not do any
-/// sort of runtime checks to ensure safe use of these commands.
-#[derive(Serialize, Debug, Clone)]
-#[repr(C, packed)]
-pub struct PicobootCmd {
-    /// Magic number ([`PICOBOOT_MAGIC`]) to identify the command for the PICOBOOT interface.
-    magic: u32,
-    /// Token number to uniquely identify commands and their responses.
-    token: u32,
-    /// Command ID ([`PicobootCmdId`]) to tell what command the data is to be used for. The top bit (0x80) indicates data transfer direction.
-    cmd_id: u8,
-    /// Command size, number of bytes to read from the `args` field.
-    cmd_size: u8,
-    /// Reserved space
-    _unused: u16,
-    /// Transfer length, the number of bytes expected to send or recieve over the bulk endpoint(s).
-    transfer_len: u32,
-    /// Command specific args, padded with zeros.
-    args: [u8; 16],
-}
-impl PicobootCmd {
-    /// Creates a new PicobootCmd
-    pub fn new(cmd_id: PicobootCmdId, cmd_size: u8, transfer_len: u32, args: [u8; 16]) -> Self {
-        PicobootCmd {
-            magic: PICOBOOT_MAGIC,
-            token: 0,
-            cmd_id: cmd_id as u8,
-            cmd_size,
-            _unused: 0,
-            transfer_len,
-            args,
+impl From<ProtocolError> for PicobootError {
+    fn from(e: ProtocolError) -> Self {
+        match e {
+            ProtocolError::PacketLengthInvalid { expected, got } => {
+                Self::CmdPacketLengthInvalid { expected, got }
+            }
+            ProtocolError::MagicInvalid(magic) => Self::CmdMagicInvalid(magic),
+            ProtocolError::UnknownCmdId(id) => Self::CmdUnknownId(id),
+            ProtocolError::UnknownStatusCode(code) => Self::StatusCodeUnknown(code),
         }
     }
-
-    pub fn set_token(mut self, token: u32) -> Self {
-        self.token = token;
-        self
-    }
-
-    pub fn get_transfer_len(&self) -> u32 {
-        self.transfer_len
-    }
-
-    pub fn get_cmd_id(&self) -> PicobootCmdId {
-        self.cmd_id.try_into().unwrap()
-    }
-
-    /// Creates an EXCLUSIVE_ACCESS command
-    pub fn exclusive_access(exclusive: u8) -> Self {
-        let mut args = [0; 16];
-        args[0] = exclusive;
-        PicobootCmd::new(PicobootCmdId::ExclusiveAccess, 1, 0, args)
-    }
-
-    /// Creates a REBOOT command
-    pub fn reboot(pc: u32, sp: u32, delay: u32) -> Self {
-        let args = PicobootRebootCmd::ser(pc, sp, delay);
-        PicobootCmd::new(PicobootCmdId::Reboot, 12, 0, args)
-    }
-
-    /// Creates a REBOOT2 command (normal boot)
-    pub fn reboot2_normal(delay: u32) -> Self {
-        let flags = 0x0; // Normal boot
-        let args = PicobootReboot2Cmd::ser(flags, delay, 0, 0);
-        PicobootCmd::new(PicobootCmdId::Reboot2, 0x10, 0, args)
-    }
-
-    /// Creates a FLASH_ERASE command
-    pub fn flash_erase(addr: u32, size: u32) -> Self {
-        let args = PicobootRangeCmd::ser(addr, size);
-        PicobootCmd::new(PicobootCmdId::FlashErase, 8, 0, args)
-    }
-
-    /// Creates a WRITE command
-    pub fn flash_write(addr: u32, size: u32) -> Self {
-        let args = PicobootRangeCmd::ser(addr, size);
-        PicobootCmd::new(PicobootCmdId::Write, 8, size, args)
-    }
-
-    /// Creates a READ command
-    pub fn flash_read(addr: u32, size: u32) -> Self {
-        let args = PicobootRangeCmd::ser(addr, size);
-        PicobootCmd::new(PicobootCmdId::Read, 8, size, args)
-    }
-
-    /// Creates an ENTER_XIP command
-    pub fn enter_xip() -> Self {
-        PicobootCmd::new(PicobootCmdId::EnterCmdXip, 0, 0, [0; 16])
-    }
-
-    /// Creates an EXIT_XIP command
-    pub fn exit_xip() -> Self {
-        PicobootCmd::new(PicobootCmdId::ExitXip, 0, 0, [0; 16])
-    }
 }
@@ -0,0 +1,761 @@
+use crate::protocol::{
+    PicobootCmd, PicobootCmdId, PicobootGetInfoCmd, PicobootOtpCmd, PicobootRangeCmd,
+    PicobootReboot2Cmd, PicobootRebootCmd, PicobootStatus, PicobootStatusCmd, GET_INFO_MAX_SIZE,
+    OTP_ECC_ROW_SIZE, OTP_RAW_ROW_SIZE, OTP_ROW_COUNT,
+};
+
+type Result<T> = ::core::result::Result<T, PicobootStatus>;
+
+/// Application callbacks driven by a [`PicobootResponder`]
+///
+/// Each callback either accepts a command or rejects it with the status
+/// reported to the host, which also stalls both bulk endpoints. Commands
+/// with a default implementation that returns [`PicobootStatus::UnknownCmd`]
+/// are rejected unless the application implements them. Address ranges that
+/// wrap past the end of the address space are rejected with
+/// [`PicobootStatus::InvalidAddress`] before any callback is made.
+pub trait PicobootHandler {
+    /// Handles an EXCLUSIVE_ACCESS command, `mode` is 0, 1 or 2.
+    fn exclusive_access(&mut self, _mode: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handles a REBOOT command.
+    ///
+    /// The reboot should happen after the command is acknowledged, see
+    /// [`Self::cmd_complete`].
+    fn reboot(&mut self, _pc: u32, _sp: u32, _delay: u32) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Handles a REBOOT2 command.
+    ///
+    /// The reboot should happen after the command is acknowledged, see
+    /// [`Self::cmd_complete`].
+    fn reboot2(&mut self, _flags: u32, _delay: u32, _p0: u32, _p1: u32) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Handles a FLASH_ERASE command.
+    fn flash_erase(&mut self, addr: u32, size: u32) -> Result<()>;
+
+    /// Checks a READ command before any data is sent to the host.
+    fn begin_read(&mut self, _addr: u32, _size: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Fills `buf` with memory starting at `addr`.
+    ///
+    /// Called for every packet of a READ command, with consecutive addresses.
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<()>;
+
+    /// Checks a WRITE command before any data is received from the host.
+    fn begin_write(&mut self, _addr: u32, _size: u32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes `data` to memory starting at `addr`.
+    ///
+    /// Called for every packet of a WRITE command, with consecutive
+    /// addresses.
+    fn write(&mut self, addr: u32, data: &[u8]) -> Result<()>;
+
+    /// Handles an EXIT_XIP command.
+    fn exit_xip(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handles an ENTER_CMD_XIP command.
+    fn enter_xip(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handles an EXEC command.
+    fn exec(&mut self, _addr: u32) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Handles a VECTORIZE_FLASH command.
+    fn vectorize_flash(&mut self, _addr: u32) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Handles a GET_INFO command by filling `buf` with the response.
+    ///
+    /// `buf` is zeroed and as long as the transfer length requested by the
+    /// host, at most [`GET_INFO_MAX_SIZE`] bytes.
+    fn get_info(&mut self, _info_type: u8, _params: [u32; 3], _buf: &mut [u8]) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Checks an OTP_READ command before any data is sent to the host.
+    fn begin_otp_read(&mut self, _row: u16, _row_count: u16, _ecc: bool) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Fills `buf` with OTP rows starting at `row`, 2 bytes per row in ECC
+    /// mode and 4 bytes per row in raw mode.
+    ///
+    /// Called for every packet of an OTP_READ command, with consecutive rows.
+    /// A packet that ends with a partial row fails the command with
+    /// [`PicobootStatus::InvalidTransferLength`] instead.
+    fn otp_read(&mut self, _row: u16, _ecc: bool, _buf: &mut [u8]) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Checks an OTP_WRITE command before any data is received from the
+    /// host.
+    fn begin_otp_write(&mut self, _row: u16, _row_count: u16, _ecc: bool) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Writes `data` to OTP rows starting at `row`, 2 bytes per row in ECC
+    /// mode and 4 bytes per row in raw mode.
+    ///
+    /// Called for every packet of an OTP_WRITE command, with consecutive
+    /// rows. A packet that ends with a partial row fails the command with
+    /// [`PicobootStatus::InvalidTransferLength`] instead.
+    fn otp_write(&mut self, _row: u16, _ecc: bool, _data: &[u8]) -> Result<()> {
+        Err(PicobootStatus::UnknownCmd)
+    }
+
+    /// Called after the host acknowledged a command.
+    fn cmd_complete(&mut self, _cmd: &PicobootCmd) {}
+
+    /// Called when the host resets the PICOBOOT interface.
+    fn interface_reset(&mut self) {}
+}
+
+/// Result of a USB transfer a [`PicobootResponder`] could not complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponderError {
+    /// The endpoint is halted, or the control request is not supported.
+    Stall,
+    /// The device has nothing to send, or does not expect any data.
+    Nak,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Waiting for a command.
+    Idle,
+    /// Sending data to the host.
+    DataIn { addr: u32, remaining: u32 },
+    /// Sending OTP rows to the host.
+    OtpIn { row: u16, ecc: bool, remaining: u32 },
+    /// Receiving OTP rows from the host.
+    OtpOut { row: u16, ecc: bool, remaining: u32 },
+    /// Sending the GET_INFO response to the host.
+    InfoIn { offset: usize, len: usize },
+    /// Receiving data from the host.
+    DataOut { addr: u32, remaining: u32 },
+    /// Waiting for the host to send the acknowledgement of an IN command.
+    AckIn,
+    /// Waiting for the host to read the acknowledgement of an OUT command.
+    AckOut,
+}
+
+/// Device side of the PICOBOOT interface
+///
+/// The responder decodes commands received on the bulk OUT endpoint, drives
+/// the data and acknowledgement phases and calls a [`PicobootHandler`] for
+/// the actual work. The USB stack of the application forwards bulk transfers
+/// of the PICOBOOT interface to [`Self::bulk_out`] and [`Self::bulk_in`], and
+/// class-specific control requests to [`Self::control_in`] and
+/// [`Self::control_out`]. The responder does not allocate and is available
+/// without the `std` feature.
+#[derive(Debug)]
+pub struct PicobootResponder<H: PicobootHandler> {
+    handler: H,
+    state: State,
+    cmd: Option<PicobootCmd>,
+    token: u32,
+    cmd_id: u8,
+    status: PicobootStatus,
+    halted_in: bool,
+    halted_out: bool,
+    info: [u8; GET_INFO_MAX_SIZE as usize],
+}
+impl<H: PicobootHandler> PicobootResponder<H> {
+    /// Creates a new responder waiting for a command.
+    pub fn new(handler: H) -> Self {
+        PicobootResponder {
+            handler,
+            state: State::Idle,
+            cmd: None,
+            token: 0,
+            cmd_id: 0,
+            status: PicobootStatus::Ok,
+            halted_in: false,
+            halted_out: false,
+            info: [0; GET_INFO_MAX_SIZE as usize],
+        }
+    }
+
+    /// Returns a reference to the application handler.
+    pub fn get_handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// Returns a mutable reference to the application handler.
+    pub fn get_handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Consumes the responder, returning the application handler.
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+
+    /// Returns the command status that GET_COMMAND_STATUS reports.
+    pub fn get_status(&self) -> PicobootStatusCmd {
+        let in_progress = !matches!(self.state, State::Idle);
+        PicobootStatusCmd::new(self.token, self.status, self.cmd_id, in_progress)
+    }
+
+    /// Returns `true` if the bulk IN endpoint is halted after a failed
+    /// command.
+    pub fn is_halted_in(&self) -> bool {
+        self.halted_in
+    }
+
+    /// Returns `true` if the bulk OUT endpoint is halted after a failed
+    /// command.
+    pub fn is_halted_out(&self) -> bool {
+        self.halted_out
+    }
+
+    /// Clears the halt of the bulk IN endpoint, on a CLEAR_FEATURE request
+    /// from the host.
+    pub fn clear_halt_in(&mut self) {
+        self.halted_in = false;
+    }
+
+    /// Clears the halt of the bulk OUT endpoint, on a CLEAR_FEATURE request
+    /// from the host.
+    pub fn clear_halt_out(&mut self) {
+        self.halted_out = false;
+    }
+
+    /// Handles a packet received on the bulk OUT endpoint, returning the
+    /// number of bytes accepted.
+    ///
+    /// # Errors:
+    /// - [`ResponderError::Stall`]
+    /// - [`ResponderError::Nak`]
+    pub fn bulk_out(&mut self, buf: &[u8]) -> ::core::result::Result<usize, ResponderError> {
+        if self.halted_out {
+            return Err(ResponderError::Stall);
+        }
+
+        match core::mem::replace(&mut self.state, State::Idle) {
+            State::Idle => {
+                let cmd = match PicobootCmd::from_bytes(buf) {
+                    Ok(c) => c,
+                    Err(_) => {
+                        self.fail(PicobootStatus::InvalidCmdLength);
+                        return Ok(buf.len());
+                    }
+                };
+
+                self.token = cmd.get_token();
+                self.cmd_id = cmd.get_raw_cmd_id();
+                self.status = PicobootStatus::Ok;
+                match self.start_cmd(&cmd) {
+                    Ok(state) => {
+                        self.state = state;
+                        self.cmd = Some(cmd);
+                    }
+                    Err(status) => self.fail(status),
+                }
+                Ok(buf.len())
+            }
+            State::DataOut { addr, remaining } => {
+                let len = buf.len().min(remaining as usize);
+                if let Err(status) = self.handler.write(addr, &buf[..len]) {
+                    self.fail(status);
+                    return Err(ResponderError::Stall);
+                }
+
+                let remaining = remaining - len as u32;
+                self.state = match remaining {
+                    0 => State::AckOut,
+                    _ => State::DataOut {
+                        addr: addr.wrapping_add(len as u32),
+                        remaining,
+                    },
+                };
+                Ok(len)
+            }
+            State::OtpOut {
+                row,
+                ecc,
+                remaining,
+            } => {
+                let len = buf.len().min(remaining as usize);
+                let rows = match otp_rows(len, ecc) {
+                    Some(rows) => rows,
+                    None => {
+                        self.fail(PicobootStatus::InvalidTransferLength);
+                        return Err(ResponderError::Stall);
+                    }
+                };
+                if let Err(status) = self.handler.otp_write(row, ecc, &buf[..len]) {
+                    self.fail(status);
+                    return Err(ResponderError::Stall);
+                }
+
+                let remaining = remaining - len as u32;
+                self.state = match remaining {
+                    0 => State::AckOut,
+                    _ => State::OtpOut {
+                        row: row.wrapping_add(rows),
+                        ecc,
+                        remaining,
+                    },
+                };
+                Ok(len)
+            }
+            State::AckIn => {
+                self.finish();
+                Ok(buf.len())
+            }
+            state => {
+                self.state = state;
+                Err(ResponderError::Nak)
+            }
+        }
+    }
+
+    /// Fills a packet to send on the bulk IN endpoint, returning its length.
+    ///
+    /// # Errors:
+    /// - [`ResponderError::Stall`]
+    /// - [`ResponderError::Nak`]
+    pub fn bulk_in(&mut self, buf: &mut [u8]) -> ::core::result::Result<usize, ResponderError> {
+        if self.halted_in {
+            return Err(ResponderError::Stall);
+        }
+
+        match core::mem::replace(&mut self.state, State::Idle) {
+            State::DataIn { addr, remaining } => {
+                let len = buf.len().min(remaining as usize);
+                if let Err(status) = self.handler.read(addr, &mut buf[..len]) {
+                    self.fail(status);
+                    return Err(ResponderError::Stall);
+                }
+
+                let remaining = remaining - len as u32;
+                self.state = match remaining {
+                    0 => State::AckIn,
+                    _ => State::DataIn {
+                        addr: addr.wrapping_add(len as u32),
+                        remaining,
+                    },
+                };
+                Ok(len)
+            }
+            State::OtpIn {
+                row,
+                ecc,
+                remaining,
+            } => {
+                let len = buf.len().min(remaining as usize);
+                let rows = match otp_rows(len, ecc) {
+                    Some(rows) => rows,
+                    None => {
+                        self.fail(PicobootStatus::InvalidTransferLength);
+                        return Err(ResponderError::Stall);
+                    }
+                };
+                if let Err(status) = self.handler.otp_read(row, ecc, &mut buf[..len]) {
+                    self.fail(status);
+                    return Err(ResponderError::Stall);
+                }
+
+                let remaining = remaining - len as u32;
+                self.state = match remaining {
+                    0 => State::AckIn,
+                    _ => State::OtpIn {
+                        row: row.wrapping_add(rows),
+                        ecc,
+                        remaining,
+                    },
+                };
+                Ok(len)
+            }
+            State::InfoIn { offset, len } => {
+                let n = buf.len().min(len - offset);
+                buf[..n].copy_from_slice(&self.info[offset..offset + n]);
+
+                self.state = match offset + n {
+                    end if end == len => State::AckIn,
+                    end => State::InfoIn { offset: end, len },
+                };
+                Ok(n)
+            }
+            State::AckOut => {
+                self.finish();
+                Ok(0)
+            }
+            state => {
+                self.state = state;
+                Err(ResponderError::Nak)
+            }
+        }
+    }
+
+    /// Handles a device-to-host control request to the PICOBOOT interface,
+    /// returning the number of bytes written to `buf`.
+    ///
+    /// Answers the GET_COMMAND_STATUS request (0xC1, 0x42).
+    ///
+    /// # Errors:
+    /// - [`ResponderError::Stall`]
+    pub fn control_in(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        buf: &mut [u8],
+    ) -> ::core::result::Result<usize, ResponderError> {
+        if (request_type, request) != (0xC1, 0x42) {
+            return Err(ResponderError::Stall);
+        }
+
+        let status = self.get_status().to_bytes();
+        let len = buf.len().min(status.len());
+        buf[..len].copy_from_slice(&status[..len]);
+        Ok(len)
+    }
+
+    /// Handles a host-to-device control request to the PICOBOOT interface,
+    /// returning the number of bytes accepted.
+    ///
+    /// Answers the INTERFACE_RESET request (0x41, 0x41), which aborts the
+    /// current command and clears the halt of both bulk endpoints.
+    ///
+    /// # Errors:
+    /// - [`ResponderError::Stall`]
+    pub fn control_out(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        _value: u16,
+        _index: u16,
+        _data: &[u8],
+    ) -> ::core::result::Result<usize, ResponderError> {
+        if (request_type, request) != (0x41, 0x41) {
+            return Err(ResponderError::Stall);
+        }
+
+        self.state = State::Idle;
+        self.cmd = None;
+        self.status = PicobootStatus::Ok;
+        self.halted_in = false;
+        self.halted_out = false;
+        self.handler.interface_reset();
+        Ok(0)
+    }
+
+    fn fail(&mut self, status: PicobootStatus) {
+        self.state = State::Idle;
+        self.cmd = None;
+        self.status = status;
+        self.halted_in = true;
+        self.halted_out = true;
+    }
+
+    fn finish(&mut self) {
+        self.state = State::Idle;
+        if let Some(cmd) = self.cmd.take() {
+            self.handler.cmd_complete(&cmd);
+        }
+    }
+
+    /// Starts processing of a command, returning the next protocol state.
+    fn start_cmd(&mut self, cmd: &PicobootCmd) -> Result<State> {
+        let id = cmd.get_cmd_id().map_err(|_| PicobootStatus::UnknownCmd)?;
+
+        let expected_size = match id {
+            PicobootCmdId::ExclusiveAccess => 1,
+            PicobootCmdId::Reboot => 12,
+            PicobootCmdId::FlashErase | PicobootCmdId::Read | PicobootCmdId::Write => 8,
+            PicobootCmdId::ExitXip | PicobootCmdId::EnterCmdXip => 0,
+            PicobootCmdId::Exec | PicobootCmdId::VectorizeFlash => 4,
+            PicobootCmdId::OtpRead | PicobootCmdId::OtpWrite => 5,
+            PicobootCmdId::Reboot2 | PicobootCmdId::GetInfo => 16,
+            _ => return Err(PicobootStatus::UnknownCmd),
+        };
+        if cmd.get_cmd_size() != expected_size {
+            return Err(PicobootStatus::InvalidCmdLength);
+        }
+
+        let args = cmd.get_args();
+        let range = PicobootRangeCmd::from_args(&args);
+        let (addr, size) = (range.get_addr(), range.get_size());
+        let otp = PicobootOtpCmd::from_args(&args);
+        let otp_size = otp.get_row_size() * u32::from(otp.get_row_count());
+        let transfer_len = cmd.get_transfer_len();
+        let transfer_len_valid = match id {
+            PicobootCmdId::OtpRead | PicobootCmdId::OtpWrite => transfer_len == otp_size,
+            PicobootCmdId::Read | PicobootCmdId::Write => transfer_len == size,
+            PicobootCmdId::GetInfo => transfer_len != 0 && transfer_len <= GET_INFO_MAX_SIZE,
+            _ => transfer_len == 0,
+        };
+        if !transfer_len_valid {
+            return Err(PicobootStatus::InvalidTransferLength);
+        }
+        let is_range = matches!(
+            id,
+            PicobootCmdId::FlashErase | PicobootCmdId::Read | PicobootCmdId::Write
+        );
+        if is_range && addr.checked_add(size).is_none() {
+            return Err(PicobootStatus::InvalidAddress);
+        }
+
+        match id {
+            PicobootCmdId::ExclusiveAccess => {
+                if args[0] > 2 {
+                    return Err(PicobootStatus::InvalidArg);
+                }
+                self.handler.exclusive_access(args[0])?;
+            }
+            PicobootCmdId::Reboot => {
+                let c = PicobootRebootCmd::from_args(&args);
+                self.handler.reboot(c.get_pc(), c.get_sp(), c.get_delay())?;
+            }
+            PicobootCmdId::Reboot2 => {
+                let c = PicobootReboot2Cmd::from_args(&args);
+                self.handler
+                    .reboot2(c.get_flags(), c.get_delay(), c.get_p0(), c.get_p1())?;
+            }
+            PicobootCmdId::FlashErase => self.handler.flash_erase(addr, size)?,
+            PicobootCmdId::Read => {
+                self.handler.begin_read(addr, size)?;
+                if size != 0 {
+                    return Ok(State::DataIn {
+                        addr,
+                        remaining: size,
+                    });
+                }
+            }
+            PicobootCmdId::Write => {
+                self.handler.begin_write(addr, size)?;
+                if size != 0 {
+                    return Ok(State::DataOut {
+                        addr,
+                        remaining: size,
+                    });
+                }
+            }
+            PicobootCmdId::ExitXip => self.handler.exit_xip()?,
+            PicobootCmdId::EnterCmdXip => self.handler.enter_xip()?,
+            PicobootCmdId::Exec => self.handler.exec(addr)?,
+            PicobootCmdId::VectorizeFlash => self.handler.vectorize_flash(addr)?,
+            PicobootCmdId::OtpRead | PicobootCmdId::OtpWrite => {
+                let (row, row_count, ecc) = (otp.get_row(), otp.get_row_count(), otp.is_ecc());
+                if u32::from(row) + u32::from(row_count) > u32::from(OTP_ROW_COUNT) {
+                    return Err(PicobootStatus::InvalidAddress);
+                }
+
+                if let PicobootCmdId::OtpRead = id {
+                    self.handler.begin_otp_read(row, row_count, ecc)?;
+                    if otp_size != 0 {
+                        return Ok(State::OtpIn {
+                            row,
+                            ecc,
+                            remaining: otp_size,
+                        });
+                    }
+                } else {
+                    self.handler.begin_otp_write(row, row_count, ecc)?;
+                    if otp_size != 0 {
+                        return Ok(State::OtpOut {
+                            row,
+                            ecc,
+                            remaining: otp_size,
+                        });
+                    }
+                }
+            }
+            PicobootCmdId::GetInfo => {
+                let c = PicobootGetInfoCmd::from_args(&args);
+                let len = transfer_len as usize;
+                self.info = [0; GET_INFO_MAX_SIZE as usize];
+                self.handler
+                    .get_info(c.get_info_type(), c.get_params(), &mut self.info[..len])?;
+                return Ok(State::InfoIn { offset: 0, len });
+            }
+            _ => return Err(PicobootStatus::UnknownCmd),
+        }
+
+        match cmd.is_in() {
+            true => Ok(State::AckIn),
+            false => Ok(State::AckOut),
+        }
+    }
+}
+
+/// Returns the number of OTP rows in a packet of `len` bytes, or `None` if
+/// the packet ends with a partial row.
+fn otp_rows(len: usize, ecc: bool) -> Option<u16> {
+    let row_size = match ecc {
+        true => OTP_ECC_ROW_SIZE,
+        false => OTP_RAW_ROW_SIZE,
+    };
+    match len as u32 % row_size {
+        0 => Some((len as u32 / row_size) as u16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAM_START: u32 = 0x2000_0000;
+
+    /// Handler with 1 KiB of RAM and 16 raw OTP rows.
+    struct TestHandler {
+        ram: [u8; 1024],
+        otp: [u8; 64],
+        completed: usize,
+    }
+    impl TestHandler {
+        fn range(addr: u32, len: usize) -> Result<core::ops::Range<usize>> {
+            let start = addr.wrapping_sub(RAM_START) as usize;
+            match start.checked_add(len) {
+                Some(end) if addr >= RAM_START && end <= 1024 => Ok(start..end),
+                _ => Err(PicobootStatus::InvalidAddress),
+            }
+        }
+    }
+    impl PicobootHandler for TestHandler {
+        fn flash_erase(&mut self, _addr: u32, _size: u32) -> Result<()> {
+            Err(PicobootStatus::InvalidAddress)
+        }
+
+        fn read(&mut self, addr: u32, buf: &mut [u8]) -> Result<()> {
+            buf.copy_from_slice(&self.ram[Self::range(addr, buf.len())?]);
+            Ok(())
+        }
+
+        fn write(&mut self, addr: u32, data: &[u8]) -> Result<()> {
+            self.ram[Self::range(addr, data.len())?].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn begin_otp_write(&mut self, _row: u16, _row_count: u16, _ecc: bool) -> Result<()> {
+            Ok(())
+        }
+
+        fn otp_write(&mut self, row: u16, _ecc: bool, data: &[u8]) -> Result<()> {
+            let start = usize::from(row) * 4;
+            self.otp[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn cmd_complete(&mut self, _cmd: &PicobootCmd) {
+            self.completed += 1;
+        }
+    }
+
+    fn responder() -> PicobootResponder<TestHandler> {
+        PicobootResponder::new(TestHandler {
+            ram: [0; 1024],
+            otp: [0; 64],
+            completed: 0,
+        })
+    }
+
+    fn send(responder: &mut PicobootResponder<TestHandler>, cmd: PicobootCmd) {
+        let packet = cmd.set_token(7).to_bytes();
+        assert_eq!(responder.bulk_out(&packet), Ok(packet.len()));
+    }
+
+    fn status(responder: &PicobootResponder<TestHandler>) -> PicobootStatus {
+        responder.get_status().get_status_code().unwrap()
+    }
+
+    #[test]
+    fn write_then_read() {
+        let mut r = responder();
+        send(&mut r, PicobootCmd::flash_write(RAM_START, 96));
+        assert_eq!(r.bulk_out(&[0xAB; 64]), Ok(64));
+        assert_eq!(r.bulk_out(&[0xCD; 64]), Ok(32));
+
+        // OUT commands are acknowledged with a zero length IN packet
+        assert_eq!(r.bulk_out(&[0]), Err(ResponderError::Nak));
+        assert_eq!(r.bulk_in(&mut [0; 64]), Ok(0));
+        assert_eq!(r.get_handler().completed, 1);
+
+        send(&mut r, PicobootCmd::flash_read(RAM_START + 32, 64));
+        let mut buf = [0; 64];
+        assert_eq!(r.bulk_in(&mut buf), Ok(64));
+        assert_eq!(buf[..32], [0xAB; 32]);
+        assert_eq!(buf[32..], [0xCD; 32]);
+
+        // IN commands are acknowledged with an OUT packet
+        assert_eq!(r.bulk_in(&mut buf), Err(ResponderError::Nak));
+        assert_eq!(r.get_handler().completed, 1);
+        assert_eq!(r.bulk_out(&[0]), Ok(1));
+        assert_eq!(r.get_handler().completed, 2);
+        assert_eq!(status(&r), PicobootStatus::Ok);
+    }
+
+    #[test]
+    fn rejects_wrapping_range() {
+        for cmd in [
+            PicobootCmd::flash_read(0xFFFF_FF00, 0x200),
+            PicobootCmd::flash_write(0xFFFF_FF00, 0x200),
+            PicobootCmd::flash_erase(0xFFFF_F000, 0x2000),
+        ] {
+            let mut r = responder();
+            send(&mut r, cmd);
+            assert_eq!(status(&r), PicobootStatus::InvalidAddress);
+            assert!(r.is_halted_in() && r.is_halted_out());
+            assert_eq!(r.bulk_in(&mut [0; 64]), Err(ResponderError::Stall));
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_data() {
+        let mut r = responder();
+        send(&mut r, PicobootCmd::flash_read(RAM_START + 1000, 64));
+        assert_eq!(r.bulk_in(&mut [0; 64]), Err(ResponderError::Stall));
+        assert_eq!(status(&r), PicobootStatus::InvalidAddress);
+    }
+
+    #[test]
+    fn rejects_transfer_len_mismatch() {
+        let args = PicobootRangeCmd::ser(RAM_START, 256);
+        let mut r = responder();
+        send(&mut r, PicobootCmd::new(PicobootCmdId::Write, 8, 128, args));
+        assert_eq!(status(&r), PicobootStatus::InvalidTransferLength);
+        assert_eq!(r.bulk_out(&[0; 64]), Err(ResponderError::Stall));
+    }
+
+    #[test]
+    fn rejects_partial_otp_row() {
+        let mut r = responder();
+        send(&mut r, PicobootCmd::otp_write(0, 4, false));
+        assert_eq!(r.bulk_out(&[0x11; 8]), Ok(8));
+        assert_eq!(r.bulk_out(&[0x22; 6]), Err(ResponderError::Stall));
+        assert_eq!(status(&r), PicobootStatus::InvalidTransferLength);
+        assert_eq!(
+            r.get_handler().otp[..10],
+            [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0, 0]
+        );
+    }
+
+    #[test]
+    fn interface_reset_clears_halt() {
+        let mut r = responder();
+        send(&mut r, PicobootCmd::flash_read(0xFFFF_FF00, 0x200));
+        assert_eq!(r.control_out(0x41, 0x41, 0, 0, &[]), Ok(0));
+        assert!(!r.is_halted_in() && !r.is_halted_out());
+        assert_eq!(status(&r), PicobootStatus::Ok);
+    }
+}
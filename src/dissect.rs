@@ -0,0 +1,850 @@
+use crate::cmd::{PicobootCmd, PicobootError, PicobootStatusCmd};
+
+use std::{collections::HashMap, fmt, io::Read, time::Duration};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// pcap link type of Linux usbmon captures with a 48 byte header.
+const LINKTYPE_USB_LINUX: u32 = 189;
+/// pcap link type of Linux usbmon captures with a 64 byte header.
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+/// usbmon transfer type of control transfers.
+const XFER_CONTROL: u8 = 2;
+/// usbmon transfer type of bulk transfers.
+const XFER_BULK: u8 = 3;
+/// URB status of a stalled endpoint (`-EPIPE`).
+const URB_STALL: i32 = -32;
+
+/// A completed USB transfer read from a usbmon capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbTransfer {
+    timestamp: Duration,
+    bus: u16,
+    device: u8,
+    endpoint: u8,
+    transfer_type: u8,
+    setup: Option<[u8; 8]>,
+    data: Vec<u8>,
+    actual_len: u32,
+    status: i32,
+}
+impl UsbTransfer {
+    /// Returns the time the transfer completed, as reported by usbmon.
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Returns the number of the USB bus of the device.
+    pub fn get_bus(&self) -> u16 {
+        self.bus
+    }
+
+    /// Returns the address of the device on its USB bus.
+    pub fn get_device(&self) -> u8 {
+        self.device
+    }
+
+    /// Returns the endpoint address, with the top bit (0x80) set for IN
+    /// endpoints.
+    pub fn get_endpoint(&self) -> u8 {
+        self.endpoint
+    }
+
+    /// Returns `true` for bulk transfers.
+    pub fn is_bulk(&self) -> bool {
+        self.transfer_type == XFER_BULK
+    }
+
+    /// Returns `true` for control transfers.
+    pub fn is_control(&self) -> bool {
+        self.transfer_type == XFER_CONTROL
+    }
+
+    /// Returns the setup packet of a control transfer.
+    pub fn get_setup(&self) -> Option<[u8; 8]> {
+        self.setup
+    }
+
+    /// Returns the captured data of the transfer, which may be shorter than
+    /// the transferred length if the capture was truncated.
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the number of bytes transferred.
+    pub fn get_actual_len(&self) -> u32 {
+        self.actual_len
+    }
+
+    /// Returns the URB status, `0` on success or a negative errno.
+    pub fn get_status(&self) -> i32 {
+        self.status
+    }
+}
+
+/// Reads little or big endian integers from captured data.
+#[derive(Clone, Copy)]
+struct Endian {
+    le: bool,
+}
+impl Endian {
+    fn u16(self, buf: &[u8], at: usize) -> Option<u16> {
+        let b = buf.get(at..at + 2)?.try_into().ok()?;
+        Some(match self.le {
+            true => u16::from_le_bytes(b),
+            false => u16::from_be_bytes(b),
+        })
+    }
+
+    fn u32(self, buf: &[u8], at: usize) -> Option<u32> {
+        let b = buf.get(at..at + 4)?.try_into().ok()?;
+        Some(match self.le {
+            true => u32::from_le_bytes(b),
+            false => u32::from_be_bytes(b),
+        })
+    }
+
+    fn u64(self, buf: &[u8], at: usize) -> Option<u64> {
+        let b = buf.get(at..at + 8)?.try_into().ok()?;
+        Some(match self.le {
+            true => u64::from_le_bytes(b),
+            false => u64::from_be_bytes(b),
+        })
+    }
+}
+
+/// A usbmon event, either the submission or the completion of a URB.
+struct Urb {
+    id: u64,
+    kind: u8,
+    transfer_type: u8,
+    endpoint: u8,
+    device: u8,
+    bus: u16,
+    setup: Option<[u8; 8]>,
+    timestamp: Duration,
+    status: i32,
+    length: u32,
+    data: Vec<u8>,
+}
+impl Urb {
+    /// Parses a usbmon packet with a header of `header_len` bytes.
+    fn parse(pkt: &[u8], header_len: usize, e: Endian) -> Option<Self> {
+        if pkt.len() < header_len {
+            return None;
+        }
+
+        let flag_setup = pkt[14];
+        let ts_sec = e.u64(pkt, 16)?;
+        let ts_usec = e.u32(pkt, 24)?;
+        let len_cap = e.u32(pkt, 36)? as usize;
+        let data = pkt.get(header_len..)?;
+
+        Some(Urb {
+            id: e.u64(pkt, 0)?,
+            kind: pkt[8],
+            transfer_type: pkt[9],
+            endpoint: pkt[10],
+            device: pkt[11],
+            bus: e.u16(pkt, 12)?,
+            setup: match flag_setup {
+                0 => pkt[40..48].try_into().ok(),
+                _ => None,
+            },
+            timestamp: Duration::from_secs(ts_sec) + Duration::from_micros(ts_usec.into()),
+            status: e.u32(pkt, 28)? as i32,
+            length: e.u32(pkt, 32)?,
+            data: data[..len_cap.min(data.len())].to_vec(),
+        })
+    }
+}
+
+/// Splits a pcap file into its packets.
+fn pcap_packets(buf: &[u8]) -> Result<(u32, Endian, Vec<&[u8]>)> {
+    let e = match buf.get(0..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) | Some([0x4d, 0x3c, 0xb2, 0xa1]) => Endian { le: true },
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) | Some([0xa1, 0xb2, 0x3c, 0x4d]) => Endian { le: false },
+        _ => return Err(Error::CaptureFormatInvalid),
+    };
+    let linktype = e.u32(buf, 20).ok_or(Error::CaptureFormatInvalid)?;
+
+    let mut packets = vec![];
+    let mut at = 24;
+    while at < buf.len() {
+        let incl_len = e.u32(buf, at + 8).ok_or(Error::CaptureFormatInvalid)? as usize;
+        let pkt = buf
+            .get(at + 16..at + 16 + incl_len)
+            .ok_or(Error::CaptureFormatInvalid)?;
+        packets.push(pkt);
+        at += 16 + incl_len;
+    }
+
+    Ok((linktype & 0xffff, e, packets))
+}
+
+/// Splits a pcapng file into its packets, which must all use the same link
+/// type.
+fn pcapng_packets(buf: &[u8]) -> Result<(u32, Endian, Vec<&[u8]>)> {
+    let mut e = Endian { le: true };
+    let mut linktypes = vec![];
+    let mut packets = vec![];
+
+    let mut at = 0;
+    while at < buf.len() {
+        let block_type = e.u32(buf, at).ok_or(Error::CaptureFormatInvalid)?;
+        if block_type == 0x0A0D0D0A {
+            // section header block, the byte order magic sets the endianness
+            e = match buf.get(at + 8..at + 12) {
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => Endian { le: true },
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => Endian { le: false },
+                _ => return Err(Error::CaptureFormatInvalid),
+            };
+            linktypes.clear();
+        }
+
+        let block_len = e.u32(buf, at + 4).ok_or(Error::CaptureFormatInvalid)? as usize;
+        let block = buf
+            .get(at..at + block_len)
+            .filter(|_| block_len >= 12)
+            .ok_or(Error::CaptureFormatInvalid)?;
+
+        match block_type {
+            // interface description block
+            1 => linktypes.push(e.u16(block, 8).ok_or(Error::CaptureFormatInvalid)? as u32),
+            // enhanced packet block
+            6 => {
+                let iface = e.u32(block, 8).ok_or(Error::CaptureFormatInvalid)? as usize;
+                let cap_len = e.u32(block, 20).ok_or(Error::CaptureFormatInvalid)? as usize;
+                let pkt = block
+                    .get(28..28 + cap_len)
+                    .ok_or(Error::CaptureFormatInvalid)?;
+                packets.push((iface, pkt));
+            }
+            // simple packet block
+            3 => packets.push((0, block.get(12..block_len - 4).unwrap_or(&[]))),
+            _ => {}
+        }
+
+        at += block_len;
+    }
+
+    let linktype = match linktypes.first() {
+        Some(&l) => l,
+        None => return Err(Error::CaptureFormatInvalid),
+    };
+    if let Some(&other) = linktypes.iter().find(|&&l| l != linktype) {
+        return Err(Error::CaptureLinkTypeUnsupported(other));
+    }
+
+    Ok((linktype, e, packets.into_iter().map(|(_, p)| p).collect()))
+}
+
+/// Reads the USB transfers of a Linux usbmon capture
+///
+/// Accepts pcap and pcapng files with the `LINKTYPE_USB_LINUX` (189) or
+/// `LINKTYPE_USB_LINUX_MMAPPED` (220) link types, as written by Wireshark or
+/// tcpdump on a `usbmonN` interface. Submissions and completions of each URB
+/// are combined into one [`UsbTransfer`], in the order the transfers
+/// completed.
+///
+/// # Errors
+/// - [`Error::CaptureReadFailure`]
+/// - [`Error::CaptureFormatInvalid`]
+/// - [`Error::CaptureLinkTypeUnsupported`]
+pub fn read_capture<R: Read>(mut reader: R) -> Result<Vec<UsbTransfer>> {
+    let mut buf = vec![];
+    reader
+        .read_to_end(&mut buf)
+        .map_err(Error::CaptureReadFailure)?;
+
+    let (linktype, e, packets) = match buf.get(0..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a]) => pcapng_packets(&buf)?,
+        _ => pcap_packets(&buf)?,
+    };
+    let header_len = match linktype {
+        LINKTYPE_USB_LINUX => 48,
+        LINKTYPE_USB_LINUX_MMAPPED => 64,
+        l => return Err(Error::CaptureLinkTypeUnsupported(l)),
+    };
+
+    let mut submitted: HashMap<u64, Urb> = HashMap::new();
+    let mut transfers = vec![];
+    for pkt in packets {
+        let urb = Urb::parse(pkt, header_len, e).ok_or(Error::CaptureFormatInvalid)?;
+        if urb.kind == b'S' {
+            submitted.insert(urb.id, urb);
+            continue;
+        }
+
+        // 'C' completes a submitted URB, 'E' reports a failed submission. The
+        // setup packet and OUT data are only captured on submission.
+        let (setup, data) = match submitted.remove(&urb.id) {
+            Some(s) if urb.endpoint & 0x80 == 0 => (s.setup, s.data),
+            Some(s) => (s.setup, urb.data),
+            None => (urb.setup, urb.data),
+        };
+        transfers.push(UsbTransfer {
+            timestamp: urb.timestamp,
+            bus: urb.bus,
+            device: urb.device,
+            endpoint: urb.endpoint,
+            transfer_type: urb.transfer_type,
+            setup,
+            data,
+            actual_len: urb.length,
+            status: urb.status,
+        });
+    }
+
+    Ok(transfers)
+}
+
+/// A PICOBOOT command reconstructed from USB traffic.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    timestamp: Duration,
+    bus: u16,
+    device: u8,
+    cmd: PicobootCmd,
+    data_len: u32,
+    status: Option<PicobootStatusCmd>,
+    stalled: bool,
+    acked: bool,
+}
+impl Transaction {
+    /// Returns the time the bulk OUT transfer of the command packet
+    /// completed, relative to the completion of the first transfer of the
+    /// capture.
+    pub fn get_timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Returns the command that was sent.
+    pub fn get_cmd(&self) -> &PicobootCmd {
+        &self.cmd
+    }
+
+    /// Returns the number of bytes moved in the data phase.
+    pub fn get_data_len(&self) -> u32 {
+        self.data_len
+    }
+
+    /// Returns the last command status read for this command, if any.
+    pub fn get_status(&self) -> Option<&PicobootStatusCmd> {
+        self.status.as_ref()
+    }
+
+    /// Returns `true` if an endpoint stalled during the command.
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    /// Returns `true` if the command was acknowledged.
+    pub fn is_acked(&self) -> bool {
+        self.acked
+    }
+
+    fn data_pending(&self) -> bool {
+        self.data_len < self.cmd.get_transfer_len()
+    }
+}
+
+/// Formats a command status reply.
+struct Status<'a>(&'a PicobootStatusCmd);
+impl fmt::Display for Status<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.get_status_code() {
+            Ok(code) => write!(f, "{:?}", code)?,
+            Err(_) => write!(f, "unknown status")?,
+        }
+        if self.0.get_in_progress() != 0 {
+            write!(f, " (in progress)")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:>12.6}] {}:{} {}",
+            self.timestamp.as_secs_f64(),
+            self.bus,
+            self.device,
+            self.cmd
+        )?;
+        if self.cmd.get_transfer_len() != 0 {
+            write!(f, " data={:#x}", self.data_len)?;
+        }
+        if self.stalled {
+            write!(f, " stalled")?;
+        }
+        if !self.acked {
+            write!(f, " no-ack")?;
+        }
+        match &self.status {
+            Some(s) => write!(f, " -> {}", Status(s)),
+            None => write!(f, " -> no status"),
+        }
+    }
+}
+
+/// An entry of a PICOBOOT transcript.
+#[derive(Debug, Clone)]
+pub enum TranscriptEntry {
+    /// A command and everything that happened until it was acknowledged.
+    Command(Transaction),
+    /// A PICOBOOT interface reset control request.
+    InterfaceReset {
+        timestamp: Duration,
+        bus: u16,
+        device: u8,
+    },
+    /// A command status reply that does not belong to any captured command.
+    Status {
+        timestamp: Duration,
+        bus: u16,
+        device: u8,
+        status: PicobootStatusCmd,
+    },
+}
+impl fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptEntry::Command(t) => t.fmt(f),
+            TranscriptEntry::InterfaceReset {
+                timestamp,
+                bus,
+                device,
+            } => write!(
+                f,
+                "[{:>12.6}] {}:{} interface reset",
+                timestamp.as_secs_f64(),
+                bus,
+                device
+            ),
+            TranscriptEntry::Status {
+                timestamp,
+                bus,
+                device,
+                status,
+            } => write!(
+                f,
+                "[{:>12.6}] {}:{} status {}",
+                timestamp.as_secs_f64(),
+                bus,
+                device,
+                status
+            ),
+        }
+    }
+}
+
+/// PICOBOOT state of one device while dissecting.
+#[derive(Default)]
+struct DeviceState {
+    out_ep: Option<u8>,
+    in_ep: Option<u8>,
+    /// Index of the command that has not been acknowledged yet.
+    current: Option<usize>,
+}
+
+/// Reconstructs PICOBOOT commands from captured USB transfers
+///
+/// Command packets are recognized on any bulk OUT endpoint by their
+/// [`crate::PICOBOOT_MAGIC`], which also identifies the PICOBOOT endpoints of
+/// each device. The following bulk transfers are assigned to the data and
+/// acknowledgement phases of the command, and command status replies to the
+/// command with the same token. Transfers that are not PICOBOOT traffic are
+/// skipped.
+pub fn dissect(transfers: &[UsbTransfer]) -> Vec<TranscriptEntry> {
+    let start = transfers.first().map(|t| t.timestamp).unwrap_or_default();
+    let mut devices: HashMap<(u16, u8), DeviceState> = HashMap::new();
+    let mut entries = vec![];
+
+    for t in transfers {
+        let timestamp = t.timestamp.saturating_sub(start);
+        let (bus, device) = (t.bus, t.device);
+        let state = devices.entry((bus, device)).or_default();
+
+        if t.is_bulk() {
+            let ep = t.endpoint;
+            if ep & 0x80 == 0 {
+                if let Ok(cmd) = PicobootCmd::from_bytes(&t.data) {
+                    state.out_ep = Some(ep);
+                    state.current = Some(entries.len());
+                    entries.push(TranscriptEntry::Command(Transaction {
+                        timestamp,
+                        bus,
+                        device,
+                        cmd,
+                        data_len: 0,
+                        status: None,
+                        stalled: t.status == URB_STALL,
+                        acked: false,
+                    }));
+                    continue;
+                }
+                if state.out_ep != Some(ep) {
+                    continue;
+                }
+            } else if state.out_ep.is_none() || state.in_ep.map_or(false, |e| e != ep) {
+                continue;
+            }
+
+            let tx = match state.current.map(|i| &mut entries[i]) {
+                Some(TranscriptEntry::Command(tx)) => tx,
+                _ => continue,
+            };
+            if ep & 0x80 != 0 {
+                state.in_ep = Some(ep);
+            }
+            if t.status == URB_STALL {
+                tx.stalled = true;
+                continue;
+            }
+
+            let is_data_dir = tx.cmd.is_in() == (ep & 0x80 != 0);
+            if is_data_dir && tx.data_pending() {
+                tx.data_len += t.actual_len;
+            } else if !is_data_dir {
+                tx.acked = true;
+                state.current = None;
+            }
+        } else if t.is_control() {
+            let setup = match t.setup {
+                Some(s) => s,
+                None => continue,
+            };
+            match (setup[0], setup[1]) {
+                (0xC1, 0x42) => {
+                    let status = match PicobootStatusCmd::from_bytes(&t.data) {
+                        Ok(s) => s,
+                        Err(_) => continue,
+                    };
+
+                    // attach to the latest command of the device with this token
+                    let owner = entries.iter_mut().rev().find_map(|e| match e {
+                        TranscriptEntry::Command(tx)
+                            if (tx.bus, tx.device) == (bus, device)
+                                && tx.cmd.get_token() == status.get_token() =>
+                        {
+                            Some(tx)
+                        }
+                        _ => None,
+                    });
+                    match owner {
+                        Some(tx) => tx.status = Some(status),
+                        None => entries.push(TranscriptEntry::Status {
+                            timestamp,
+                            bus,
+                            device,
+                            status,
+                        }),
+                    }
+                }
+                (0x41, 0x41) => {
+                    state.current = None;
+                    entries.push(TranscriptEntry::InterfaceReset {
+                        timestamp,
+                        bus,
+                        device,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PicobootStatus, FLASH_START};
+
+    const OUT_EP: u8 = 0x03;
+    const IN_EP: u8 = 0x84;
+
+    /// A usbmon event, to be written into a capture.
+    struct Event {
+        id: u64,
+        kind: u8,
+        transfer_type: u8,
+        endpoint: u8,
+        setup: Option<[u8; 8]>,
+        micros: u64,
+        length: u32,
+        data: Vec<u8>,
+    }
+
+    fn put(out: &mut Vec<u8>, le: bool, bytes_le: &[u8]) {
+        match le {
+            true => out.extend(bytes_le),
+            false => out.extend(bytes_le.iter().rev()),
+        }
+    }
+
+    impl Event {
+        fn ser(&self, le: bool, header_len: usize) -> Vec<u8> {
+            let mut pkt = vec![];
+            put(&mut pkt, le, &self.id.to_le_bytes());
+            pkt.extend([self.kind, self.transfer_type, self.endpoint, 5]);
+            put(&mut pkt, le, &1u16.to_le_bytes());
+            pkt.extend([if self.setup.is_some() { 0 } else { b'-' }, 0]);
+            put(&mut pkt, le, &(self.micros / 1_000_000).to_le_bytes());
+            put(
+                &mut pkt,
+                le,
+                &((self.micros % 1_000_000) as u32).to_le_bytes(),
+            );
+            put(&mut pkt, le, &0u32.to_le_bytes());
+            put(&mut pkt, le, &self.length.to_le_bytes());
+            put(&mut pkt, le, &(self.data.len() as u32).to_le_bytes());
+            pkt.extend(self.setup.unwrap_or_default());
+            pkt.resize(header_len, 0);
+            pkt.extend(&self.data);
+            pkt
+        }
+    }
+
+    /// Returns the submission and completion of a transfer.
+    fn transfer(
+        id: u64,
+        micros: u64,
+        transfer_type: u8,
+        endpoint: u8,
+        setup: Option<[u8; 8]>,
+        data: &[u8],
+    ) -> [Event; 2] {
+        let is_in = endpoint & 0x80 != 0 || setup.map_or(false, |s| s[0] & 0x80 != 0);
+        let (submit_data, complete_data) = match is_in {
+            true => (vec![], data.to_vec()),
+            false => (data.to_vec(), vec![]),
+        };
+        [
+            Event {
+                id,
+                kind: b'S',
+                transfer_type,
+                endpoint,
+                setup,
+                micros,
+                length: data.len() as u32,
+                data: submit_data,
+            },
+            Event {
+                id,
+                kind: b'C',
+                transfer_type,
+                endpoint,
+                setup: None,
+                micros: micros + 100,
+                length: data.len() as u32,
+                data: complete_data,
+            },
+        ]
+    }
+
+    fn status(in_progress: bool) -> Vec<u8> {
+        let status = PicobootStatusCmd::new(1, PicobootStatus::Ok, 0x84, in_progress);
+        status.to_bytes().to_vec()
+    }
+
+    /// A READ of 16 bytes followed by an interface reset.
+    fn session() -> Vec<Event> {
+        let cmd = PicobootCmd::flash_read(FLASH_START, 16).set_token(1);
+        let get_status = Some([0xC1, 0x42, 0, 0, 0, 0, 16, 0]);
+        let reset = Some([0x41, 0x41, 0, 0, 0, 0, 0, 0]);
+
+        [
+            transfer(1, 1_000_000, XFER_BULK, OUT_EP, None, &cmd.to_bytes()),
+            transfer(2, 1_001_000, XFER_CONTROL, 0x80, get_status, &status(true)),
+            transfer(3, 1_002_000, XFER_BULK, IN_EP, None, &[0xA5; 16]),
+            transfer(4, 1_003_000, XFER_BULK, OUT_EP, None, &[0]),
+            transfer(5, 1_004_000, XFER_CONTROL, 0x80, get_status, &status(false)),
+            transfer(6, 2_500_000, XFER_CONTROL, 0x00, reset, &[]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn pcap(events: &[Event], le: bool, linktype: u32) -> Vec<u8> {
+        let header_len = if linktype == LINKTYPE_USB_LINUX {
+            48
+        } else {
+            64
+        };
+        let mut out = vec![];
+        put(&mut out, le, &0xa1b2c3d4u32.to_le_bytes());
+        put(&mut out, le, &2u16.to_le_bytes());
+        put(&mut out, le, &4u16.to_le_bytes());
+        out.extend([0; 12]);
+        put(&mut out, le, &linktype.to_le_bytes());
+        for event in events {
+            let pkt = event.ser(le, header_len);
+            out.extend([0; 8]);
+            put(&mut out, le, &(pkt.len() as u32).to_le_bytes());
+            put(&mut out, le, &(pkt.len() as u32).to_le_bytes());
+            out.extend(pkt);
+        }
+        out
+    }
+
+    fn pcapng_block(out: &mut Vec<u8>, le: bool, block_type: u32, body: &[u8]) {
+        let len = (12 + body.len() + 3) / 4 * 4;
+        put(out, le, &block_type.to_le_bytes());
+        put(out, le, &(len as u32).to_le_bytes());
+        out.extend(body);
+        out.resize(out.len() + len - 12 - body.len(), 0);
+        put(out, le, &(len as u32).to_le_bytes());
+    }
+
+    fn pcapng(events: &[Event], le: bool, simple: bool) -> Vec<u8> {
+        let mut out = vec![];
+        let mut shb = vec![];
+        put(&mut shb, le, &0x1A2B3C4Du32.to_le_bytes());
+        put(&mut shb, le, &1u16.to_le_bytes());
+        put(&mut shb, le, &0u16.to_le_bytes());
+        shb.extend([0xFF; 8]);
+        pcapng_block(&mut out, le, 0x0A0D0D0A, &shb);
+
+        let mut idb = vec![];
+        put(
+            &mut idb,
+            le,
+            &(LINKTYPE_USB_LINUX_MMAPPED as u16).to_le_bytes(),
+        );
+        put(&mut idb, le, &0u16.to_le_bytes());
+        put(&mut idb, le, &0u32.to_le_bytes());
+        pcapng_block(&mut out, le, 1, &idb);
+
+        for event in events {
+            let pkt = event.ser(le, 64);
+            let mut body = vec![];
+            if simple {
+                put(&mut body, le, &(pkt.len() as u32).to_le_bytes());
+                body.extend(pkt);
+                pcapng_block(&mut out, le, 3, &body);
+            } else {
+                body.extend([0; 12]);
+                put(&mut body, le, &(pkt.len() as u32).to_le_bytes());
+                put(&mut body, le, &(pkt.len() as u32).to_le_bytes());
+                body.extend(pkt);
+                pcapng_block(&mut out, le, 6, &body);
+            }
+        }
+        out
+    }
+
+    fn check_session(capture: &[u8]) {
+        let transfers = read_capture(capture).unwrap();
+        assert_eq!(transfers.len(), 6);
+
+        // OUT data comes from the submission, IN data from the completion
+        let cmd = &transfers[0];
+        assert!(cmd.is_bulk());
+        assert_eq!((cmd.get_bus(), cmd.get_device()), (1, 5));
+        assert_eq!(cmd.get_timestamp(), Duration::from_micros(1_000_100));
+        assert_eq!(cmd.get_data().len(), 32);
+        assert_eq!(transfers[1].get_setup().unwrap()[..2], [0xC1, 0x42]);
+        assert_eq!(transfers[1].get_data(), status(true));
+        assert_eq!(transfers[2].get_data(), [0xA5; 16]);
+        assert_eq!(transfers[2].get_actual_len(), 16);
+
+        let entries = dissect(&transfers);
+        assert_eq!(entries.len(), 2);
+        let tx = match &entries[0] {
+            TranscriptEntry::Command(tx) => tx,
+            e => panic!("unexpected entry {:?}", e),
+        };
+        assert_eq!(
+            tx.get_cmd(),
+            &PicobootCmd::flash_read(FLASH_START, 16).set_token(1)
+        );
+        assert_eq!(tx.get_timestamp(), Duration::ZERO);
+        assert_eq!(tx.get_data_len(), 16);
+        assert!(tx.is_acked() && !tx.is_stalled());
+        assert_eq!(tx.get_status().unwrap().get_in_progress(), 0);
+
+        match &entries[1] {
+            TranscriptEntry::InterfaceReset { timestamp, .. } => {
+                assert_eq!(*timestamp, Duration::from_micros(1_500_000))
+            }
+            e => panic!("unexpected entry {:?}", e),
+        }
+    }
+
+    #[test]
+    fn pcap_both_byte_orders() {
+        for le in [true, false] {
+            check_session(&pcap(&session(), le, LINKTYPE_USB_LINUX));
+            check_session(&pcap(&session(), le, LINKTYPE_USB_LINUX_MMAPPED));
+        }
+    }
+
+    #[test]
+    fn pcapng_both_byte_orders() {
+        for le in [true, false] {
+            check_session(&pcapng(&session(), le, false));
+            check_session(&pcapng(&session(), le, true));
+        }
+    }
+
+    #[test]
+    fn unmatched_status() {
+        let get_status = Some([0xC1, 0x42, 0, 0, 0, 0, 16, 0]);
+        let events = transfer(1, 0, XFER_CONTROL, 0x80, get_status, &status(false));
+        let entries = dissect(&read_capture(&pcap(&events, true, LINKTYPE_USB_LINUX)[..]).unwrap());
+        assert!(matches!(
+            entries[..],
+            [TranscriptEntry::Status { ref status, .. }] if status.get_token() == 1
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_packet() {
+        let capture = pcap(&session(), true, LINKTYPE_USB_LINUX);
+        assert!(matches!(
+            read_capture(&capture[..capture.len() - 1]),
+            Err(Error::CaptureFormatInvalid)
+        ));
+
+        // a packet shorter than the usbmon header
+        let mut capture = pcap(&[], true, LINKTYPE_USB_LINUX);
+        capture.extend([0; 8]);
+        capture.extend(16u32.to_le_bytes());
+        capture.extend(16u32.to_le_bytes());
+        capture.extend([0; 16]);
+        assert!(matches!(
+            read_capture(&capture[..]),
+            Err(Error::CaptureFormatInvalid)
+        ));
+    }
+
+    #[test]
+    fn rejects_short_block() {
+        let mut capture = pcapng(&[], true, false);
+        capture.extend(6u32.to_le_bytes());
+        capture.extend(8u32.to_le_bytes());
+        capture.extend([0; 4]);
+        assert!(matches!(
+            read_capture(&capture[..]),
+            Err(Error::CaptureFormatInvalid)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_link_type() {
+        let capture = pcap(&session(), true, 1);
+        assert!(matches!(
+            read_capture(&capture[..]),
+            Err(Error::CaptureLinkTypeUnsupported(1))
+        ));
+    }
+}
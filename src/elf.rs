@@ -0,0 +1,361 @@
+use crate::{cmd::PicobootError, image::SparseImage, CpuArch};
+
+use std::fmt;
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+// see https://refspecs.linuxfoundation.org/elf/elf.pdf for the file format
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_TYPE_EXEC: u16 = 2;
+const ELF_MACHINE_ARM: u16 = 40;
+const ELF_MACHINE_RISCV: u16 = 243;
+const ELF_HEADER_SIZE: usize = 52;
+const ELF_PROGRAM_HEADER_SIZE: usize = 32;
+const PT_LOAD: u32 = 1;
+
+fn half(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn word(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Loadable (PT_LOAD) segment of an ELF file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSegment {
+    addr: u32,
+    vaddr: u32,
+    mem_size: u32,
+    flags: u32,
+    data: Vec<u8>,
+}
+impl ElfSegment {
+    /// Returns the physical address the segment is loaded at.
+    pub fn get_addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Returns the virtual address the segment runs at, which differs from
+    /// the physical address for data copied to RAM at startup.
+    pub fn get_vaddr(&self) -> u32 {
+        self.vaddr
+    }
+
+    /// Returns the size of the segment in memory, which may be larger than
+    /// its data, the rest being zeroed at startup.
+    pub fn get_mem_size(&self) -> u32 {
+        self.mem_size
+    }
+
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns the data of the segment in the file.
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+}
+impl fmt::Display for ElfSegment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "addr={:#010x} size={:#x}", self.addr, self.data.len())?;
+        if self.vaddr != self.addr {
+            write!(f, " vaddr={:#010x}", self.vaddr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Overlap between two loadable segments of an ELF file.
+///
+/// Overlapping segments usually point to a broken linker script. When the
+/// file is loaded, the data of the later segment is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElfOverlap {
+    first: usize,
+    second: usize,
+    addr: u32,
+    size: u32,
+}
+impl ElfOverlap {
+    /// Returns the index of the earlier segment.
+    pub fn get_first(&self) -> usize {
+        self.first
+    }
+
+    /// Returns the index of the later segment.
+    pub fn get_second(&self) -> usize {
+        self.second
+    }
+
+    /// Returns the address the overlap starts at.
+    pub fn get_addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Returns the number of overlapping bytes.
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+}
+impl fmt::Display for ElfOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "segments {} and {} overlap at {:#010x}..{:#010x}",
+            self.first,
+            self.second,
+            self.addr,
+            self.addr as u64 + self.size as u64
+        )
+    }
+}
+
+/// Parsed 32-bit little-endian ELF executable for ARM or RISC-V.
+///
+/// Only the loadable (PT_LOAD) segments with data in the file are kept,
+/// addressed by their physical (load) address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfFile {
+    cpu_arch: CpuArch,
+    entry: u32,
+    segments: Vec<ElfSegment>,
+}
+impl ElfFile {
+    /// Parses an ELF file.
+    ///
+    /// # Errors:
+    /// - [`Error::ElfFormatInvalid`]
+    /// - [`Error::ElfUnsupported`]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < ELF_HEADER_SIZE || data[..4] != ELF_MAGIC {
+            return Err(Error::ElfFormatInvalid);
+        }
+        if data[4] != ELF_CLASS_32 || data[5] != ELF_DATA_LSB {
+            return Err(Error::ElfUnsupported);
+        }
+        if half(data, 16) != ELF_TYPE_EXEC {
+            return Err(Error::ElfUnsupported);
+        }
+        let cpu_arch = match half(data, 18) {
+            ELF_MACHINE_ARM => CpuArch::Arm,
+            ELF_MACHINE_RISCV => CpuArch::RiscV,
+            _ => return Err(Error::ElfUnsupported),
+        };
+        let entry = word(data, 24);
+
+        let phoff = word(data, 28) as usize;
+        let phentsize = half(data, 42) as usize;
+        let phnum = half(data, 44) as usize;
+        if phnum > 0 && phentsize < ELF_PROGRAM_HEADER_SIZE {
+            return Err(Error::ElfFormatInvalid);
+        }
+
+        let mut segments = vec![];
+        for i in 0..phnum {
+            let ph = (i.checked_mul(phentsize))
+                .and_then(|offset| phoff.checked_add(offset))
+                .and_then(|start| data.get(start..start.checked_add(ELF_PROGRAM_HEADER_SIZE)?))
+                .ok_or(Error::ElfFormatInvalid)?;
+            let filesz = word(ph, 16) as usize;
+            if word(ph, 0) != PT_LOAD || filesz == 0 {
+                continue;
+            }
+
+            let offset = word(ph, 4) as usize;
+            let seg = offset
+                .checked_add(filesz)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(Error::ElfFormatInvalid)?;
+            segments.push(ElfSegment {
+                addr: word(ph, 12),
+                vaddr: word(ph, 8),
+                mem_size: word(ph, 20),
+                flags: word(ph, 24),
+                data: seg.to_vec(),
+            });
+        }
+
+        Ok(ElfFile {
+            cpu_arch,
+            entry,
+            segments,
+        })
+    }
+
+    /// Returns the architecture the file is built for.
+    pub fn get_cpu_arch(&self) -> CpuArch {
+        self.cpu_arch
+    }
+
+    /// Returns the entry point address.
+    pub fn get_entry(&self) -> u32 {
+        self.entry
+    }
+
+    pub fn get_segments(&self) -> &[ElfSegment] {
+        &self.segments
+    }
+
+    /// Returns every overlap between the loadable segments.
+    pub fn get_overlaps(&self) -> Vec<ElfOverlap> {
+        let mut overlaps = vec![];
+        for (i, a) in self.segments.iter().enumerate() {
+            for (j, b) in self.segments.iter().enumerate().skip(i + 1) {
+                let start = a.addr.max(b.addr) as u64;
+                let end =
+                    (a.addr as u64 + a.data.len() as u64).min(b.addr as u64 + b.data.len() as u64);
+                if start < end {
+                    overlaps.push(ElfOverlap {
+                        first: i,
+                        second: j,
+                        addr: start as u32,
+                        size: (end - start) as u32,
+                    });
+                }
+            }
+        }
+        overlaps
+    }
+
+    /// Collects the loadable segments into an image, in file order.
+    pub fn to_image(&self) -> SparseImage {
+        let mut image = SparseImage::new();
+        for seg in self.segments.iter() {
+            image.write(seg.addr, &seg.data);
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an ELF executable from `(type, paddr, vaddr, data)` segments.
+    fn build(machine: u16, entry: u32, segments: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+        let phoff = ELF_HEADER_SIZE;
+        let mut offset = phoff + segments.len() * ELF_PROGRAM_HEADER_SIZE;
+
+        let mut elf = vec![0; ELF_HEADER_SIZE];
+        elf[..4].copy_from_slice(&ELF_MAGIC);
+        elf[4] = ELF_CLASS_32;
+        elf[5] = ELF_DATA_LSB;
+        elf[6] = 1;
+        elf[16..18].copy_from_slice(&ELF_TYPE_EXEC.to_le_bytes());
+        elf[18..20].copy_from_slice(&machine.to_le_bytes());
+        elf[24..28].copy_from_slice(&entry.to_le_bytes());
+        elf[28..32].copy_from_slice(&(phoff as u32).to_le_bytes());
+        elf[42..44].copy_from_slice(&(ELF_PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        elf[44..46].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        for (kind, paddr, vaddr, data) in segments {
+            let size = data.len() as u32;
+            let words = [*kind, offset as u32, *vaddr, *paddr, size, size, 5, 4];
+            elf.extend(words.iter().flat_map(|w| w.to_le_bytes()));
+            offset += data.len();
+        }
+        for (_, _, _, data) in segments {
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    #[test]
+    fn parse_segments() {
+        let data = build(
+            ELF_MACHINE_ARM,
+            0x1000_00ED,
+            &[
+                (PT_LOAD, 0x1000_0000, 0x1000_0000, &[1; 64]),
+                (4, 0x1000_0040, 0x1000_0040, &[2; 8]),
+                (PT_LOAD, 0x1000_0040, 0x2000_0000, &[3; 16]),
+                (PT_LOAD, 0x2000_0100, 0x2000_0100, &[]),
+            ],
+        );
+        let elf = ElfFile::parse(&data).unwrap();
+        assert_eq!(elf.get_cpu_arch(), CpuArch::Arm);
+        assert_eq!(elf.get_entry(), 0x1000_00ED);
+
+        // only loadable segments with data are kept
+        let segments = elf.get_segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].get_addr(), 0x1000_0040);
+        assert_eq!(segments[1].get_vaddr(), 0x2000_0000);
+        assert_eq!(segments[1].get_data(), &[3; 16]);
+        assert!(elf.get_overlaps().is_empty());
+
+        let image = elf.to_image();
+        assert_eq!(image.get_segments().count(), 1);
+        assert_eq!(image.len(), 80);
+    }
+
+    #[test]
+    fn overlapping_segments() {
+        let data = build(
+            ELF_MACHINE_RISCV,
+            0,
+            &[
+                (PT_LOAD, 0x2000_0000, 0x2000_0000, &[1; 32]),
+                (PT_LOAD, 0x2000_0010, 0x2000_0010, &[2; 32]),
+            ],
+        );
+        let elf = ElfFile::parse(&data).unwrap();
+        assert_eq!(elf.get_cpu_arch(), CpuArch::RiscV);
+
+        let overlaps = elf.get_overlaps();
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!((overlaps[0].get_first(), overlaps[0].get_second()), (0, 1));
+        assert_eq!(
+            (overlaps[0].get_addr(), overlaps[0].get_size()),
+            (0x2000_0010, 16)
+        );
+
+        // the later segment wins
+        let image = elf.to_image();
+        assert_eq!(image.read(0x2000_000F, 2), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn rejects_truncated() {
+        let data = build(ELF_MACHINE_ARM, 0, &[(PT_LOAD, 0, 0, &[1; 64])]);
+
+        // header, program headers and segment data
+        for len in [ELF_HEADER_SIZE - 1, ELF_HEADER_SIZE + 16, data.len() - 1] {
+            assert!(matches!(
+                ElfFile::parse(&data[..len]),
+                Err(Error::ElfFormatInvalid)
+            ));
+        }
+
+        let mut bad_magic = data.clone();
+        bad_magic[1] = b'F';
+        assert!(matches!(
+            ElfFile::parse(&bad_magic),
+            Err(Error::ElfFormatInvalid)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported() {
+        let data = build(62, 0, &[]);
+        assert!(matches!(ElfFile::parse(&data), Err(Error::ElfUnsupported)));
+
+        let mut data = build(ELF_MACHINE_ARM, 0, &[]);
+        data[4] = 2;
+        assert!(matches!(ElfFile::parse(&data), Err(Error::ElfUnsupported)));
+
+        let mut data = build(ELF_MACHINE_ARM, 0, &[]);
+        data[16] = 3;
+        assert!(matches!(ElfFile::parse(&data), Err(Error::ElfUnsupported)));
+    }
+}
@@ -0,0 +1,1060 @@
+use crate::{
+    cmd::{PicobootCmd, PicobootStatus, TargetID},
+    device::{PicobootHandler, PicobootResponder},
+    protocol::Reboot2Options,
+    transport::{Endpoint, LoopbackTransport, PicobootTransport, TransportError},
+    FLASH_END_RP2040, FLASH_END_RP2350, FLASH_START, GET_INFO_PARTITION_TABLE, GET_INFO_SYS,
+    GET_INFO_UF2_STATUS, GET_INFO_UF2_TARGET_PARTITION, OTP_ROW_COUNT, PAGE_SIZE, PT_INFO_PT_INFO,
+    PT_INFO_SINGLE_PARTITION, ROM_CHIP_RP2040, ROM_CHIP_RP2350, ROM_END_RP2040, ROM_END_RP2350,
+    ROM_IDENTITY_ADDR, ROM_START, SECTOR_SIZE, SRAM_END_RP2040, SRAM_END_RP2350, SRAM_START_RP2040,
+    SYS_INFO_CHIP_INFO, SYS_INFO_CPU_INFO, SYS_INFO_CRITICAL, SYS_INFO_FLASH_DEV_INFO,
+    XIP_SRAM_END_RP2040, XIP_SRAM_END_RP2350, XIP_SRAM_START_RP2040, XIP_SRAM_START_RP2350,
+};
+
+use std::{collections::BTreeMap, time::Duration};
+
+type Result<T> = ::std::result::Result<T, TransportError>;
+type StatusResult = ::std::result::Result<(), PicobootStatus>;
+
+/// Default size of the emulated flash chip (2MB, as on a Raspberry Pi Pico).
+pub const DEFAULT_FLASH_SIZE: u32 = 0x200000;
+
+/// A reboot requested from an emulated device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootRequest {
+    /// REBOOT command (RP2040).
+    Reboot {
+        /// Program counter, `0` for a normal flash boot.
+        pc: u32,
+        /// Stack pointer.
+        sp: u32,
+        /// Delay in milliseconds.
+        delay: u32,
+    },
+    /// REBOOT2 command (RP2350).
+    Reboot2 {
+        /// Reboot type and option flags.
+        flags: u32,
+        /// Delay in milliseconds.
+        delay: u32,
+        /// First flag-specific parameter.
+        p0: u32,
+        /// Second flag-specific parameter.
+        p1: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Rom,
+    Flash,
+    Sram,
+    XipSram,
+}
+
+/// Memory and BOOTSEL state of an emulated device.
+#[derive(Debug)]
+struct Device {
+    target_id: TargetID,
+    rom: Vec<u8>,
+    flash: BTreeMap<u32, Vec<u8>>,
+    flash_size: u32,
+    sram: Vec<u8>,
+    xip_sram: Vec<u8>,
+    otp: Vec<u32>,
+
+    exclusive: u8,
+    xip: bool,
+    reboot: Option<RebootRequest>,
+    pending_reboot: Option<RebootRequest>,
+    disconnected: bool,
+}
+impl Device {
+    fn flash_end(&self) -> u32 {
+        match self.target_id {
+            TargetID::Rp2040 => FLASH_END_RP2040,
+            TargetID::Rp2350 => FLASH_END_RP2350,
+        }
+    }
+
+    fn region(&self, addr: u32, size: u32) -> Option<Region> {
+        let (rom_end, xip_sram, sram_end) = match self.target_id {
+            TargetID::Rp2040 => (
+                ROM_END_RP2040,
+                (XIP_SRAM_START_RP2040, XIP_SRAM_END_RP2040),
+                SRAM_END_RP2040,
+            ),
+            TargetID::Rp2350 => (
+                ROM_END_RP2350,
+                (XIP_SRAM_START_RP2350, XIP_SRAM_END_RP2350),
+                SRAM_END_RP2350,
+            ),
+        };
+        let regions = [
+            (Region::Rom, ROM_START, rom_end),
+            (Region::Flash, FLASH_START, FLASH_START + self.flash_size),
+            (Region::XipSram, xip_sram.0, xip_sram.1),
+            (Region::Sram, SRAM_START_RP2040, sram_end),
+        ];
+
+        let end = addr.checked_add(size)?;
+        regions
+            .iter()
+            .find(|(_, start, stop)| addr >= *start && end <= *stop)
+            .map(|(r, _, _)| *r)
+    }
+
+    fn region_slice(&mut self, region: Region, addr: u32, size: u32) -> &mut [u8] {
+        let (mem, start) = match region {
+            Region::Rom => (&mut self.rom, ROM_START),
+            Region::Sram => (&mut self.sram, SRAM_START_RP2040),
+            Region::XipSram => match self.target_id {
+                TargetID::Rp2040 => (&mut self.xip_sram, XIP_SRAM_START_RP2040),
+                TargetID::Rp2350 => (&mut self.xip_sram, XIP_SRAM_START_RP2350),
+            },
+            Region::Flash => unreachable!("flash is stored sparsely"),
+        };
+        let offset = (addr - start) as usize;
+        &mut mem[offset..offset + size as usize]
+    }
+
+    fn flash_byte(&mut self, addr: u32) -> &mut u8 {
+        let sector = addr - addr % SECTOR_SIZE;
+        let data = self
+            .flash
+            .entry(sector)
+            .or_insert_with(|| vec![0xFF; SECTOR_SIZE as usize]);
+        &mut data[(addr - sector) as usize]
+    }
+
+    fn read_region(&self, addr: u32, buf: &mut [u8]) {
+        let size = buf.len() as u32;
+        let (mem, start) = match self.region(addr, size) {
+            Some(Region::Flash) => {
+                for (a, b) in (addr..addr + size).zip(buf.iter_mut()) {
+                    let sector = a - a % SECTOR_SIZE;
+                    *b = self
+                        .flash
+                        .get(&sector)
+                        .map_or(0xFF, |d| d[(a - sector) as usize]);
+                }
+                return;
+            }
+            Some(Region::Rom) => (&self.rom, ROM_START),
+            Some(Region::Sram) => (&self.sram, SRAM_START_RP2040),
+            Some(Region::XipSram) => match self.target_id {
+                TargetID::Rp2040 => (&self.xip_sram, XIP_SRAM_START_RP2040),
+                TargetID::Rp2350 => (&self.xip_sram, XIP_SRAM_START_RP2350),
+            },
+            None => return,
+        };
+        let offset = (addr - start) as usize;
+        buf.copy_from_slice(&mem[offset..offset + buf.len()]);
+    }
+}
+impl PicobootHandler for Device {
+    fn exclusive_access(&mut self, mode: u8) -> StatusResult {
+        self.exclusive = mode;
+        Ok(())
+    }
+
+    fn reboot(&mut self, pc: u32, sp: u32, delay: u32) -> StatusResult {
+        if self.target_id != TargetID::Rp2040 {
+            return Err(PicobootStatus::UnknownCmd);
+        }
+        self.pending_reboot = Some(RebootRequest::Reboot { pc, sp, delay });
+        Ok(())
+    }
+
+    fn reboot2(&mut self, flags: u32, delay: u32, p0: u32, p1: u32) -> StatusResult {
+        if self.target_id != TargetID::Rp2350 {
+            return Err(PicobootStatus::UnknownCmd);
+        }
+        if Reboot2Options::from_args(flags, p0, p1).is_none() {
+            return Err(PicobootStatus::InvalidArg);
+        }
+        self.pending_reboot = Some(RebootRequest::Reboot2 {
+            flags,
+            delay,
+            p0,
+            p1,
+        });
+        Ok(())
+    }
+
+    fn flash_erase(&mut self, addr: u32, size: u32) -> StatusResult {
+        if addr % SECTOR_SIZE != 0 || size % SECTOR_SIZE != 0 {
+            return Err(PicobootStatus::BadAlignment);
+        }
+        if self.region(addr, size) != Some(Region::Flash) {
+            return Err(PicobootStatus::InvalidAddress);
+        }
+        for sector in (addr..addr + size).step_by(SECTOR_SIZE as usize) {
+            self.flash.remove(&sector);
+        }
+        Ok(())
+    }
+
+    fn begin_read(&mut self, addr: u32, size: u32) -> StatusResult {
+        match self.region(addr, size) {
+            Some(_) => Ok(()),
+            None => Err(PicobootStatus::InvalidAddress),
+        }
+    }
+
+    fn read(&mut self, addr: u32, buf: &mut [u8]) -> StatusResult {
+        self.read_region(addr, buf);
+        Ok(())
+    }
+
+    fn begin_write(&mut self, addr: u32, size: u32) -> StatusResult {
+        match self.region(addr, size) {
+            None | Some(Region::Rom) => Err(PicobootStatus::InvalidAddress),
+            Some(Region::Flash) if addr % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 => {
+                Err(PicobootStatus::BadAlignment)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn write(&mut self, addr: u32, data: &[u8]) -> StatusResult {
+        match self.region(addr, data.len() as u32) {
+            Some(Region::Flash) => {
+                for (i, b) in data.iter().enumerate() {
+                    *self.flash_byte(addr + i as u32) &= *b;
+                }
+            }
+            Some(region) => self
+                .region_slice(region, addr, data.len() as u32)
+                .copy_from_slice(data),
+            None => return Err(PicobootStatus::InvalidAddress),
+        }
+        Ok(())
+    }
+
+    fn exit_xip(&mut self) -> StatusResult {
+        self.xip = false;
+        Ok(())
+    }
+
+    fn enter_xip(&mut self) -> StatusResult {
+        self.xip = true;
+        Ok(())
+    }
+
+    fn exec(&mut self, addr: u32) -> StatusResult {
+        self.vectorize_flash(addr)
+    }
+
+    fn vectorize_flash(&mut self, addr: u32) -> StatusResult {
+        if self.target_id != TargetID::Rp2040 {
+            return Err(PicobootStatus::UnknownCmd);
+        }
+        match self.region(addr, 4) {
+            Some(Region::Sram) | Some(Region::XipSram) => Ok(()),
+            _ => Err(PicobootStatus::InvalidAddress),
+        }
+    }
+
+    fn begin_otp_read(&mut self, _row: u16, _row_count: u16, _ecc: bool) -> StatusResult {
+        match self.target_id {
+            TargetID::Rp2040 => Err(PicobootStatus::UnknownCmd),
+            TargetID::Rp2350 => Ok(()),
+        }
+    }
+
+    fn otp_read(&mut self, row: u16, ecc: bool, buf: &mut [u8]) -> StatusResult {
+        let row = usize::from(row);
+        match ecc {
+            true => {
+                for (chunk, value) in buf.chunks_exact_mut(2).zip(&self.otp[row..]) {
+                    chunk.copy_from_slice(&(*value as u16).to_le_bytes());
+                }
+            }
+            false => {
+                for (chunk, value) in buf.chunks_exact_mut(4).zip(&self.otp[row..]) {
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_otp_write(&mut self, row: u16, row_count: u16, _ecc: bool) -> StatusResult {
+        self.begin_otp_read(row, row_count, false)
+    }
+
+    fn otp_write(&mut self, row: u16, ecc: bool, data: &[u8]) -> StatusResult {
+        let row = usize::from(row);
+        match ecc {
+            true => {
+                for (chunk, value) in data.chunks_exact(2).zip(&mut self.otp[row..]) {
+                    let new = u32::from(u16::from_le_bytes([chunk[0], chunk[1]]));
+                    // ECC rows can only be programmed once
+                    if *value != 0 && *value != new {
+                        return Err(PicobootStatus::UnsupportedModification);
+                    }
+                    *value = new;
+                }
+            }
+            false => {
+                for (chunk, value) in data.chunks_exact(4).zip(&mut self.otp[row..]) {
+                    let new = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                    if new > 0xFFFFFF {
+                        return Err(PicobootStatus::InvalidData);
+                    }
+                    *value |= new;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_info(&mut self, info_type: u8, params: [u32; 3], buf: &mut [u8]) -> StatusResult {
+        if self.target_id != TargetID::Rp2350 {
+            return Err(PicobootStatus::UnknownCmd);
+        }
+
+        // word count, followed by the response
+        let mut words = vec![0];
+        match info_type {
+            GET_INFO_SYS => {
+                // package, device id (low, high), critical, Arm, flash device info
+                let supported = SYS_INFO_CHIP_INFO
+                    | SYS_INFO_CRITICAL
+                    | SYS_INFO_CPU_INFO
+                    | SYS_INFO_FLASH_DEV_INFO;
+                let flags = params[0] & supported;
+                words.push(flags);
+                if flags & SYS_INFO_CHIP_INFO != 0 {
+                    words.extend([0, 0, 0]);
+                }
+                for flag in [
+                    SYS_INFO_CRITICAL,
+                    SYS_INFO_CPU_INFO,
+                    SYS_INFO_FLASH_DEV_INFO,
+                ] {
+                    if flags & flag != 0 {
+                        words.push(0);
+                    }
+                }
+            }
+            GET_INFO_PARTITION_TABLE => {
+                // no partition table, so there is no single partition to return
+                if params[0] & PT_INFO_SINGLE_PARTITION != 0 {
+                    return Err(PicobootStatus::InvalidArg);
+                }
+                words.push(params[0] & 0xFFFF);
+                if params[0] & PT_INFO_PT_INFO != 0 {
+                    // all permissions, accepting every default family
+                    words.extend([0, 0xFC0F_C000]);
+                }
+            }
+            // no partition accepts any family
+            GET_INFO_UF2_TARGET_PARTITION => words.push(u32::MAX),
+            // no UF2 download
+            GET_INFO_UF2_STATUS => words.extend([0, 0, 0, 0]),
+            _ => return Err(PicobootStatus::InvalidArg),
+        }
+        words[0] = words.len() as u32 - 1;
+
+        if words.len() * 4 > buf.len() {
+            return Err(PicobootStatus::BufferTooSmall);
+        }
+        for (chunk, w) in buf.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&w.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    fn cmd_complete(&mut self, _cmd: &PicobootCmd) {
+        if let Some(reboot) = self.pending_reboot.take() {
+            self.reboot = Some(reboot);
+            self.disconnected = true;
+        }
+    }
+
+    fn interface_reset(&mut self) {
+        self.pending_reboot = None;
+    }
+}
+
+/// A simulated PICOBOOT device
+///
+/// The emulator implements [`PicobootTransport`] and answers the PICOBOOT
+/// wire protocol the way an RP2040 or RP2350 in BOOTSEL mode would, so a
+/// [`crate::PicobootConnection`] can be exercised without any hardware:
+///
+/// - ROM is read-only. Flash, SRAM and XIP SRAM are readable and writable.
+/// - Flash erases must be aligned to [`SECTOR_SIZE`], flash writes must be
+///   aligned to [`PAGE_SIZE`]. Erased flash reads as `0xFF`, and writing can
+///   only clear bits, so writing to flash that was not erased leaves the
+///   bitwise AND of the old and new data.
+/// - Rejected commands set the status returned by the GET_COMMAND_STATUS
+///   control request and stall both bulk endpoints until they are cleared.
+/// - After a reboot command is acknowledged, the device disconnects.
+/// - An RP2350 answers GET_INFO as a device without a partition table,
+///   with zeroed chip information, running on Arm.
+/// - The OTP of an RP2350 starts blank. Raw writes can only set bits, ECC
+///   rows can only be programmed once. ECC bits are not emulated, so a raw
+///   read of an ECC row returns just its 16 data bits.
+///
+/// The protocol itself is handled by a [`PicobootResponder`], the same state
+/// machine a custom bootloader would use.
+#[derive(Debug)]
+pub struct PicobootEmulator {
+    transport: LoopbackTransport<Device>,
+}
+impl PicobootEmulator {
+    /// Creates a new emulated device in BOOTSEL mode
+    ///
+    /// The flash is fully erased and [`DEFAULT_FLASH_SIZE`] bytes large. SRAM
+    /// is zero-filled, and so is ROM apart from the bootrom identity at
+    /// [`ROM_IDENTITY_ADDR`] (an RP2040 B2 or an RP2350 A2).
+    pub fn new(target_id: TargetID) -> Self {
+        let (rom_end, sram_end, xip_sram_size, identity) = match target_id {
+            TargetID::Rp2040 => (
+                ROM_END_RP2040,
+                SRAM_END_RP2040,
+                XIP_SRAM_END_RP2040 - XIP_SRAM_START_RP2040,
+                [b'M', b'u', ROM_CHIP_RP2040, 3],
+            ),
+            TargetID::Rp2350 => (
+                ROM_END_RP2350,
+                SRAM_END_RP2350,
+                XIP_SRAM_END_RP2350 - XIP_SRAM_START_RP2350,
+                [b'M', b'u', ROM_CHIP_RP2350, 2],
+            ),
+        };
+        let mut rom = vec![0; (rom_end - ROM_START) as usize];
+        let at = (ROM_IDENTITY_ADDR - ROM_START) as usize;
+        rom[at..at + identity.len()].copy_from_slice(&identity);
+
+        let device = Device {
+            target_id,
+            rom,
+            flash: BTreeMap::new(),
+            flash_size: DEFAULT_FLASH_SIZE,
+            sram: vec![0; (sram_end - SRAM_START_RP2040) as usize],
+            xip_sram: vec![0; xip_sram_size as usize],
+            otp: match target_id {
+                TargetID::Rp2040 => Vec::new(),
+                TargetID::Rp2350 => vec![0; OTP_ROW_COUNT as usize],
+            },
+
+            exclusive: 0,
+            xip: true,
+            reboot: None,
+            pending_reboot: None,
+            disconnected: false,
+        };
+
+        PicobootEmulator {
+            transport: LoopbackTransport::new(PicobootResponder::new(device)),
+        }
+    }
+
+    fn device(&self) -> &Device {
+        self.transport.get_responder().get_handler()
+    }
+
+    fn device_mut(&mut self) -> &mut Device {
+        self.transport.get_responder_mut().get_handler_mut()
+    }
+
+    /// Sets the size of the emulated flash chip.
+    ///
+    /// The size is clamped to the flash address range of the target, and
+    /// rounded down to a multiple of [`SECTOR_SIZE`].
+    pub fn with_flash_size(mut self, size: u32) -> Self {
+        let device = self.device_mut();
+        let max = device.flash_end() - FLASH_START;
+        device.flash_size = size.min(max) / SECTOR_SIZE * SECTOR_SIZE;
+        let end = FLASH_START + device.flash_size;
+        device.flash.retain(|&addr, _| addr < end);
+        self
+    }
+
+    /// Sets the contents of the emulated ROM, starting at [`ROM_START`].
+    ///
+    /// Data past the end of the target's ROM is ignored.
+    pub fn with_rom(mut self, data: &[u8]) -> Self {
+        let rom = &mut self.device_mut().rom;
+        let len = data.len().min(rom.len());
+        rom[..len].copy_from_slice(&data[..len]);
+        self
+    }
+
+    /// Returns the type of the emulated device.
+    pub fn get_device_type(&self) -> TargetID {
+        self.device().target_id
+    }
+
+    /// Returns the size of the emulated flash chip.
+    pub fn get_flash_size(&self) -> u32 {
+        self.device().flash_size
+    }
+
+    /// Reads memory directly, bypassing the PICOBOOT interface.
+    ///
+    /// Returns `None` if the range is not entirely inside a single memory
+    /// region of the device.
+    pub fn read_memory(&self, addr: u32, size: u32) -> Option<Vec<u8>> {
+        self.device().region(addr, size)?;
+        let mut buf = vec![0; size as usize];
+        self.device().read_region(addr, &mut buf);
+        Some(buf)
+    }
+
+    /// Writes memory directly, bypassing the PICOBOOT interface.
+    ///
+    /// Unlike writes over PICOBOOT, this replaces the previous contents of
+    /// flash and may also write to ROM. Returns `false` if the range is not
+    /// entirely inside a single memory region of the device.
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> bool {
+        let device = self.device_mut();
+        let region = match device.region(addr, data.len() as u32) {
+            Some(r) => r,
+            None => return false,
+        };
+
+        match region {
+            Region::Flash => {
+                for (i, b) in data.iter().enumerate() {
+                    *device.flash_byte(addr + i as u32) = *b;
+                }
+            }
+            _ => device
+                .region_slice(region, addr, data.len() as u32)
+                .copy_from_slice(data),
+        }
+
+        true
+    }
+
+    /// Reads OTP rows directly, bypassing the PICOBOOT interface.
+    ///
+    /// Returns `None` if the device has no OTP or the rows are out of range.
+    pub fn read_otp(&self, row: u16, row_count: u16) -> Option<Vec<u32>> {
+        let start = usize::from(row);
+        let rows = self
+            .device()
+            .otp
+            .get(start..start + usize::from(row_count))?;
+        Some(rows.to_vec())
+    }
+
+    /// Writes OTP rows directly, bypassing the PICOBOOT interface.
+    ///
+    /// Unlike writes over PICOBOOT, this replaces the previous rows. Returns
+    /// `false` if the device has no OTP or the rows are out of range.
+    pub fn write_otp(&mut self, row: u16, values: &[u32]) -> bool {
+        let start = usize::from(row);
+        match self.device_mut().otp.get_mut(start..start + values.len()) {
+            Some(rows) => {
+                rows.copy_from_slice(values);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the current exclusive access mode (0, 1 or 2), as set by the
+    /// EXCLUSIVE_ACCESS command.
+    pub fn get_exclusive_access(&self) -> u8 {
+        self.device().exclusive
+    }
+
+    /// Returns `true` if flash is in XIP (execute-in-place) mode.
+    pub fn is_xip(&self) -> bool {
+        self.device().xip
+    }
+
+    /// Returns the last reboot requested by the host, if any.
+    pub fn get_reboot(&self) -> Option<RebootRequest> {
+        self.device().reboot
+    }
+
+    /// Returns `true` if the device has rebooted and left BOOTSEL mode.
+    pub fn is_disconnected(&self) -> bool {
+        self.device().disconnected
+    }
+
+    /// Brings a rebooted device back into BOOTSEL mode.
+    ///
+    /// Memory contents are kept, while all protocol state is reset.
+    pub fn reconnect(&mut self) {
+        let device = self.device_mut();
+        device.disconnected = false;
+        device.exclusive = 0;
+        device.xip = true;
+
+        // an interface reset clears the protocol state and pending reboots
+        let _ = self
+            .transport
+            .get_responder_mut()
+            .control_out(0x41, 0x41, 0, 0, &[]);
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.device().disconnected {
+            return Err(TransportError::Disconnected);
+        }
+        Ok(())
+    }
+}
+impl PicobootTransport for PicobootEmulator {
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.check_connected()?;
+        self.transport.read_bulk(buf, timeout)
+    }
+
+    fn write_bulk(&mut self, buf: &[u8], timeout: Duration) -> Result<usize> {
+        self.check_connected()?;
+        self.transport.write_bulk(buf, timeout)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        self.check_connected()?;
+        self.transport
+            .read_control(request_type, request, value, index, buf, timeout)
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize> {
+        self.check_connected()?;
+        self.transport
+            .write_control(request_type, request, value, index, buf, timeout)
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> Result<()> {
+        self.check_connected()?;
+        self.transport.clear_halt(endpoint)
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.transport.interface_number()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CpuArch, LoadOptions, PicobootConnection, PicobootError, PicobootStatus, ProgressPhase,
+        Reboot2Type, SparseImage, PT_INFO_PARTITION_LOCATION_AND_FLAGS, SRAM_END_RP2040,
+        SRAM_START_RP2040, STACK_POINTER_RP2040, UF2_RP2350_ARM_S_FAMILY_ID, XIP_SRAM_END_RP2040,
+        XIP_SRAM_START_RP2040,
+    };
+
+    fn connect(target_id: TargetID) -> PicobootConnection<PicobootEmulator> {
+        PicobootConnection::from_transport(PicobootEmulator::new(target_id), target_id)
+    }
+
+    #[test]
+    fn flash_erase_write_read_round_trip() {
+        for target_id in [TargetID::Rp2040, TargetID::Rp2350] {
+            let mut conn = connect(target_id);
+            let addr = FLASH_START + SECTOR_SIZE;
+            let page: Vec<u8> = (0..PAGE_SIZE).map(|i| i as u8).collect();
+
+            conn.flash_erase(addr, SECTOR_SIZE).unwrap();
+            assert_eq!(conn.flash_read(addr, PAGE_SIZE).unwrap(), vec![0xFF; 256]);
+
+            conn.flash_write(addr, &page).unwrap();
+            assert_eq!(conn.flash_read(addr, PAGE_SIZE).unwrap(), page);
+            assert_eq!(conn.transport().read_memory(addr, PAGE_SIZE), Some(page));
+        }
+    }
+
+    #[test]
+    fn flash_write_without_erase_clears_bits() {
+        let mut conn = connect(TargetID::Rp2040);
+        conn.flash_write(FLASH_START, &[0x0F; 256]).unwrap();
+        conn.flash_write(FLASH_START, &[0x3C; 256]).unwrap();
+        assert_eq!(conn.flash_read(FLASH_START, 4).unwrap(), vec![0x0C; 4]);
+    }
+
+    #[test]
+    fn exclusive_access_and_xip() {
+        let mut conn = connect(TargetID::Rp2040);
+        assert_eq!(conn.transport().get_exclusive_access(), 0);
+        assert!(conn.transport().is_xip());
+
+        conn.access_exclusive_eject().unwrap();
+        conn.exit_xip().unwrap();
+        assert_eq!(conn.transport().get_exclusive_access(), 2);
+        assert!(!conn.transport().is_xip());
+
+        conn.access_not_exclusive().unwrap();
+        conn.enter_xip().unwrap();
+        assert_eq!(conn.transport().get_exclusive_access(), 0);
+        assert!(conn.transport().is_xip());
+    }
+
+    #[test]
+    fn rejected_cmd_reports_status() {
+        let mut conn = connect(TargetID::Rp2040);
+
+        // misaligned erase, only checked by the device
+        let err = conn
+            .cmd(
+                PicobootCmd::flash_erase(FLASH_START + PAGE_SIZE, SECTOR_SIZE),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(err.status(), Some(PicobootStatus::BadAlignment));
+
+        // endpoints stay halted until the interface is reset
+        assert!(conn
+            .flash_read(FLASH_START, 4)
+            .unwrap_err()
+            .status()
+            .is_none());
+        conn.reset_interface().unwrap();
+
+        // writes to ROM are rejected
+        let err = conn.flash_write(0, &[0; 256]).unwrap_err();
+        assert_eq!(err.status(), Some(PicobootStatus::InvalidAddress));
+
+        conn.reset_interface().unwrap();
+        assert_eq!(conn.flash_read(FLASH_START, 4).unwrap(), vec![0xFF; 4]);
+    }
+
+    #[test]
+    fn load_cancelled_before_reboot() {
+        let mut conn = connect(TargetID::Rp2040);
+        let cancel = conn.get_cancel_token();
+        conn.set_observer(move |phase, done, total| {
+            if phase == ProgressPhase::Verify && done == total {
+                cancel.cancel();
+            }
+        });
+
+        let image = SparseImage::new().with_data(FLASH_START, &[0x5A; 256]);
+        let options = LoadOptions::new().with_reboot(100);
+        assert!(matches!(
+            conn.load(&image, options),
+            Err(PicobootError::CmdCancelled)
+        ));
+
+        let emu = conn.transport();
+        assert_eq!(emu.read_memory(FLASH_START, 256), Some(vec![0x5A; 256]));
+        assert_eq!(emu.get_exclusive_access(), 0);
+        assert!(emu.is_xip());
+        assert_eq!(emu.get_reboot(), None);
+    }
+
+    #[test]
+    fn load_ram_only_reboots_into_ram() {
+        let mut conn = connect(TargetID::Rp2040);
+        let image = SparseImage::new()
+            .with_data(SRAM_START_RP2040 + 0x100, &[0x11; 64])
+            .with_data(XIP_SRAM_START_RP2040, &[0x22; 16]);
+        let options = LoadOptions::new().with_reboot(10);
+        conn.load(&image, options).unwrap();
+        let emu = conn.transport();
+        assert_eq!(
+            emu.read_memory(SRAM_START_RP2040 + 0x100, 64),
+            Some(vec![0x11; 64])
+        );
+        assert_eq!(
+            emu.get_reboot(),
+            Some(RebootRequest::Reboot {
+                pc: XIP_SRAM_START_RP2040,
+                sp: STACK_POINTER_RP2040,
+                delay: 10
+            })
+        );
+
+        // the RAM image spans the data in the memory of its lowest address
+        let ram_image = |image: &SparseImage| {
+            let mut conn = connect(TargetID::Rp2350);
+            conn.load(image, options).unwrap();
+            let reboot = conn.transport().get_reboot();
+            let (flags, p0, p1) = match reboot {
+                Some(RebootRequest::Reboot2 { flags, p0, p1, .. }) => (flags, p0, p1),
+                _ => panic!("unexpected reboot {:?}", reboot),
+            };
+            Reboot2Options::from_args(flags, p0, p1)
+                .unwrap()
+                .get_reboot_type()
+        };
+
+        let image = SparseImage::new()
+            .with_data(SRAM_START_RP2040 + 0x100, &[0x11; 64])
+            .with_data(SRAM_START_RP2040 + 0x400, &[0x33; 32]);
+        assert_eq!(
+            ram_image(&image),
+            Reboot2Type::RamImage {
+                base: SRAM_START_RP2040 + 0x100,
+                size: 0x320
+            }
+        );
+
+        let image = image.with_data(XIP_SRAM_START_RP2350 + 0x10, &[0x22; 16]);
+        assert_eq!(
+            ram_image(&image),
+            Reboot2Type::RamImage {
+                base: XIP_SRAM_START_RP2350 + 0x10,
+                size: 16
+            }
+        );
+    }
+
+    #[test]
+    fn identify_corrects_target() {
+        let mut conn = PicobootConnection::from_transport(
+            PicobootEmulator::new(TargetID::Rp2350),
+            TargetID::Rp2040,
+        );
+        let info = conn.identify().unwrap();
+        assert_eq!(info.get_target_id(), TargetID::Rp2350);
+        assert_eq!(conn.get_device_type(), TargetID::Rp2350);
+    }
+
+    #[test]
+    fn reboot2_encodes_options() {
+        let bootsel = Reboot2Type::Bootsel {
+            disable_msd: true,
+            disable_picoboot: false,
+            led_gpio: Some(25),
+            led_active_low: true,
+        };
+        let cases = [
+            (Reboot2Options::new(Reboot2Type::Normal), (0x0, 0, 0)),
+            (
+                Reboot2Options::new(bootsel).with_arch(CpuArch::RiscV),
+                (0x22, 0x31, 25),
+            ),
+            (
+                Reboot2Options::new(Reboot2Type::Bootsel {
+                    disable_msd: false,
+                    disable_picoboot: true,
+                    led_gpio: None,
+                    led_active_low: false,
+                }),
+                (0x2, 0x02, 0),
+            ),
+            (
+                Reboot2Options::new(Reboot2Type::RamImage {
+                    base: 0x2000_0000,
+                    size: 0x1000,
+                })
+                .with_no_return_on_success(true),
+                (0x103, 0x2000_0000, 0x1000),
+            ),
+            (
+                Reboot2Options::new(Reboot2Type::FlashUpdate {
+                    buffer: 0x1000_2000,
+                })
+                .with_arch(CpuArch::Arm),
+                (0x14, 0x1000_2000, 0),
+            ),
+            (
+                Reboot2Options::new(Reboot2Type::PcSp {
+                    pc: 0x2000_0101,
+                    sp: 0x2008_2000,
+                }),
+                (0xd, 0x2000_0101, 0x2008_2000),
+            ),
+        ];
+
+        for (options, (flags, p0, p1)) in cases {
+            assert_eq!(options.to_args(), (flags, p0, p1), "{}", options);
+            assert_eq!(Reboot2Options::from_args(flags, p0, p1), Some(options));
+
+            let mut conn = connect(TargetID::Rp2350);
+            conn.reboot2(options, 10).unwrap();
+            assert_eq!(
+                conn.transport().get_reboot(),
+                Some(RebootRequest::Reboot2 {
+                    flags,
+                    delay: 10,
+                    p0,
+                    p1
+                })
+            );
+        }
+
+        // unknown boot type, and both architectures at once
+        assert_eq!(Reboot2Options::from_args(0x1, 0, 0), None);
+        assert_eq!(Reboot2Options::from_args(0x30, 0, 0), None);
+
+        let mut conn = connect(TargetID::Rp2040);
+        assert!(matches!(
+            conn.reboot2_normal(10),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+    }
+
+    #[test]
+    fn exec_and_vectorize_check_addresses() {
+        let mut conn = connect(TargetID::Rp2040);
+        let sram = SRAM_START_RP2040;
+        let xip_sram = XIP_SRAM_START_RP2040;
+
+        // the Thumb bit may be set
+        conn.exec(sram | 1).unwrap();
+        conn.exec(XIP_SRAM_END_RP2040 - 4).unwrap();
+        conn.vectorize_flash(sram + 0x100).unwrap();
+        conn.vectorize_flash(xip_sram).unwrap();
+
+        for addr in [FLASH_START, SRAM_END_RP2040, XIP_SRAM_END_RP2040, 0] {
+            assert!(matches!(
+                conn.exec(addr),
+                Err(PicobootError::ExecInvalidAddr)
+            ));
+            assert!(matches!(
+                conn.vectorize_flash(addr),
+                Err(PicobootError::VectorizeInvalidAddr)
+            ));
+        }
+        // the table must be word aligned
+        assert!(matches!(
+            conn.vectorize_flash(sram + 2),
+            Err(PicobootError::VectorizeInvalidAddr)
+        ));
+
+        let mut conn = connect(TargetID::Rp2350);
+        assert!(matches!(
+            conn.exec(SRAM_START_RP2040 | 1),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+        assert!(matches!(
+            conn.vectorize_flash(SRAM_START_RP2040),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+    }
+
+    #[test]
+    fn otp_write_read_round_trip() {
+        let mut conn = connect(TargetID::Rp2350);
+
+        conn.otp_write_raw(0x100, &[0x12_3456, 0xFF_FFFF]).unwrap();
+        assert_eq!(
+            conn.otp_read_raw(0x100, 2).unwrap(),
+            vec![0x12_3456, 0xFF_FFFF]
+        );
+
+        conn.otp_write_ecc(0x200, &[0xBEEF, 0x1234, 0]).unwrap();
+        assert_eq!(
+            conn.otp_read_ecc(0x200, 3).unwrap(),
+            vec![0xBEEF, 0x1234, 0]
+        );
+        assert_eq!(
+            conn.transport().read_otp(0x200, 2),
+            Some(vec![0xBEEF, 0x1234])
+        );
+
+        // the last row can be read and written
+        conn.otp_write_raw(OTP_ROW_COUNT - 1, &[1]).unwrap();
+        assert_eq!(conn.otp_read_raw(OTP_ROW_COUNT - 1, 1).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn otp_checks_rows_and_values() {
+        let mut conn = connect(TargetID::Rp2350);
+
+        assert!(matches!(
+            conn.otp_write_raw(0, &[0x100_0000]),
+            Err(PicobootError::OtpInvalidValue)
+        ));
+        assert!(matches!(
+            conn.otp_read_raw(OTP_ROW_COUNT, 1),
+            Err(PicobootError::OtpInvalidRow)
+        ));
+        assert!(matches!(
+            conn.otp_write_ecc(OTP_ROW_COUNT, &[0]),
+            Err(PicobootError::OtpInvalidRow)
+        ));
+        assert!(matches!(
+            conn.otp_read_ecc(OTP_ROW_COUNT - 1, 2),
+            Err(PicobootError::OtpInvalidRowCount)
+        ));
+        assert!(matches!(
+            conn.otp_write_raw(OTP_ROW_COUNT - 1, &[0, 0]),
+            Err(PicobootError::OtpInvalidRowCount)
+        ));
+        assert!(matches!(
+            conn.otp_read_raw(0, 0),
+            Err(PicobootError::OtpInvalidRowCount)
+        ));
+        // nothing was sent to the device
+        assert_eq!(conn.transport().read_otp(0, 1), Some(vec![0]));
+
+        let mut conn = connect(TargetID::Rp2040);
+        assert!(matches!(
+            conn.otp_read_raw(0, 1),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+        assert!(matches!(
+            conn.otp_read_ecc(0, 1),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+        assert!(matches!(
+            conn.otp_write_raw(0, &[0]),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+        assert!(matches!(
+            conn.otp_write_ecc(0, &[0]),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+    }
+
+    #[test]
+    fn get_info_queries() {
+        let mut conn = connect(TargetID::Rp2350);
+
+        let info = conn
+            .get_partition_table_info(PT_INFO_PT_INFO | PT_INFO_PARTITION_LOCATION_AND_FLAGS)
+            .unwrap();
+        assert_eq!(info.get_partition_count(), Some(0));
+        assert_eq!(info.has_partition_table(), Some(false));
+        assert!(info.get_partitions().is_empty());
+
+        let target = conn.get_uf2_target_partition(UF2_RP2350_ARM_S_FAMILY_ID);
+        assert_eq!(target.unwrap(), None);
+
+        let status = conn.get_uf2_status().unwrap();
+        assert_eq!(status.get_status(), 0);
+        assert_eq!(status.get_blocks_total(), 0);
+
+        let mut conn = connect(TargetID::Rp2040);
+        assert!(matches!(
+            conn.get_uf2_status(),
+            Err(PicobootError::CmdNotAllowedForTarget)
+        ));
+    }
+
+    #[test]
+    fn reboot_disconnects() {
+        let mut conn = connect(TargetID::Rp2040);
+        conn.reboot(0, STACK_POINTER_RP2040, 100).unwrap();
+        assert_eq!(
+            conn.transport().get_reboot(),
+            Some(RebootRequest::Reboot {
+                pc: 0,
+                sp: STACK_POINTER_RP2040,
+                delay: 100
+            })
+        );
+        assert!(conn.transport().is_disconnected());
+        assert!(conn.flash_read(FLASH_START, 4).is_err());
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{cmd::PicobootError, image::SparseImage};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+const HEX_RECORD_DATA: u8 = 0x00;
+const HEX_RECORD_END_OF_FILE: u8 = 0x01;
+const HEX_RECORD_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const HEX_RECORD_START_SEGMENT_ADDRESS: u8 = 0x03;
+const HEX_RECORD_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const HEX_RECORD_START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Number of data bytes written per data record
+const HEX_RECORD_SIZE: usize = 16;
+
+/// Decodes pairs of ASCII hex digits into bytes, also used for S-records.
+pub(crate) fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    let s = s.as_bytes();
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    s.chunks_exact(2)
+        .map(|pair| Some(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Parsed Intel HEX file.
+///
+/// Supports data records with 16-bit offsets as well as extended segment and
+/// extended linear address records, so images anywhere in the 32-bit address
+/// space can be read and written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HexFile {
+    image: SparseImage,
+    start_addr: Option<u32>,
+}
+impl HexFile {
+    /// Parses an Intel HEX file, checking the checksum of every record.
+    ///
+    /// Blank lines are ignored. Start segment addresses are converted to
+    /// linear addresses. After an extended segment address record, data
+    /// records wrap around at the end of their 64 KiB segment.
+    ///
+    /// # Errors:
+    /// - [`Error::HexFormatInvalid`]
+    /// - [`Error::HexChecksumMismatch`]
+    /// - [`Error::HexEndMissing`]
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut image = SparseImage::new();
+        let mut start_addr = None;
+        let mut base = 0u32;
+        let mut segmented = false;
+        let mut ended = false;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if ended {
+                return Err(Error::HexFormatInvalid(line_no));
+            }
+
+            let bytes = line
+                .strip_prefix(':')
+                .and_then(decode_hex)
+                .filter(|b| b.len() >= 5 && b.len() == 5 + b[0] as usize)
+                .ok_or(Error::HexFormatInvalid(line_no))?;
+            if checksum(&bytes) != 0 {
+                return Err(Error::HexChecksumMismatch(line_no));
+            }
+
+            let offset = u16::from_be_bytes([bytes[1], bytes[2]]);
+            let data = &bytes[4..bytes.len() - 1];
+            match (bytes[3], data.len()) {
+                (HEX_RECORD_DATA, _) => {
+                    // segment offsets wrap, linear addresses do not
+                    let split = match segmented {
+                        true => data.len().min(0x10000 - usize::from(offset)),
+                        false => data.len(),
+                    };
+                    image.write(base.wrapping_add(offset.into()), &data[..split]);
+                    if split < data.len() {
+                        image.write(base, &data[split..]);
+                    }
+                }
+                (HEX_RECORD_END_OF_FILE, 0) => ended = true,
+                (HEX_RECORD_EXTENDED_SEGMENT_ADDRESS, 2) => {
+                    base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 4;
+                    segmented = true;
+                }
+                (HEX_RECORD_EXTENDED_LINEAR_ADDRESS, 2) => {
+                    base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 16;
+                    segmented = false;
+                }
+                (HEX_RECORD_START_SEGMENT_ADDRESS, 4) => {
+                    let cs = u16::from_be_bytes([data[0], data[1]]);
+                    let ip = u16::from_be_bytes([data[2], data[3]]);
+                    start_addr = Some((u32::from(cs) << 4) + u32::from(ip));
+                }
+                (HEX_RECORD_START_LINEAR_ADDRESS, 4) => {
+                    start_addr = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+                }
+                _ => return Err(Error::HexFormatInvalid(line_no)),
+            }
+        }
+
+        if !ended {
+            return Err(Error::HexEndMissing);
+        }
+        Ok(HexFile { image, start_addr })
+    }
+
+    /// Creates an Intel HEX file from an image.
+    pub fn from_image(image: &SparseImage) -> Self {
+        HexFile {
+            image: image.clone(),
+            start_addr: None,
+        }
+    }
+
+    /// Sets the start (entry point) address, written as a start linear
+    /// address record.
+    pub fn with_start_addr(mut self, addr: u32) -> Self {
+        self.start_addr = Some(addr);
+        self
+    }
+
+    /// Serializes the file, with 16 data bytes per record and extended linear
+    /// address records where needed.
+    pub fn ser(&self) -> String {
+        let mut out = String::new();
+        let mut upper = 0u32;
+        for (addr, seg) in self.image.get_segments() {
+            let mut pos = 0;
+            while pos < seg.len() {
+                let at = addr + pos as u32;
+                if at >> 16 != upper {
+                    upper = at >> 16;
+                    let ela = (upper as u16).to_be_bytes();
+                    write_record(&mut out, HEX_RECORD_EXTENDED_LINEAR_ADDRESS, 0, &ela);
+                }
+
+                // records may not cross a 64 KiB boundary
+                let n = HEX_RECORD_SIZE
+                    .min(seg.len() - pos)
+                    .min(0x10000 - (at & 0xFFFF) as usize);
+                write_record(&mut out, HEX_RECORD_DATA, at as u16, &seg[pos..pos + n]);
+                pos += n;
+            }
+        }
+        if let Some(start_addr) = self.start_addr {
+            let sla = start_addr.to_be_bytes();
+            write_record(&mut out, HEX_RECORD_START_LINEAR_ADDRESS, 0, &sla);
+        }
+        write_record(&mut out, HEX_RECORD_END_OF_FILE, 0, &[]);
+        out
+    }
+
+    pub fn get_image(&self) -> &SparseImage {
+        &self.image
+    }
+
+    pub fn into_image(self) -> SparseImage {
+        self.image
+    }
+
+    /// Returns the start (entry point) address, if the file has one.
+    pub fn get_start_addr(&self) -> Option<u32> {
+        self.start_addr
+    }
+}
+
+fn write_record(out: &mut String, kind: u8, offset: u16, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    bytes.push(checksum(&bytes).wrapping_neg());
+
+    out.push(':');
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_only_hex_digits() {
+        assert_eq!(decode_hex("00aF9e"), Some(vec![0x00, 0xAF, 0x9E]));
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(decode_hex("abc"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..40u8).collect();
+        let image = SparseImage::new()
+            .with_data(0x1000_0000, &data)
+            .with_data(0x2000_0000, &[0xAB; 3]);
+        let text = HexFile::from_image(&image)
+            .with_start_addr(0x1000_00ED)
+            .ser();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                ":020000041000EA",
+                ":10000000000102030405060708090A0B0C0D0E0F78",
+                ":10001000101112131415161718191A1B1C1D1E1F68",
+                ":080020002021222324252627BC",
+                ":020000042000DA",
+                ":03000000ABABABFC",
+                ":04000005100000EDFA",
+                ":00000001FF",
+            ]
+        );
+
+        let file = HexFile::parse(&text).unwrap();
+        assert_eq!(file.get_image(), &image);
+        assert_eq!(file.get_start_addr(), Some(0x1000_00ED));
+    }
+
+    #[test]
+    fn splits_records_at_64k() {
+        let image = SparseImage::new().with_data(0x1000_FFF8, &[0x11; 16]);
+        let text = HexFile::from_image(&image).ser();
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                ":020000041000EA",
+                ":08FFF800111111111111111179",
+                ":020000041001E9",
+                ":08000000111111111111111170",
+                ":00000001FF",
+            ]
+        );
+        assert_eq!(HexFile::parse(&text).unwrap().into_image(), image);
+    }
+
+    #[test]
+    fn wraps_segment_offsets() {
+        // a record crossing the end of segment 0x1000 wraps to its start
+        let text = ":020000021000EC\n:04FFFE0001020304F5\n:00000001FF\n";
+        let image = HexFile::parse(text).unwrap().into_image();
+        assert_eq!(image.read(0x1FFFE, 2), Some(&[1, 2][..]));
+        assert_eq!(image.read(0x10000, 2), Some(&[3, 4][..]));
+        assert_eq!(image.len(), 4);
+
+        // linear addresses continue into the next 64 KiB
+        let text = ":020000040001F9\n:04FFFE0001020304F5\n:00000001FF\n";
+        let image = HexFile::parse(text).unwrap().into_image();
+        assert_eq!(image.read(0x1FFFE, 4), Some(&[1, 2, 3, 4][..]));
+    }
+
+    #[test]
+    fn start_segment_address() {
+        let text = ":0400000310000100E8\n:00000001FF\n";
+        let file = HexFile::parse(text).unwrap();
+        assert_eq!(file.get_start_addr(), Some(0x10100));
+        assert!(file.get_image().is_empty());
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let text = ":0400000001020304F3\n:00000001FF\n";
+        assert!(matches!(
+            HexFile::parse(text),
+            Err(Error::HexChecksumMismatch(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        for text in [
+            // truncated record
+            ":04000000010203\n:00000001FF\n",
+            // sign in place of a digit
+            ":+4000000010203040F2\n:00000001FF\n",
+            // missing start code
+            "0400000001020304F2\n:00000001FF\n",
+            // extended address with the wrong length
+            ":0100000400FB\n:00000001FF\n",
+            // unknown record type
+            ":0000000AF6\n:00000001FF\n",
+        ] {
+            assert!(matches!(
+                HexFile::parse(text),
+                Err(Error::HexFormatInvalid(1))
+            ));
+        }
+
+        // records after the end of file
+        let text = ":00000001FF\n:0400000001020304F2\n";
+        assert!(matches!(
+            HexFile::parse(text),
+            Err(Error::HexFormatInvalid(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_end() {
+        assert!(matches!(
+            HexFile::parse(":0400000001020304F2\n"),
+            Err(Error::HexEndMissing)
+        ));
+        assert!(matches!(HexFile::parse(""), Err(Error::HexEndMissing)));
+    }
+}
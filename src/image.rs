@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+
+/// Memory image made of non-contiguous segments of data.
+///
+/// Segments are keyed by their start address. Writes that overlap or touch
+/// existing segments are merged into them, so the segments of an image never
+/// overlap and are never adjacent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SparseImage {
+    segments: BTreeMap<u32, Vec<u8>>,
+}
+impl SparseImage {
+    /// Creates an empty image.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes data at an address, replacing any data already there.
+    ///
+    /// Data that would extend past the end of the 32-bit address space is
+    /// dropped.
+    pub fn write(&mut self, addr: u32, data: &[u8]) {
+        let len = (data.len() as u64).min(0x1_0000_0000 - addr as u64);
+        let data = &data[..len as usize];
+        if data.is_empty() {
+            return;
+        }
+        let end = addr as u64 + data.len() as u64;
+
+        // collect every segment overlapping or touching the new data
+        let touching: Vec<u32> = self
+            .segments
+            .range(..=end.min(u32::MAX as u64) as u32)
+            .rev()
+            .take_while(|(&start, seg)| start as u64 + seg.len() as u64 >= addr as u64)
+            .map(|(&start, _)| start)
+            .collect();
+
+        let mut start = addr;
+        let mut merged_end = end;
+        for &s in &touching {
+            let seg = &self.segments[&s];
+            start = start.min(s);
+            merged_end = merged_end.max(s as u64 + seg.len() as u64);
+        }
+
+        let mut merged = vec![0; (merged_end - start as u64) as usize];
+        for s in touching {
+            let seg = self.segments.remove(&s).unwrap();
+            let offset = (s - start) as usize;
+            merged[offset..offset + seg.len()].copy_from_slice(&seg);
+        }
+        let offset = (addr - start) as usize;
+        merged[offset..offset + data.len()].copy_from_slice(data);
+        self.segments.insert(start, merged);
+    }
+
+    /// Writes data at an address, replacing any data already there.
+    pub fn with_data(mut self, addr: u32, data: &[u8]) -> Self {
+        self.write(addr, data);
+        self
+    }
+
+    /// Reads a range of the image, or returns `None` if any byte of it has
+    /// no data.
+    pub fn read(&self, addr: u32, size: u32) -> Option<&[u8]> {
+        let (&start, seg) = self.segments.range(..=addr).next_back()?;
+        let offset = (addr - start) as usize;
+        seg.get(offset..offset.checked_add(size as usize)?)
+    }
+
+    /// Returns the segments of the image in address order.
+    pub fn get_segments(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.segments
+            .iter()
+            .map(|(&addr, seg)| (addr, seg.as_slice()))
+    }
+
+    /// Returns the address of the first byte of data, if any.
+    pub fn get_start(&self) -> Option<u32> {
+        self.segments.keys().next().copied()
+    }
+
+    /// Returns the address just past the last byte of data, if any.
+    pub fn get_end(&self) -> Option<u64> {
+        self.segments
+            .iter()
+            .next_back()
+            .map(|(&addr, seg)| addr as u64 + seg.len() as u64)
+    }
+
+    /// Returns the number of bytes of data in the image.
+    pub fn len(&self) -> usize {
+        self.segments.values().map(Vec::len).sum()
+    }
+
+    /// Returns `true` if the image has no data.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Splits the image into aligned pages of `page_size` bytes, in address
+    /// order.
+    ///
+    /// Only pages holding data are returned. Bytes of a page that have no
+    /// data are set to `fill`.
+    ///
+    /// # Panics
+    /// Panics if `page_size` is zero.
+    pub fn get_pages(&self, page_size: u32, fill: u8) -> Vec<(u32, Vec<u8>)> {
+        assert!(page_size > 0, "page size must not be zero");
+        let mut pages: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        for (addr, seg) in self.get_segments() {
+            let mut pos = 0;
+            while pos < seg.len() {
+                let at = addr + pos as u32;
+                let page_addr = at - at % page_size;
+                let offset = (at - page_addr) as usize;
+                let n = (page_size as usize - offset).min(seg.len() - pos);
+                let page = pages
+                    .entry(page_addr)
+                    .or_insert_with(|| vec![fill; page_size as usize]);
+                page[offset..offset + n].copy_from_slice(&seg[pos..pos + n]);
+                pos += n;
+            }
+        }
+        pages.into_iter().collect()
+    }
+}
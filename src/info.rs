@@ -0,0 +1,844 @@
+use crate::{
+    cmd::{PicobootError, TargetID},
+    PT_INFO_PARTITION_FAMILY_IDS, PT_INFO_PARTITION_ID, PT_INFO_PARTITION_LOCATION_AND_FLAGS,
+    PT_INFO_PARTITION_NAME, PT_INFO_PT_INFO, ROM_CHIP_RP2040, ROM_CHIP_RP2350, SECTOR_SIZE,
+    SYS_INFO_BOOT_INFO, SYS_INFO_BOOT_RANDOM, SYS_INFO_CHIP_INFO, SYS_INFO_CPU_INFO,
+    SYS_INFO_CRITICAL, SYS_INFO_FLASH_DEV_INFO, UF2_ABSOLUTE_FAMILY_ID, UF2_DATA_FAMILY_ID,
+    UF2_RP2040_FAMILY_ID, UF2_RP2350_ARM_NS_FAMILY_ID, UF2_RP2350_ARM_S_FAMILY_ID,
+    UF2_RP2350_RISCV_FAMILY_ID,
+};
+
+pub use crate::protocol::CpuArch;
+
+use std::fmt;
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+// partition flags, see the RP2350 datasheet (section 5.9.4)
+const PARTITION_HAS_ID: u32 = 1 << 0;
+const PARTITION_NOT_BOOTABLE_ARM: u32 = 1 << 9;
+const PARTITION_NOT_BOOTABLE_RISCV: u32 = 1 << 10;
+const PARTITION_HAS_NAME: u32 = 1 << 12;
+const PARTITION_UF2_DOWNLOAD_NO_REBOOT: u32 = 1 << 13;
+
+/// Families accepted by a partition, in order of their flag bits (14 to 19)
+const PARTITION_DEFAULT_FAMILIES: [u32; 6] = [
+    UF2_RP2040_FAMILY_ID,
+    UF2_ABSOLUTE_FAMILY_ID,
+    UF2_RP2350_ARM_S_FAMILY_ID,
+    UF2_RP2350_RISCV_FAMILY_ID,
+    UF2_RP2350_ARM_NS_FAMILY_ID,
+    UF2_DATA_FAMILY_ID,
+];
+
+/// Words of a GET_INFO response, consumed in order.
+struct Words {
+    words: Vec<u32>,
+    pos: usize,
+}
+impl Words {
+    /// Splits a response into words, keeping the number of words given by
+    /// the leading word count.
+    fn new(data: &[u8]) -> Result<Self> {
+        let mut words: Vec<u32> = data
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let count = *words.first().ok_or(Error::InfoResponseInvalid)? as usize;
+        if count >= words.len() {
+            return Err(Error::InfoResponseInvalid);
+        }
+        words.truncate(1 + count);
+        Ok(Words { words, pos: 1 })
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u32]> {
+        let words = self
+            .words
+            .get(self.pos..self.pos + n)
+            .ok_or(Error::InfoResponseInvalid)?;
+        self.pos += n;
+        Ok(words)
+    }
+
+    fn next(&mut self) -> Result<u32> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u32> {
+        self.words
+            .get(self.pos)
+            .copied()
+            .ok_or(Error::InfoResponseInvalid)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.words.len()
+    }
+}
+
+/// System information returned by GET_INFO (INFO_SYS)
+///
+/// Each item is `None` unless its `SYS_INFO_*` flag was requested and the
+/// device returned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SysInfo {
+    flags: u32,
+    package_sel: Option<u32>,
+    device_id: Option<u64>,
+    critical: Option<u32>,
+    cpu_arch: Option<CpuArch>,
+    flash_dev_info: Option<u32>,
+    boot_random: Option<[u32; 4]>,
+    boot_info: Option<[u32; 4]>,
+}
+impl SysInfo {
+    /// Parses the response of a GET_INFO (INFO_SYS) command.
+    ///
+    /// The response starts with the number of words that follow, then the
+    /// flags of the returned information and the information itself, in
+    /// order of the flags.
+    ///
+    /// # Errors
+    /// - [`Error::InfoResponseInvalid`]
+    pub fn from_response(data: &[u8]) -> Result<Self> {
+        let mut words = Words::new(data)?;
+        let flags = words.next()?;
+        let known = SYS_INFO_CHIP_INFO
+            | SYS_INFO_CRITICAL
+            | SYS_INFO_CPU_INFO
+            | SYS_INFO_FLASH_DEV_INFO
+            | SYS_INFO_BOOT_RANDOM
+            | SYS_INFO_BOOT_INFO;
+        if flags & !known != 0 {
+            return Err(Error::InfoResponseInvalid);
+        }
+
+        let mut info = SysInfo {
+            flags,
+            package_sel: None,
+            device_id: None,
+            critical: None,
+            cpu_arch: None,
+            flash_dev_info: None,
+            boot_random: None,
+            boot_info: None,
+        };
+        if flags & SYS_INFO_CHIP_INFO != 0 {
+            let w = words.take(3)?;
+            info.package_sel = Some(w[0]);
+            info.device_id = Some(u64::from(w[1]) | u64::from(w[2]) << 32);
+        }
+        if flags & SYS_INFO_CRITICAL != 0 {
+            info.critical = Some(words.next()?);
+        }
+        if flags & SYS_INFO_CPU_INFO != 0 {
+            info.cpu_arch = match words.next()? {
+                0 => Some(CpuArch::Arm),
+                1 => Some(CpuArch::RiscV),
+                _ => return Err(Error::InfoResponseInvalid),
+            };
+        }
+        if flags & SYS_INFO_FLASH_DEV_INFO != 0 {
+            info.flash_dev_info = Some(words.next()?);
+        }
+        if flags & SYS_INFO_BOOT_RANDOM != 0 {
+            let w = words.take(4)?;
+            info.boot_random = Some([w[0], w[1], w[2], w[3]]);
+        }
+        if flags & SYS_INFO_BOOT_INFO != 0 {
+            let w = words.take(4)?;
+            info.boot_info = Some([w[0], w[1], w[2], w[3]]);
+        }
+
+        Ok(info)
+    }
+
+    /// Returns the `SYS_INFO_*` flags of the information returned.
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns the value of the SYSINFO PACKAGE_SEL register.
+    pub fn get_package_sel(&self) -> Option<u32> {
+        self.package_sel
+    }
+
+    /// Returns the unique device id.
+    pub fn get_device_id(&self) -> Option<u64> {
+        self.device_id
+    }
+
+    /// Returns the value of the OTP CRITICAL register.
+    pub fn get_critical(&self) -> Option<u32> {
+        self.critical
+    }
+
+    /// Returns the architecture the bootrom is running on.
+    pub fn get_cpu_arch(&self) -> Option<CpuArch> {
+        self.cpu_arch
+    }
+
+    /// Returns the FLASH_DEVINFO value describing the attached flash
+    /// devices.
+    pub fn get_flash_dev_info(&self) -> Option<u32> {
+        self.flash_dev_info
+    }
+
+    /// Returns the random number generated at boot.
+    pub fn get_boot_random(&self) -> Option<[u32; 4]> {
+        self.boot_random
+    }
+
+    /// Returns the raw boot diagnostics: the boot type and partition, the
+    /// diagnostics of the boot, and the two reboot parameters.
+    pub fn get_boot_info(&self) -> Option<[u32; 4]> {
+        self.boot_info
+    }
+}
+
+/// A partition of the RP2350 partition table
+///
+/// Location and flags are always present. The id, extra family ids and name
+/// are only present if they were requested and the partition has them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    index: u8,
+    location: u32,
+    flags: u32,
+    id: Option<u64>,
+    extra_family_ids: Vec<u32>,
+    name: Option<String>,
+}
+impl PartitionInfo {
+    /// Returns the index of the partition in the partition table.
+    pub fn get_index(&self) -> u8 {
+        self.index
+    }
+
+    /// Returns the first flash sector of the partition.
+    pub fn get_first_sector(&self) -> u32 {
+        self.location & 0x1FFF
+    }
+
+    /// Returns the last flash sector of the partition.
+    pub fn get_last_sector(&self) -> u32 {
+        (self.location >> 13) & 0x1FFF
+    }
+
+    /// Returns the offset of the partition from the start of flash, in bytes.
+    pub fn get_offset(&self) -> u32 {
+        self.get_first_sector() * SECTOR_SIZE
+    }
+
+    /// Returns the size of the partition, in bytes.
+    pub fn get_size(&self) -> u32 {
+        (self.get_last_sector() + 1).saturating_sub(self.get_first_sector()) * SECTOR_SIZE
+    }
+
+    /// Returns the access permissions of the partition, from bit 0 to 5:
+    /// secure read, secure write, non-secure read, non-secure write, BOOTSEL
+    /// read and BOOTSEL write.
+    pub fn get_permissions(&self) -> u8 {
+        (self.location >> 26) as u8
+    }
+
+    /// Returns the raw permissions and location word.
+    pub fn get_location(&self) -> u32 {
+        self.location
+    }
+
+    /// Returns the raw permissions and flags word.
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns `true` if the partition may be booted on Arm.
+    pub fn is_bootable_arm(&self) -> bool {
+        self.flags & PARTITION_NOT_BOOTABLE_ARM == 0
+    }
+
+    /// Returns `true` if the partition may be booted on RISC-V.
+    pub fn is_bootable_riscv(&self) -> bool {
+        self.flags & PARTITION_NOT_BOOTABLE_RISCV == 0
+    }
+
+    /// Returns `true` if the device does not reboot after a UF2 is
+    /// downloaded to the partition.
+    pub fn is_uf2_download_no_reboot(&self) -> bool {
+        self.flags & PARTITION_UF2_DOWNLOAD_NO_REBOOT != 0
+    }
+
+    /// Returns the UF2 family ids the partition accepts, default families
+    /// first.
+    pub fn get_family_ids(&self) -> Vec<u32> {
+        PARTITION_DEFAULT_FAMILIES
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.flags & (1 << (14 + i)) != 0)
+            .map(|(_, id)| *id)
+            .chain(self.extra_family_ids.iter().copied())
+            .collect()
+    }
+
+    /// Returns the extra UF2 family ids the partition accepts, if requested.
+    pub fn get_extra_family_ids(&self) -> &[u32] {
+        &self.extra_family_ids
+    }
+
+    /// Returns the id of the partition, if requested and present.
+    pub fn get_id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Returns the name of the partition, if requested and present.
+    pub fn get_name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Parses the response of a GET_INFO (INFO_UF2_TARGET_PARTITION) command,
+    /// the partition a UF2 download of a family would be written to.
+    ///
+    /// Returns `None` if no partition accepts the family.
+    ///
+    /// # Errors
+    /// - [`Error::InfoResponseInvalid`]
+    pub fn from_uf2_target_response(data: &[u8]) -> Result<Option<Self>> {
+        let mut words = Words::new(data)?;
+        let index = words.next()? as i32;
+        if index < 0 {
+            return Ok(None);
+        }
+        let index = u8::try_from(index).map_err(|_| Error::InfoResponseInvalid)?;
+        Self::from_words(index, 0, &mut words).map(Some)
+    }
+
+    /// Parses one partition of a response, with the `PT_INFO_*` flags of
+    /// the response.
+    fn from_words(index: u8, flags: u32, words: &mut Words) -> Result<Self> {
+        let location = words.next()?;
+        let pflags = words.next()?;
+        let mut info = PartitionInfo {
+            index,
+            location,
+            flags: pflags,
+            id: None,
+            extra_family_ids: Vec::new(),
+            name: None,
+        };
+
+        if flags & PT_INFO_PARTITION_ID != 0 && pflags & PARTITION_HAS_ID != 0 {
+            let w = words.take(2)?;
+            info.id = Some(u64::from(w[0]) | u64::from(w[1]) << 32);
+        }
+        if flags & PT_INFO_PARTITION_FAMILY_IDS != 0 {
+            let count = ((pflags >> 7) & 0x3) as usize;
+            info.extra_family_ids = words.take(count)?.to_vec();
+        }
+        if flags & PT_INFO_PARTITION_NAME != 0 && pflags & PARTITION_HAS_NAME != 0 {
+            // a length byte followed by the name, padded to whole words
+            let len = (words.peek()? & 0x7F) as usize;
+            let w = words.take((len + 4) / 4)?;
+            let bytes: Vec<u8> = w.iter().flat_map(|w| w.to_le_bytes()).collect();
+            info.name = Some(String::from_utf8_lossy(&bytes[1..1 + len]).into_owned());
+        }
+
+        Ok(info)
+    }
+}
+impl fmt::Display for PartitionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:#08x}-{:#08x}",
+            self.index,
+            self.get_offset(),
+            self.get_offset() + self.get_size()
+        )?;
+        if let Some(name) = &self.name {
+            write!(f, " \"{}\"", name)?;
+        }
+        if let Some(id) = self.id {
+            write!(f, " id={:016x}", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Partition table information returned by GET_INFO (INFO_PARTITION_TABLE)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTableInfo {
+    flags: u32,
+    partition_count: Option<u8>,
+    has_partition_table: Option<bool>,
+    unpartitioned_space: Option<u32>,
+    partitions: Vec<PartitionInfo>,
+}
+impl PartitionTableInfo {
+    /// Parses the response of a GET_INFO (INFO_PARTITION_TABLE) command.
+    ///
+    /// - `partition` - Partition requested with
+    ///   [`crate::PT_INFO_SINGLE_PARTITION`], or `None` if all partitions
+    ///   were requested.
+    ///
+    /// Partition ids, family ids and names can only be decoded along with
+    /// the location and flags of each partition.
+    ///
+    /// # Errors
+    /// - [`Error::InfoResponseInvalid`]
+    pub fn from_response(data: &[u8], partition: Option<u8>) -> Result<Self> {
+        let mut words = Words::new(data)?;
+        let flags = words.next()?;
+
+        let mut info = PartitionTableInfo {
+            flags,
+            partition_count: None,
+            has_partition_table: None,
+            unpartitioned_space: None,
+            partitions: Vec::new(),
+        };
+        if flags & PT_INFO_PT_INFO != 0 {
+            let w = words.take(2)?;
+            info.partition_count = Some(w[0] as u8);
+            info.has_partition_table = Some(w[0] & 0x100 != 0);
+            info.unpartitioned_space = Some(w[1]);
+        }
+
+        let per_partition =
+            PT_INFO_PARTITION_ID | PT_INFO_PARTITION_FAMILY_IDS | PT_INFO_PARTITION_NAME;
+        if flags & PT_INFO_PARTITION_LOCATION_AND_FLAGS != 0 {
+            let mut index = partition.unwrap_or(0);
+            while !words.is_empty() {
+                let p = PartitionInfo::from_words(index, flags, &mut words)?;
+                info.partitions.push(p);
+                index = index.wrapping_add(1);
+            }
+        } else if flags & per_partition != 0 && !words.is_empty() {
+            return Err(Error::InfoResponseInvalid);
+        }
+
+        Ok(info)
+    }
+
+    /// Returns the `PT_INFO_*` flags of the information returned.
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Returns the number of partitions, if requested.
+    pub fn get_partition_count(&self) -> Option<u8> {
+        self.partition_count
+    }
+
+    /// Returns `true` if a partition table is loaded, if requested.
+    pub fn has_partition_table(&self) -> Option<bool> {
+        self.has_partition_table
+    }
+
+    /// Returns the raw permissions and flags word of the space outside any
+    /// partition, if requested.
+    pub fn get_unpartitioned_space(&self) -> Option<u32> {
+        self.unpartitioned_space
+    }
+
+    /// Returns the partitions, if their location and flags were requested.
+    pub fn get_partitions(&self) -> &[PartitionInfo] {
+        &self.partitions
+    }
+}
+
+/// Status of the last UF2 download, returned by GET_INFO (INFO_UF2_STATUS)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uf2Status {
+    status: u32,
+    family_id: u32,
+    blocks_written: u32,
+    blocks_total: u32,
+}
+impl Uf2Status {
+    /// Parses the response of a GET_INFO (INFO_UF2_STATUS) command.
+    ///
+    /// # Errors
+    /// - [`Error::InfoResponseInvalid`]
+    pub fn from_response(data: &[u8]) -> Result<Self> {
+        let mut words = Words::new(data)?;
+        let w = words.take(4)?;
+        Ok(Uf2Status {
+            status: w[0],
+            family_id: w[1],
+            blocks_written: w[2],
+            blocks_total: w[3],
+        })
+    }
+
+    /// Returns the `UF2_STATUS_*` flags of the download.
+    pub fn get_status(&self) -> u32 {
+        self.status
+    }
+
+    /// Returns the family id of the download.
+    pub fn get_family_id(&self) -> u32 {
+        self.family_id
+    }
+
+    /// Returns the number of UF2 blocks written so far.
+    pub fn get_blocks_written(&self) -> u32 {
+        self.blocks_written
+    }
+
+    /// Returns the total number of UF2 blocks of the download.
+    pub fn get_blocks_total(&self) -> u32 {
+        self.blocks_total
+    }
+}
+
+/// Identity of a PICOBOOT device, confirmed by querying the device
+///
+/// Both chips are identified by the bootrom identity at
+/// [`crate::ROM_IDENTITY_ADDR`], which holds the chip type and the bootrom
+/// version. The RP2350 additionally reports its package, unique device id,
+/// OTP critical register, running architecture and flash device information
+/// through GET_INFO (INFO_SYS), which are `None` on the RP2040.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipInfo {
+    target_id: TargetID,
+    rom_version: u8,
+    package_sel: Option<u32>,
+    device_id: Option<u64>,
+    critical: Option<u32>,
+    cpu_arch: Option<CpuArch>,
+    flash_dev_info: Option<u32>,
+}
+impl ChipInfo {
+    /// Parses the 4 byte bootrom identity read from
+    /// [`crate::ROM_IDENTITY_ADDR`].
+    ///
+    /// # Errors
+    /// - [`Error::ChipIdentityInvalid`]
+    pub fn from_rom_identity(data: &[u8]) -> Result<Self> {
+        let target_id = match data {
+            [b'M', b'u', ROM_CHIP_RP2040, _, ..] => TargetID::Rp2040,
+            [b'M', b'u', ROM_CHIP_RP2350, _, ..] => TargetID::Rp2350,
+            _ => return Err(Error::ChipIdentityInvalid),
+        };
+
+        Ok(ChipInfo {
+            target_id,
+            rom_version: data[3],
+            package_sel: None,
+            device_id: None,
+            critical: None,
+            cpu_arch: None,
+            flash_dev_info: None,
+        })
+    }
+
+    /// Adds the system information of an RP2350.
+    pub fn with_sys_info(mut self, info: &SysInfo) -> Self {
+        self.package_sel = info.get_package_sel();
+        self.device_id = info.get_device_id();
+        self.critical = info.get_critical();
+        self.cpu_arch = info.get_cpu_arch();
+        self.flash_dev_info = info.get_flash_dev_info();
+        self
+    }
+
+    /// Returns the chip type reported by the bootrom.
+    pub fn get_target_id(&self) -> TargetID {
+        self.target_id
+    }
+
+    /// Returns the bootrom version.
+    pub fn get_rom_version(&self) -> u8 {
+        self.rom_version
+    }
+
+    /// Returns the chip revision implied by the bootrom version, such as
+    /// `"B2"` or `"A2"`, or `None` if the version is not known.
+    pub fn get_revision(&self) -> Option<&'static str> {
+        match (self.target_id, self.rom_version) {
+            (TargetID::Rp2040, 1) => Some("B0"),
+            (TargetID::Rp2040, 2) => Some("B1"),
+            (TargetID::Rp2040, 3) => Some("B2"),
+            (TargetID::Rp2350, 2) => Some("A2"),
+            (TargetID::Rp2350, 3) => Some("A3"),
+            (TargetID::Rp2350, 4) => Some("A4"),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the SYSINFO PACKAGE_SEL register. (Only for
+    /// RP2350)
+    pub fn get_package_sel(&self) -> Option<u32> {
+        self.package_sel
+    }
+
+    /// Returns the unique device id. (Only for RP2350)
+    pub fn get_device_id(&self) -> Option<u64> {
+        self.device_id
+    }
+
+    /// Returns the value of the OTP CRITICAL register. (Only for RP2350)
+    pub fn get_critical(&self) -> Option<u32> {
+        self.critical
+    }
+
+    /// Returns the architecture the bootrom is running on. (Only for RP2350)
+    pub fn get_cpu_arch(&self) -> Option<CpuArch> {
+        self.cpu_arch
+    }
+
+    /// Returns the FLASH_DEVINFO value describing the attached flash
+    /// devices. (Only for RP2350)
+    pub fn get_flash_dev_info(&self) -> Option<u32> {
+        self.flash_dev_info
+    }
+}
+impl fmt::Display for ChipInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.target_id)?;
+        match self.get_revision() {
+            Some(rev) => write!(f, " {}", rev)?,
+            None => write!(f, " rom v{}", self.rom_version)?,
+        }
+        if let Some(arch) = self.cpu_arch {
+            write!(f, " ({:?})", arch)?;
+        }
+        if let Some(id) = self.device_id {
+            write!(f, " id={:016x}", id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PT_INFO_SINGLE_PARTITION;
+
+    /// Builds a GET_INFO response from the words following the word count.
+    fn response(words: &[u32]) -> Vec<u8> {
+        let mut data = (words.len() as u32).to_le_bytes().to_vec();
+        for w in words {
+            data.extend(w.to_le_bytes());
+        }
+        data
+    }
+
+    /// Packs a partition name, preceded by its length byte, into words.
+    fn name_words(name: &str) -> Vec<u32> {
+        let mut bytes = vec![name.len() as u8];
+        bytes.extend(name.as_bytes());
+        bytes.resize((bytes.len() + 3) / 4 * 4, 0);
+        bytes
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn sys_info_flags() {
+        let info = SysInfo::from_response(&response(&[0])).unwrap();
+        assert_eq!(info.get_flags(), 0);
+        assert_eq!(info.get_package_sel(), None);
+        assert_eq!(info.get_boot_info(), None);
+
+        let info = SysInfo::from_response(&response(&[
+            SYS_INFO_CHIP_INFO,
+            1,
+            0x89AB_CDEF,
+            0x0123_4567,
+        ]))
+        .unwrap();
+        assert_eq!(info.get_package_sel(), Some(1));
+        assert_eq!(info.get_device_id(), Some(0x0123_4567_89AB_CDEF));
+        assert_eq!(info.get_critical(), None);
+
+        let info = SysInfo::from_response(&response(&[SYS_INFO_CRITICAL, 0x11])).unwrap();
+        assert_eq!(info.get_critical(), Some(0x11));
+
+        let info = SysInfo::from_response(&response(&[SYS_INFO_CPU_INFO, 1])).unwrap();
+        assert_eq!(info.get_cpu_arch(), Some(CpuArch::RiscV));
+
+        let info = SysInfo::from_response(&response(&[SYS_INFO_FLASH_DEV_INFO, 0x0C00])).unwrap();
+        assert_eq!(info.get_flash_dev_info(), Some(0x0C00));
+
+        let info = SysInfo::from_response(&response(&[SYS_INFO_BOOT_RANDOM, 1, 2, 3, 4])).unwrap();
+        assert_eq!(info.get_boot_random(), Some([1, 2, 3, 4]));
+
+        let info = SysInfo::from_response(&response(&[SYS_INFO_BOOT_INFO, 5, 6, 7, 8])).unwrap();
+        assert_eq!(info.get_boot_info(), Some([5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn sys_info_in_flag_order() {
+        let flags = SYS_INFO_CHIP_INFO
+            | SYS_INFO_CRITICAL
+            | SYS_INFO_CPU_INFO
+            | SYS_INFO_FLASH_DEV_INFO
+            | SYS_INFO_BOOT_RANDOM
+            | SYS_INFO_BOOT_INFO;
+        let words = [flags, 1, 2, 3, 4, 0, 5, 6, 7, 8, 9, 10, 11, 12, 13];
+        let info = SysInfo::from_response(&response(&words)).unwrap();
+        assert_eq!(info.get_flags(), flags);
+        assert_eq!(info.get_package_sel(), Some(1));
+        assert_eq!(info.get_device_id(), Some(3 << 32 | 2));
+        assert_eq!(info.get_critical(), Some(4));
+        assert_eq!(info.get_cpu_arch(), Some(CpuArch::Arm));
+        assert_eq!(info.get_flash_dev_info(), Some(5));
+        assert_eq!(info.get_boot_random(), Some([6, 7, 8, 9]));
+        assert_eq!(info.get_boot_info(), Some([10, 11, 12, 13]));
+    }
+
+    #[test]
+    fn sys_info_rejects_invalid() {
+        // unknown flag
+        assert!(SysInfo::from_response(&response(&[0x20])).is_err());
+        // unknown architecture
+        assert!(SysInfo::from_response(&response(&[SYS_INFO_CPU_INFO, 2])).is_err());
+        // missing words
+        assert!(SysInfo::from_response(&response(&[SYS_INFO_BOOT_INFO, 1, 2])).is_err());
+        // word count not smaller than the response
+        let mut data = response(&[SYS_INFO_CRITICAL, 0]);
+        data[0] = 3;
+        assert!(SysInfo::from_response(&data).is_err());
+        data[0] = 2;
+        assert!(SysInfo::from_response(&data).is_ok());
+        assert!(SysInfo::from_response(&[]).is_err());
+    }
+
+    #[test]
+    fn words_beyond_count_are_ignored() {
+        let mut data = response(&[SYS_INFO_CRITICAL, 7]);
+        data.extend([0xFF; 8]);
+        let info = SysInfo::from_response(&data).unwrap();
+        assert_eq!(info.get_critical(), Some(7));
+    }
+
+    #[test]
+    fn partition_table() {
+        let flags = PT_INFO_PT_INFO
+            | PT_INFO_PARTITION_LOCATION_AND_FLAGS
+            | PT_INFO_PARTITION_ID
+            | PT_INFO_PARTITION_FAMILY_IDS
+            | PT_INFO_PARTITION_NAME;
+        // sectors 0-15, accepting RP2040 and data, with an id and a name
+        let p0_location = 15 << 13;
+        let p0_flags = PARTITION_HAS_ID | PARTITION_HAS_NAME | 1 << 14 | 1 << 19;
+        // sectors 16-31, not bootable on RISC-V, with two extra family ids
+        let p1_location = 31 << 13 | 16;
+        let p1_flags = PARTITION_NOT_BOOTABLE_RISCV | 2 << 7;
+
+        let mut words = vec![flags, 0x102, 0xFC00_0000, p0_location, p0_flags, 0xAA, 0xBB];
+        words.extend(name_words("data"));
+        words.extend([p1_location, p1_flags, 0x1234, 0x5678]);
+        let info = PartitionTableInfo::from_response(&response(&words), None).unwrap();
+
+        assert_eq!(info.get_flags(), flags);
+        assert_eq!(info.get_partition_count(), Some(2));
+        assert_eq!(info.has_partition_table(), Some(true));
+        assert_eq!(info.get_unpartitioned_space(), Some(0xFC00_0000));
+
+        let p = info.get_partitions();
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0].get_index(), 0);
+        assert_eq!(p[0].get_offset(), 0);
+        assert_eq!(p[0].get_size(), 16 * SECTOR_SIZE);
+        assert_eq!(p[0].get_id(), Some(0xBB << 32 | 0xAA));
+        assert_eq!(p[0].get_name(), Some("data"));
+        assert_eq!(
+            p[0].get_family_ids(),
+            vec![UF2_RP2040_FAMILY_ID, UF2_DATA_FAMILY_ID]
+        );
+        assert!(p[0].get_extra_family_ids().is_empty());
+
+        assert_eq!(p[1].get_index(), 1);
+        assert_eq!(p[1].get_offset(), 16 * SECTOR_SIZE);
+        assert_eq!(p[1].get_id(), None);
+        assert_eq!(p[1].get_name(), None);
+        assert!(p[1].is_bootable_arm());
+        assert!(!p[1].is_bootable_riscv());
+        assert_eq!(p[1].get_extra_family_ids(), &[0x1234, 0x5678]);
+        assert_eq!(p[1].get_family_ids(), vec![0x1234, 0x5678]);
+    }
+
+    #[test]
+    fn partition_name_padding() {
+        let flags = PT_INFO_PARTITION_LOCATION_AND_FLAGS | PT_INFO_PARTITION_NAME;
+        // the length byte and name fill whole words, or are padded to them
+        for name in ["", "abc", "boot", "firmware_a"] {
+            let mut words = vec![flags, 0, PARTITION_HAS_NAME];
+            words.extend(name_words(name));
+            words.extend([0, 0]);
+            let info = PartitionTableInfo::from_response(&response(&words), Some(3)).unwrap();
+            let p = info.get_partitions();
+            assert_eq!(p.len(), 2, "{:?}", name);
+            assert_eq!(p[0].get_index(), 3);
+            assert_eq!(p[0].get_name(), Some(name));
+            assert_eq!(p[1].get_index(), 4);
+        }
+
+        // the name is longer than the response
+        let words = [flags, 0, PARTITION_HAS_NAME, 8];
+        assert!(PartitionTableInfo::from_response(&response(&words), None).is_err());
+    }
+
+    #[test]
+    fn partition_table_rejects_invalid() {
+        // partition data without location and flags
+        let words = [PT_INFO_PARTITION_ID, 0, 0];
+        assert!(PartitionTableInfo::from_response(&response(&words), None).is_err());
+        // missing flags word of a partition
+        let words = [PT_INFO_PARTITION_LOCATION_AND_FLAGS, 0];
+        assert!(PartitionTableInfo::from_response(&response(&words), None).is_err());
+        // missing extra family ids
+        let words = [
+            PT_INFO_PARTITION_LOCATION_AND_FLAGS | PT_INFO_PARTITION_FAMILY_IDS,
+            0,
+            3 << 7,
+            0,
+        ];
+        assert!(PartitionTableInfo::from_response(&response(&words), None).is_err());
+        // word count not smaller than the response
+        let mut data = response(&[PT_INFO_PT_INFO | PT_INFO_SINGLE_PARTITION, 0, 0]);
+        data[0] = 4;
+        assert!(PartitionTableInfo::from_response(&data, None).is_err());
+    }
+
+    #[test]
+    fn uf2_target_partition() {
+        let data = response(&[u32::MAX]);
+        assert_eq!(
+            PartitionInfo::from_uf2_target_response(&data).unwrap(),
+            None
+        );
+
+        let data = response(&[2, 31 << 13 | 16, 1 << 16]);
+        let p = PartitionInfo::from_uf2_target_response(&data)
+            .unwrap()
+            .unwrap();
+        assert_eq!(p.get_index(), 2);
+        assert_eq!(p.get_offset(), 16 * SECTOR_SIZE);
+        assert_eq!(p.get_family_ids(), vec![UF2_RP2350_ARM_S_FAMILY_ID]);
+
+        // index out of range and missing flags word
+        assert!(PartitionInfo::from_uf2_target_response(&response(&[256, 0, 0])).is_err());
+        assert!(PartitionInfo::from_uf2_target_response(&response(&[0, 0])).is_err());
+    }
+
+    #[test]
+    fn uf2_status() {
+        let data = response(&[0x20, UF2_RP2350_RISCV_FAMILY_ID, 3, 10]);
+        let status = Uf2Status::from_response(&data).unwrap();
+        assert_eq!(status.get_status(), 0x20);
+        assert_eq!(status.get_family_id(), UF2_RP2350_RISCV_FAMILY_ID);
+        assert_eq!(status.get_blocks_written(), 3);
+        assert_eq!(status.get_blocks_total(), 10);
+
+        assert!(Uf2Status::from_response(&response(&[0, 0, 0])).is_err());
+        let mut data = response(&[0, 0, 0, 0]);
+        data[0] = 5;
+        assert!(Uf2Status::from_response(&data).is_err());
+    }
+}
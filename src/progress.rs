@@ -0,0 +1,78 @@
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Phase of a multi-command operation, as reported to a [`ProgressObserver`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgressPhase {
+    /// Erasing flash.
+    Erase,
+    /// Writing memory.
+    Write,
+    /// Reading memory back and comparing it with an image.
+    Verify,
+    /// Reading memory.
+    Read,
+}
+impl fmt::Display for ProgressPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgressPhase::Erase => write!(f, "erase"),
+            ProgressPhase::Write => write!(f, "write"),
+            ProgressPhase::Verify => write!(f, "verify"),
+            ProgressPhase::Read => write!(f, "read"),
+        }
+    }
+}
+
+/// Observer of the progress of multi-command operations of a
+/// [`crate::PicobootConnection`], such as [`crate::PicobootConnection::load`].
+///
+/// Each phase is reported once with zero bytes done before its first
+/// command, then again after each of its commands completes. Closures taking
+/// the same arguments as [`Self::progress`] are observers.
+pub trait ProgressObserver {
+    /// Reports the number of bytes done out of the total of a phase.
+    fn progress(&mut self, phase: ProgressPhase, done: u32, total: u32);
+}
+impl<F: FnMut(ProgressPhase, u32, u32)> ProgressObserver for F {
+    fn progress(&mut self, phase: ProgressPhase, done: u32, total: u32) {
+        self(phase, done, total)
+    }
+}
+
+/// Token for cancelling multi-command operations of a
+/// [`crate::PicobootConnection`].
+///
+/// Clones of a token share its state, so an operation can be cancelled from
+/// another thread. Operations check the token between commands, so a command
+/// that was already sent always completes. A cancelled token stays cancelled
+/// until it is reset.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+impl CancelToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the operations using the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Clears the cancellation, so the token can be used for new
+    /// operations.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,1197 @@
+use core::fmt;
+
+/// RP MCU memory address for the start of ROM storage
+pub const ROM_START: u32 = 0x00000000;
+/// RP2040 memory address for the end of ROM storage
+pub const ROM_END_RP2040: u32 = 0x00004000;
+/// RP2350 memory address for the end of ROM storage
+pub const ROM_END_RP2350: u32 = 0x00008000;
+
+/// RP MCU memory address for the start of flash storage
+pub const FLASH_START: u32 = 0x10000000;
+/// RP2040 memory address for the end of flash storage
+pub const FLASH_END_RP2040: u32 = 0x11000000;
+/// RP2350 memory address for the end of flash storage
+pub const FLASH_END_RP2350: u32 = 0x12000000;
+
+/// RP2040 memory address for the start of XIP (execute-in-place) SRAM storage
+pub const XIP_SRAM_START_RP2040: u32 = 0x15000000;
+/// RP2040 memory address for the end of XIP (execute-in-place) SRAM storage
+pub const XIP_SRAM_END_RP2040: u32 = 0x15004000;
+/// RP2350 memory address for the start of XIP (execute-in-place) SRAM storage
+pub const XIP_SRAM_START_RP2350: u32 = 0x13ffc000;
+/// RP2350 memory address for the end of XIP (execute-in-place) SRAM storage
+pub const XIP_SRAM_END_RP2350: u32 = 0x14000000;
+
+/// RP MCU memory address for the start of SRAM storage
+pub const SRAM_START_RP2040: u32 = 0x20000000;
+/// RP2040 memory address for the end of SRAM storage
+pub const SRAM_END_RP2040: u32 = 0x20042000;
+/// RP2350 memory address for the end of SRAM storage
+pub const SRAM_END_RP2350: u32 = 0x20082000;
+
+/// RP MCU flash page size (for writing)
+pub const PAGE_SIZE: u32 = 0x100;
+/// RP MCU flash sector size (for erasing)
+pub const SECTOR_SIZE: u32 = 0x1000;
+/// RP2040 memory address for the initial stack pointer
+pub const STACK_POINTER_RP2040: u32 = 0x20042000; // same as SRAM_END_RP2040
+/// RP2350 memory address for the initial stack pointer
+pub const STACK_POINTER_RP2350: u32 = 0x20082000; // same as SRAM_END_RP2350
+
+/// RP USB Vendor ID
+pub const PICOBOOT_VID: u16 = 0x2E8A;
+/// RP2040 USB Product ID
+pub const PICOBOOT_PID_RP2040: u16 = 0x0003;
+/// RP2350 USB Product ID
+pub const PICOBOOT_PID_RP2350: u16 = 0x000f;
+
+/// RP MCU magic number for USB interfacing
+pub const PICOBOOT_MAGIC: u32 = 0x431FD10B;
+
+/// UF2 Family ID for RP2040
+pub const UF2_RP2040_FAMILY_ID: u32 = 0xE48BFF56;
+pub const UF2_ABSOLUTE_FAMILY_ID: u32 = 0xE48BFF57;
+pub const UF2_DATA_FAMILY_ID: u32 = 0xE48BFF58;
+/// UF2 Family ID for RP2350 (ARM, Secure TrustZone)
+pub const UF2_RP2350_ARM_S_FAMILY_ID: u32 = 0xE48BFF59;
+/// UF2 Family ID for RP2350 (RISC-V)
+pub const UF2_RP2350_RISCV_FAMILY_ID: u32 = 0xE48BFF5A;
+/// UF2 Family ID for RP2350 (ARM, Non-Secure TrustZone)
+pub const UF2_RP2350_ARM_NS_FAMILY_ID: u32 = 0xE48BFF5B;
+pub const UF2_FAMILY_ID_MAX: u32 = 0xE48BFF5B;
+
+/// Size of a command packet sent over the bulk OUT endpoint.
+pub const PICOBOOT_CMD_SIZE: usize = 32;
+/// Size of the command status returned by the GET_COMMAND_STATUS control
+/// request.
+pub const PICOBOOT_STATUS_SIZE: usize = 16;
+
+/// ROM address of the bootrom identity: the `'M'`, `'u'` magic, the chip
+/// type and the bootrom version
+pub const ROM_IDENTITY_ADDR: u32 = 0x00000010;
+/// Chip type byte of the bootrom identity on RP2040
+pub const ROM_CHIP_RP2040: u8 = 1;
+/// Chip type byte of the bootrom identity on RP2350
+pub const ROM_CHIP_RP2350: u8 = 2;
+
+/// Largest response the device returns for GET_INFO
+pub const GET_INFO_MAX_SIZE: u32 = 256;
+/// GET_INFO type for system information
+pub const GET_INFO_SYS: u8 = 0x1;
+/// GET_INFO type for partition table information
+pub const GET_INFO_PARTITION_TABLE: u8 = 0x2;
+/// GET_INFO type for the partition a UF2 family would be downloaded to
+pub const GET_INFO_UF2_TARGET_PARTITION: u8 = 0x3;
+/// GET_INFO type for the status of the last UF2 download
+pub const GET_INFO_UF2_STATUS: u8 = 0x4;
+
+/// INFO_SYS flag for chip information (package, device id)
+pub const SYS_INFO_CHIP_INFO: u32 = 0x0001;
+/// INFO_SYS flag for the OTP critical register
+pub const SYS_INFO_CRITICAL: u32 = 0x0002;
+/// INFO_SYS flag for the current CPU architecture
+pub const SYS_INFO_CPU_INFO: u32 = 0x0004;
+/// INFO_SYS flag for the flash device information
+pub const SYS_INFO_FLASH_DEV_INFO: u32 = 0x0008;
+/// INFO_SYS flag for the per-boot random number
+pub const SYS_INFO_BOOT_RANDOM: u32 = 0x0010;
+/// INFO_SYS flag for the boot diagnostics
+pub const SYS_INFO_BOOT_INFO: u32 = 0x0040;
+
+/// INFO_PARTITION_TABLE flag for the partition count and unpartitioned space
+pub const PT_INFO_PT_INFO: u32 = 0x0001;
+/// INFO_PARTITION_TABLE flag for the location and flags of each partition
+pub const PT_INFO_PARTITION_LOCATION_AND_FLAGS: u32 = 0x0010;
+/// INFO_PARTITION_TABLE flag for the id of each partition
+pub const PT_INFO_PARTITION_ID: u32 = 0x0020;
+/// INFO_PARTITION_TABLE flag for the extra family ids of each partition
+pub const PT_INFO_PARTITION_FAMILY_IDS: u32 = 0x0040;
+/// INFO_PARTITION_TABLE flag for the name of each partition
+pub const PT_INFO_PARTITION_NAME: u32 = 0x0080;
+/// INFO_PARTITION_TABLE flag to only return the partition in bits 31:24
+pub const PT_INFO_SINGLE_PARTITION: u32 = 0x8000;
+
+/// Number of rows of the RP2350 OTP
+pub const OTP_ROW_COUNT: u16 = 4096;
+/// Bytes transferred per OTP row in raw mode (24 bits of data)
+pub const OTP_RAW_ROW_SIZE: u32 = 4;
+/// Bytes transferred per OTP row in ECC mode (16 bits of data)
+pub const OTP_ECC_ROW_SIZE: u32 = 2;
+
+/// REBOOT2 flags mask of the reboot type
+pub const REBOOT2_TYPE_MASK: u32 = 0xf;
+/// REBOOT2 reboot type for a normal boot
+pub const REBOOT2_FLAG_REBOOT_TYPE_NORMAL: u32 = 0x0;
+/// REBOOT2 reboot type for BOOTSEL mode
+pub const REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL: u32 = 0x2;
+/// REBOOT2 reboot type for booting an image in RAM
+pub const REBOOT2_FLAG_REBOOT_TYPE_RAM_IMAGE: u32 = 0x3;
+/// REBOOT2 reboot type for booting a flash update
+pub const REBOOT2_FLAG_REBOOT_TYPE_FLASH_UPDATE: u32 = 0x4;
+/// REBOOT2 reboot type for starting at a program counter and stack pointer
+pub const REBOOT2_FLAG_REBOOT_TYPE_PC_SP: u32 = 0xd;
+/// REBOOT2 flag to switch the cores to Arm
+pub const REBOOT2_FLAG_REBOOT_TO_ARM: u32 = 0x10;
+/// REBOOT2 flag to switch the cores to RISC-V
+pub const REBOOT2_FLAG_REBOOT_TO_RISCV: u32 = 0x20;
+/// REBOOT2 flag to not return from the command if the reboot succeeds
+pub const REBOOT2_FLAG_NO_RETURN_ON_SUCCESS: u32 = 0x100;
+
+/// REBOOT2 BOOTSEL flag to disable the USB mass storage interface
+pub const BOOTSEL_FLAG_DISABLE_MSD_INTERFACE: u32 = 0x01;
+/// REBOOT2 BOOTSEL flag to disable the PICOBOOT interface
+pub const BOOTSEL_FLAG_DISABLE_PICOBOOT_INTERFACE: u32 = 0x02;
+/// REBOOT2 BOOTSEL flag for an active low activity LED
+pub const BOOTSEL_FLAG_GPIO_PIN_ACTIVE_LOW: u32 = 0x10;
+/// REBOOT2 BOOTSEL flag to use the GPIO pin in p1 as activity LED
+pub const BOOTSEL_FLAG_GPIO_PIN_SPECIFIED: u32 = 0x20;
+
+/// INFO_UF2_STATUS flag set when a UF2 of a family not accepted was ignored
+pub const UF2_STATUS_IGNORED_FAMILY: u32 = 0x01;
+/// INFO_UF2_STATUS flag set when a UF2 download was aborted by exclusive
+/// access
+pub const UF2_STATUS_ABORT_EXCLUSIVELY_LOCKED: u32 = 0x10;
+/// INFO_UF2_STATUS flag set when a UF2 download was aborted by a bad address
+pub const UF2_STATUS_ABORT_BAD_ADDRESS: u32 = 0x20;
+/// INFO_UF2_STATUS flag set when a UF2 download was aborted by a write error
+pub const UF2_STATUS_ABORT_WRITE_ERROR: u32 = 0x40;
+/// INFO_UF2_STATUS flag set when the reboot after a UF2 download failed
+pub const UF2_STATUS_ABORT_REBOOT_FAILED: u32 = 0x80;
+
+/// Reads a little endian word from a buffer.
+fn word(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// Writes a little endian word into a buffer.
+fn put_word(buf: &mut [u8], at: usize, x: u32) {
+    buf[at..at + 4].copy_from_slice(&x.to_le_bytes());
+}
+
+/// Error decoding PICOBOOT wire data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// Command or status packet does not have the expected length.
+    PacketLengthInvalid { expected: usize, got: usize },
+    /// Command packet does not start with [`PICOBOOT_MAGIC`].
+    MagicInvalid(u32),
+    /// Command ID is not a known [`PicobootCmdId`].
+    UnknownCmdId(u8),
+    /// Status code is not a known [`PicobootStatus`].
+    UnknownStatusCode(u32),
+}
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PacketLengthInvalid { expected, got } => write!(
+                f,
+                "packet length invalid: expected {}, got {}",
+                expected, got
+            ),
+            Self::MagicInvalid(magic) => write!(f, "cmd magic invalid: {:#010x}", magic),
+            Self::UnknownCmdId(id) => write!(f, "cmd id unknown: {:#04x}", id),
+            Self::UnknownStatusCode(code) => write!(f, "status code unknown: {}", code),
+        }
+    }
+}
+
+// see https://datasheets.raspberrypi.com/rp2040/rp2040-datasheet.pdf
+// section 2.8.5 for details on PICOBOOT interface
+
+/// The type of microcontroller detected as the PICOBOOT device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetID {
+    /// RP2040 MCU target.
+    Rp2040,
+    /// RP2350 MCU target.
+    Rp2350,
+}
+impl TargetID {
+    /// Returns the target for a USB VID/PID pair of a device in BOOTSEL mode,
+    /// or `None` if the pair is not a known PICOBOOT VID/PID pair.
+    pub fn from_usb_ids(vid: u16, pid: u16) -> Option<Self> {
+        match (vid, pid) {
+            (PICOBOOT_VID, PICOBOOT_PID_RP2040) => Some(TargetID::Rp2040),
+            (PICOBOOT_VID, PICOBOOT_PID_RP2350) => Some(TargetID::Rp2350),
+            _ => None,
+        }
+    }
+}
+
+/// Command ID of commands for PICOBOOT interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PicobootCmdId {
+    Unknown = 0x0,
+    ExclusiveAccess = 0x1,
+    Reboot = 0x2,
+    FlashErase = 0x3,
+    Read = 0x84, // either RAM or FLASH
+    Write = 0x5, // either RAM or FLASH (does no erase)
+    ExitXip = 0x6,
+    EnterCmdXip = 0x7,
+    Exec = 0x8,
+    VectorizeFlash = 0x9,
+    // RP2350 only below here
+    Reboot2 = 0xA,
+    GetInfo = 0x8B,
+    OtpRead = 0x8C,
+    OtpWrite = 0xD,
+    //Exec2 = 0xE, // currently unused
+}
+impl TryFrom<u8> for PicobootCmdId {
+    type Error = ();
+
+    fn try_from(x: u8) -> Result<Self, Self::Error> {
+        match x {
+            x if x == Self::Unknown as u8 => Ok(Self::Unknown),
+            x if x == Self::ExclusiveAccess as u8 => Ok(Self::ExclusiveAccess),
+            x if x == Self::Reboot as u8 => Ok(Self::Reboot),
+            x if x == Self::FlashErase as u8 => Ok(Self::FlashErase),
+            x if x == Self::Read as u8 => Ok(Self::Read),
+            x if x == Self::Write as u8 => Ok(Self::Write),
+            x if x == Self::ExitXip as u8 => Ok(Self::ExitXip),
+            x if x == Self::EnterCmdXip as u8 => Ok(Self::EnterCmdXip),
+            x if x == Self::Exec as u8 => Ok(Self::Exec),
+            x if x == Self::VectorizeFlash as u8 => Ok(Self::VectorizeFlash),
+            x if x == Self::Reboot2 as u8 => Ok(Self::Reboot2),
+            x if x == Self::GetInfo as u8 => Ok(Self::GetInfo),
+            x if x == Self::OtpRead as u8 => Ok(Self::OtpRead),
+            x if x == Self::OtpWrite as u8 => Ok(Self::OtpWrite),
+            // x if x == Self::Exec2 as u8 => Ok(Self::Exec2),
+            _ => Err(()),
+        }
+    }
+}
+impl PicobootCmdId {
+    /// Returns `true` if sending the command again after a failed attempt
+    /// leaves the device in the same state as sending it once.
+    ///
+    /// Writes are not considered idempotent, since a partially completed
+    /// attempt may already have modified memory.
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Self::ExclusiveAccess
+                | Self::FlashErase
+                | Self::Read
+                | Self::ExitXip
+                | Self::EnterCmdXip
+                | Self::GetInfo
+                | Self::OtpRead
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PicobootStatus {
+    Ok = 0,
+    UnknownCmd = 1,
+    InvalidCmdLength = 2,
+    InvalidTransferLength = 3,
+    InvalidAddress = 4,
+    BadAlignment = 5,
+    InterleavedWrite = 6,
+    Rebooting = 7,
+    UnknownError = 8,
+    InvalidState = 9,
+    NotPermitted = 10,
+    InvalidArg = 11,
+    BufferTooSmall = 12,
+    PreconditionNotMet = 13,
+    ModifiedData = 14,
+    InvalidData = 15,
+    NotFound = 16,
+    UnsupportedModification = 17,
+}
+impl TryFrom<u32> for PicobootStatus {
+    type Error = ();
+
+    fn try_from(x: u32) -> Result<Self, Self::Error> {
+        match x {
+            x if x == Self::Ok as u32 => Ok(Self::Ok),
+            x if x == Self::UnknownCmd as u32 => Ok(Self::UnknownCmd),
+            x if x == Self::InvalidCmdLength as u32 => Ok(Self::InvalidCmdLength),
+            x if x == Self::InvalidTransferLength as u32 => Ok(Self::InvalidTransferLength),
+            x if x == Self::InvalidAddress as u32 => Ok(Self::InvalidAddress),
+            x if x == Self::BadAlignment as u32 => Ok(Self::BadAlignment),
+            x if x == Self::InterleavedWrite as u32 => Ok(Self::InterleavedWrite),
+            x if x == Self::Rebooting as u32 => Ok(Self::Rebooting),
+            x if x == Self::UnknownError as u32 => Ok(Self::UnknownError),
+            x if x == Self::InvalidState as u32 => Ok(Self::InvalidState),
+            x if x == Self::NotPermitted as u32 => Ok(Self::NotPermitted),
+            x if x == Self::InvalidArg as u32 => Ok(Self::InvalidArg),
+            x if x == Self::BufferTooSmall as u32 => Ok(Self::BufferTooSmall),
+            x if x == Self::PreconditionNotMet as u32 => Ok(Self::PreconditionNotMet),
+            x if x == Self::ModifiedData as u32 => Ok(Self::ModifiedData),
+            x if x == Self::InvalidData as u32 => Ok(Self::InvalidData),
+            x if x == Self::NotFound as u32 => Ok(Self::NotFound),
+            x if x == Self::UnsupportedModification as u32 => Ok(Self::UnsupportedModification),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Args of commands that operate on a memory range (FLASH_ERASE, READ and
+/// WRITE), also used for the single address of EXEC and VECTORIZE_FLASH.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootRangeCmd {
+    addr: u32,
+    size: u32,
+}
+impl PicobootRangeCmd {
+    /// Decodes the args field of a command.
+    pub fn from_args(args: &[u8; 16]) -> Self {
+        PicobootRangeCmd {
+            addr: word(args, 0),
+            size: word(args, 4),
+        }
+    }
+
+    pub fn ser(addr: u32, size: u32) -> [u8; 16] {
+        let mut args = [0; 16];
+        put_word(&mut args, 0, addr);
+        put_word(&mut args, 4, size);
+        args
+    }
+
+    pub fn get_addr(&self) -> u32 {
+        self.addr
+    }
+
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+}
+impl fmt::Display for PicobootRangeCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "addr={:#010x} size={:#x}", self.addr, self.size)
+    }
+}
+
+/// Args of the REBOOT command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootRebootCmd {
+    pc: u32,
+    sp: u32,
+    delay: u32,
+}
+impl PicobootRebootCmd {
+    /// Decodes the args field of a command.
+    pub fn from_args(args: &[u8; 16]) -> Self {
+        PicobootRebootCmd {
+            pc: word(args, 0),
+            sp: word(args, 4),
+            delay: word(args, 8),
+        }
+    }
+
+    pub fn ser(pc: u32, sp: u32, delay: u32) -> [u8; 16] {
+        let mut args = [0; 16];
+        put_word(&mut args, 0, pc);
+        put_word(&mut args, 4, sp);
+        put_word(&mut args, 8, delay);
+        args
+    }
+
+    pub fn get_pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn get_sp(&self) -> u32 {
+        self.sp
+    }
+
+    pub fn get_delay(&self) -> u32 {
+        self.delay
+    }
+}
+impl fmt::Display for PicobootRebootCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pc={:#010x} sp={:#010x} delay={}ms",
+            self.pc, self.sp, self.delay
+        )
+    }
+}
+
+/// Args of the REBOOT2 command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootReboot2Cmd {
+    flags: u32,
+    delay: u32,
+    p0: u32,
+    p1: u32,
+}
+impl PicobootReboot2Cmd {
+    /// Decodes the args field of a command.
+    pub fn from_args(args: &[u8; 16]) -> Self {
+        PicobootReboot2Cmd {
+            flags: word(args, 0),
+            delay: word(args, 4),
+            p0: word(args, 8),
+            p1: word(args, 12),
+        }
+    }
+
+    pub fn ser(flags: u32, delay: u32, p0: u32, p1: u32) -> [u8; 16] {
+        let mut args = [0; 16];
+        put_word(&mut args, 0, flags);
+        put_word(&mut args, 4, delay);
+        put_word(&mut args, 8, p0);
+        put_word(&mut args, 12, p1);
+        args
+    }
+
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn get_delay(&self) -> u32 {
+        self.delay
+    }
+
+    pub fn get_p0(&self) -> u32 {
+        self.p0
+    }
+
+    pub fn get_p1(&self) -> u32 {
+        self.p1
+    }
+
+    /// Returns the decoded boot type and options, or `None` if they are not
+    /// known.
+    pub fn get_options(&self) -> Option<Reboot2Options> {
+        Reboot2Options::from_args(self.flags, self.p0, self.p1)
+    }
+}
+impl fmt::Display for PicobootReboot2Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(options) = self.get_options() {
+            return write!(f, "{} delay={}ms", options, self.delay);
+        }
+        write!(
+            f,
+            "flags={:#x} delay={}ms p0={:#010x} p1={:#010x}",
+            self.flags, self.delay, self.p0, self.p1
+        )
+    }
+}
+
+/// Args of the OTP_READ and OTP_WRITE commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootOtpCmd {
+    row: u16,
+    row_count: u16,
+    ecc: bool,
+}
+impl PicobootOtpCmd {
+    /// Decodes the args field of a command.
+    pub fn from_args(args: &[u8; 16]) -> Self {
+        PicobootOtpCmd {
+            row: u16::from_le_bytes([args[0], args[1]]),
+            row_count: u16::from_le_bytes([args[2], args[3]]),
+            ecc: args[4] != 0,
+        }
+    }
+
+    pub fn ser(row: u16, row_count: u16, ecc: bool) -> [u8; 16] {
+        let mut args = [0; 16];
+        args[0..2].copy_from_slice(&row.to_le_bytes());
+        args[2..4].copy_from_slice(&row_count.to_le_bytes());
+        args[4] = ecc as u8;
+        args
+    }
+
+    pub fn get_row(&self) -> u16 {
+        self.row
+    }
+
+    pub fn get_row_count(&self) -> u16 {
+        self.row_count
+    }
+
+    pub fn is_ecc(&self) -> bool {
+        self.ecc
+    }
+
+    /// Returns the number of bytes transferred per row.
+    pub fn get_row_size(&self) -> u32 {
+        match self.ecc {
+            true => OTP_ECC_ROW_SIZE,
+            false => OTP_RAW_ROW_SIZE,
+        }
+    }
+}
+impl fmt::Display for PicobootOtpCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.ecc { "ecc" } else { "raw" };
+        write!(f, "row={:#05x} rows={} {}", self.row, self.row_count, mode)
+    }
+}
+
+/// Args of the GET_INFO command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootGetInfoCmd {
+    info_type: u8,
+    params: [u32; 3],
+}
+impl PicobootGetInfoCmd {
+    /// Decodes the args field of a command.
+    pub fn from_args(args: &[u8; 16]) -> Self {
+        PicobootGetInfoCmd {
+            info_type: args[0],
+            params: [word(args, 4), word(args, 8), word(args, 12)],
+        }
+    }
+
+    pub fn ser(info_type: u8, params: [u32; 3]) -> [u8; 16] {
+        let mut args = [0; 16];
+        args[0] = info_type;
+        put_word(&mut args, 4, params[0]);
+        put_word(&mut args, 8, params[1]);
+        put_word(&mut args, 12, params[2]);
+        args
+    }
+
+    pub fn get_info_type(&self) -> u8 {
+        self.info_type
+    }
+
+    pub fn get_params(&self) -> [u32; 3] {
+        self.params
+    }
+}
+impl fmt::Display for PicobootGetInfoCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.info_type {
+            GET_INFO_SYS => write!(f, "sys flags={:#x}", self.params[0]),
+            GET_INFO_PARTITION_TABLE => write!(f, "partition_table flags={:#x}", self.params[0]),
+            GET_INFO_UF2_TARGET_PARTITION => {
+                write!(f, "uf2_target_partition family={:#010x}", self.params[0])
+            }
+            GET_INFO_UF2_STATUS => write!(f, "uf2_status"),
+            t => write!(
+                f,
+                "type={} params={:#x},{:#x},{:#x}",
+                t, self.params[0], self.params[1], self.params[2]
+            ),
+        }
+    }
+}
+
+/// Architecture of the cores of an RP2350
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuArch {
+    /// Arm Cortex-M33
+    Arm,
+    /// Hazard3 RISC-V
+    RiscV,
+}
+
+/// Boot type of a REBOOT2 command, with its parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reboot2Type {
+    /// Boots normally.
+    Normal,
+    /// Enters BOOTSEL mode.
+    Bootsel {
+        /// Disables the USB mass storage interface.
+        disable_msd: bool,
+        /// Disables the PICOBOOT interface.
+        disable_picoboot: bool,
+        /// GPIO pin of the activity LED, if any.
+        led_gpio: Option<u8>,
+        /// Whether the activity LED is active low.
+        led_active_low: bool,
+    },
+    /// Boots an image already loaded into RAM.
+    RamImage {
+        /// Start of the RAM region searched for the image.
+        base: u32,
+        /// Size of the RAM region searched for the image.
+        size: u32,
+    },
+    /// Boots an image just written to flash, as a flash update.
+    FlashUpdate {
+        /// Flash address of the updated image.
+        buffer: u32,
+    },
+    /// Starts executing at a program counter with a stack pointer.
+    PcSp {
+        /// Program counter to start at.
+        pc: u32,
+        /// Initial stack pointer.
+        sp: u32,
+    },
+}
+
+/// Boot type and options of a REBOOT2 command (RP2350)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reboot2Options {
+    reboot_type: Reboot2Type,
+    arch: Option<CpuArch>,
+    no_return_on_success: bool,
+}
+impl Reboot2Options {
+    /// Creates options for a reboot of a type, keeping the current
+    /// architecture.
+    pub fn new(reboot_type: Reboot2Type) -> Self {
+        Reboot2Options {
+            reboot_type,
+            arch: None,
+            no_return_on_success: false,
+        }
+    }
+
+    /// Switches the cores to an architecture on reboot.
+    pub fn with_arch(mut self, arch: CpuArch) -> Self {
+        self.arch = Some(arch);
+        self
+    }
+
+    /// Sets whether the device does not return from the command when the
+    /// reboot succeeds.
+    pub fn with_no_return_on_success(mut self, no_return: bool) -> Self {
+        self.no_return_on_success = no_return;
+        self
+    }
+
+    /// Returns the boot type.
+    pub fn get_reboot_type(&self) -> Reboot2Type {
+        self.reboot_type
+    }
+
+    /// Returns the architecture the cores switch to, or `None` to keep the
+    /// current one.
+    pub fn get_arch(&self) -> Option<CpuArch> {
+        self.arch
+    }
+
+    /// Returns `true` if the device does not return from the command when
+    /// the reboot succeeds.
+    pub fn is_no_return_on_success(&self) -> bool {
+        self.no_return_on_success
+    }
+
+    /// Encodes the options as the flags, p0 and p1 args of REBOOT2.
+    pub fn to_args(&self) -> (u32, u32, u32) {
+        let (reboot_type, p0, p1) = match self.reboot_type {
+            Reboot2Type::Normal => (REBOOT2_FLAG_REBOOT_TYPE_NORMAL, 0, 0),
+            Reboot2Type::Bootsel {
+                disable_msd,
+                disable_picoboot,
+                led_gpio,
+                led_active_low,
+            } => {
+                let mut p0 = 0;
+                if disable_msd {
+                    p0 |= BOOTSEL_FLAG_DISABLE_MSD_INTERFACE;
+                }
+                if disable_picoboot {
+                    p0 |= BOOTSEL_FLAG_DISABLE_PICOBOOT_INTERFACE;
+                }
+                if led_gpio.is_some() {
+                    p0 |= BOOTSEL_FLAG_GPIO_PIN_SPECIFIED;
+                }
+                if led_active_low {
+                    p0 |= BOOTSEL_FLAG_GPIO_PIN_ACTIVE_LOW;
+                }
+                let p1 = u32::from(led_gpio.unwrap_or(0));
+                (REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL, p0, p1)
+            }
+            Reboot2Type::RamImage { base, size } => {
+                (REBOOT2_FLAG_REBOOT_TYPE_RAM_IMAGE, base, size)
+            }
+            Reboot2Type::FlashUpdate { buffer } => {
+                (REBOOT2_FLAG_REBOOT_TYPE_FLASH_UPDATE, buffer, 0)
+            }
+            Reboot2Type::PcSp { pc, sp } => (REBOOT2_FLAG_REBOOT_TYPE_PC_SP, pc, sp),
+        };
+
+        let mut flags = reboot_type;
+        match self.arch {
+            Some(CpuArch::Arm) => flags |= REBOOT2_FLAG_REBOOT_TO_ARM,
+            Some(CpuArch::RiscV) => flags |= REBOOT2_FLAG_REBOOT_TO_RISCV,
+            None => {}
+        }
+        if self.no_return_on_success {
+            flags |= REBOOT2_FLAG_NO_RETURN_ON_SUCCESS;
+        }
+        (flags, p0, p1)
+    }
+
+    /// Decodes the flags, p0 and p1 args of REBOOT2.
+    ///
+    /// Returns `None` if the reboot type is not known, or both architectures
+    /// are requested.
+    pub fn from_args(flags: u32, p0: u32, p1: u32) -> Option<Self> {
+        let reboot_type = match flags & REBOOT2_TYPE_MASK {
+            REBOOT2_FLAG_REBOOT_TYPE_NORMAL => Reboot2Type::Normal,
+            REBOOT2_FLAG_REBOOT_TYPE_BOOTSEL => Reboot2Type::Bootsel {
+                disable_msd: p0 & BOOTSEL_FLAG_DISABLE_MSD_INTERFACE != 0,
+                disable_picoboot: p0 & BOOTSEL_FLAG_DISABLE_PICOBOOT_INTERFACE != 0,
+                led_gpio: match p0 & BOOTSEL_FLAG_GPIO_PIN_SPECIFIED {
+                    0 => None,
+                    _ => Some(p1 as u8),
+                },
+                led_active_low: p0 & BOOTSEL_FLAG_GPIO_PIN_ACTIVE_LOW != 0,
+            },
+            REBOOT2_FLAG_REBOOT_TYPE_RAM_IMAGE => Reboot2Type::RamImage { base: p0, size: p1 },
+            REBOOT2_FLAG_REBOOT_TYPE_FLASH_UPDATE => Reboot2Type::FlashUpdate { buffer: p0 },
+            REBOOT2_FLAG_REBOOT_TYPE_PC_SP => Reboot2Type::PcSp { pc: p0, sp: p1 },
+            _ => return None,
+        };
+
+        let arch = match flags & (REBOOT2_FLAG_REBOOT_TO_ARM | REBOOT2_FLAG_REBOOT_TO_RISCV) {
+            0 => None,
+            REBOOT2_FLAG_REBOOT_TO_ARM => Some(CpuArch::Arm),
+            REBOOT2_FLAG_REBOOT_TO_RISCV => Some(CpuArch::RiscV),
+            _ => return None,
+        };
+
+        Some(Reboot2Options {
+            reboot_type,
+            arch,
+            no_return_on_success: flags & REBOOT2_FLAG_NO_RETURN_ON_SUCCESS != 0,
+        })
+    }
+}
+impl fmt::Display for Reboot2Options {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reboot_type {
+            Reboot2Type::Normal => write!(f, "normal")?,
+            Reboot2Type::Bootsel {
+                disable_msd,
+                disable_picoboot,
+                led_gpio,
+                led_active_low,
+            } => {
+                write!(f, "bootsel")?;
+                if disable_msd {
+                    write!(f, " no-msd")?;
+                }
+                if disable_picoboot {
+                    write!(f, " no-picoboot")?;
+                }
+                if let Some(gpio) = led_gpio {
+                    let level = if led_active_low { "low" } else { "high" };
+                    write!(f, " led={} active-{}", gpio, level)?;
+                }
+            }
+            Reboot2Type::RamImage { base, size } => {
+                write!(f, "ram_image base={:#010x} size={:#x}", base, size)?
+            }
+            Reboot2Type::FlashUpdate { buffer } => {
+                write!(f, "flash_update buffer={:#010x}", buffer)?
+            }
+            Reboot2Type::PcSp { pc, sp } => write!(f, "pc_sp pc={:#010x} sp={:#010x}", pc, sp)?,
+        }
+        match self.arch {
+            Some(CpuArch::Arm) => write!(f, " to-arm")?,
+            Some(CpuArch::RiscV) => write!(f, " to-riscv")?,
+            None => {}
+        }
+        if self.no_return_on_success {
+            write!(f, " no-return")?;
+        }
+        Ok(())
+    }
+}
+
+/// Command status reported by the device through the GET_COMMAND_STATUS
+/// control request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PicobootStatusCmd {
+    token: u32,
+    status_code: u32,
+    cmd_id: u8,
+    in_progress: u8,
+}
+impl PicobootStatusCmd {
+    /// Creates a command status for the command with a token and command id.
+    pub fn new(token: u32, status: PicobootStatus, cmd_id: u8, in_progress: bool) -> Self {
+        PicobootStatusCmd {
+            token,
+            status_code: status as u32,
+            cmd_id,
+            in_progress: in_progress as u8,
+        }
+    }
+
+    /// Encodes the command status as 16 bytes.
+    pub fn to_bytes(&self) -> [u8; PICOBOOT_STATUS_SIZE] {
+        let mut buf = [0; PICOBOOT_STATUS_SIZE];
+        put_word(&mut buf, 0, self.token);
+        put_word(&mut buf, 4, self.status_code);
+        buf[8] = self.cmd_id;
+        buf[9] = self.in_progress;
+        buf
+    }
+
+    /// Decodes a 16 byte command status.
+    ///
+    /// # Errors:
+    /// - [`ProtocolError::PacketLengthInvalid`]
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ProtocolError> {
+        if buf.len() != PICOBOOT_STATUS_SIZE {
+            return Err(ProtocolError::PacketLengthInvalid {
+                expected: PICOBOOT_STATUS_SIZE,
+                got: buf.len(),
+            });
+        }
+
+        Ok(PicobootStatusCmd {
+            token: word(buf, 0),
+            status_code: word(buf, 4),
+            cmd_id: buf[8],
+            in_progress: buf[9],
+        })
+    }
+
+    pub fn get_token(&self) -> u32 {
+        self.token
+    }
+
+    /// Returns the status code reported by the device.
+    ///
+    /// # Errors:
+    /// - [`ProtocolError::UnknownStatusCode`]
+    pub fn get_status_code(&self) -> Result<PicobootStatus, ProtocolError> {
+        self.status_code
+            .try_into()
+            .map_err(|_| ProtocolError::UnknownStatusCode(self.status_code))
+    }
+
+    pub fn get_cmd_id(&self) -> u8 {
+        self.cmd_id
+    }
+
+    pub fn get_in_progress(&self) -> u8 {
+        self.in_progress
+    }
+}
+impl fmt::Display for PicobootStatusCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "token={} cmd={:#04x} ", self.token, self.cmd_id)?;
+        match PicobootStatus::try_from(self.status_code) {
+            Ok(status) => write!(f, "{:?}", status)?,
+            Err(_) => write!(f, "Status({})", self.status_code)?,
+        }
+        if self.in_progress != 0 {
+            write!(f, " (in progress)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Command structure for PICOBOOT interface.
+///
+/// This structure contains shorthands for creating commands but does not do any
+/// sort of runtime checks to ensure safe use of these commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PicobootCmd {
+    /// Magic number ([`PICOBOOT_MAGIC`]) to identify the command for the PICOBOOT interface.
+    magic: u32,
+    /// Token number to uniquely identify commands and their responses.
+    token: u32,
+    /// Command ID ([`PicobootCmdId`]) to tell what command the data is to be used for. The top bit (0x80) indicates data transfer direction.
+    cmd_id: u8,
+    /// Command size, number of bytes to read from the `args` field.
+    cmd_size: u8,
+    /// Transfer length, the number of bytes expected to send or recieve over the bulk endpoint(s).
+    transfer_len: u32,
+    /// Command specific args, padded with zeros.
+    args: [u8; 16],
+}
+impl PicobootCmd {
+    /// Creates a new PicobootCmd
+    pub fn new(cmd_id: PicobootCmdId, cmd_size: u8, transfer_len: u32, args: [u8; 16]) -> Self {
+        PicobootCmd {
+            magic: PICOBOOT_MAGIC,
+            token: 0,
+            cmd_id: cmd_id as u8,
+            cmd_size,
+            transfer_len,
+            args,
+        }
+    }
+
+    pub fn set_token(mut self, token: u32) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub fn get_token(&self) -> u32 {
+        self.token
+    }
+
+    pub fn get_transfer_len(&self) -> u32 {
+        self.transfer_len
+    }
+
+    /// Returns the command id.
+    ///
+    /// # Errors:
+    /// - [`ProtocolError::UnknownCmdId`]
+    pub fn get_cmd_id(&self) -> Result<PicobootCmdId, ProtocolError> {
+        PicobootCmdId::try_from(self.cmd_id).map_err(|_| ProtocolError::UnknownCmdId(self.cmd_id))
+    }
+
+    /// Decodes a 32 byte command packet.
+    ///
+    /// Commands with an unknown command id are decoded, and report the id
+    /// as an error from [`Self::get_cmd_id`].
+    ///
+    /// # Errors:
+    /// - [`ProtocolError::PacketLengthInvalid`]
+    /// - [`ProtocolError::MagicInvalid`]
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ProtocolError> {
+        if buf.len() != PICOBOOT_CMD_SIZE {
+            return Err(ProtocolError::PacketLengthInvalid {
+                expected: PICOBOOT_CMD_SIZE,
+                got: buf.len(),
+            });
+        }
+
+        let magic = word(buf, 0);
+        if magic != PICOBOOT_MAGIC {
+            return Err(ProtocolError::MagicInvalid(magic));
+        }
+
+        let mut args = [0; 16];
+        args.copy_from_slice(&buf[16..32]);
+        Ok(PicobootCmd {
+            magic,
+            token: word(buf, 4),
+            cmd_id: buf[8],
+            cmd_size: buf[9],
+            transfer_len: word(buf, 12),
+            args,
+        })
+    }
+
+    /// Encodes the command as a 32 byte command packet.
+    pub fn to_bytes(&self) -> [u8; PICOBOOT_CMD_SIZE] {
+        let mut buf = [0; PICOBOOT_CMD_SIZE];
+        put_word(&mut buf, 0, self.magic);
+        put_word(&mut buf, 4, self.token);
+        buf[8] = self.cmd_id;
+        buf[9] = self.cmd_size;
+        put_word(&mut buf, 12, self.transfer_len);
+        buf[16..32].copy_from_slice(&self.args);
+        buf
+    }
+
+    /// Returns the raw command id byte, which may not be a known
+    /// [`PicobootCmdId`].
+    pub fn get_raw_cmd_id(&self) -> u8 {
+        self.cmd_id
+    }
+
+    /// Returns `true` if the data phase of the command is sent from the
+    /// device to the host.
+    pub fn is_in(&self) -> bool {
+        self.cmd_id & 0x80 != 0
+    }
+
+    /// Returns the number of bytes used in the args field.
+    pub fn get_cmd_size(&self) -> u8 {
+        self.cmd_size
+    }
+
+    /// Returns the command specific args.
+    pub fn get_args(&self) -> [u8; 16] {
+        self.args
+    }
+
+    /// Returns the memory range a command operates on, as an address and size.
+    ///
+    /// Commands without a memory range report an address of zero and their
+    /// transfer length as the size.
+    pub fn get_range(&self) -> (u32, u32) {
+        let range = PicobootRangeCmd::from_args(&self.args);
+        match self.get_cmd_id() {
+            Ok(PicobootCmdId::FlashErase | PicobootCmdId::Read | PicobootCmdId::Write) => {
+                (range.addr, range.size)
+            }
+            Ok(PicobootCmdId::Exec | PicobootCmdId::VectorizeFlash) => (range.addr, 0),
+            _ => (0, self.transfer_len),
+        }
+    }
+
+    /// Creates an EXCLUSIVE_ACCESS command
+    pub fn exclusive_access(exclusive: u8) -> Self {
+        let mut args = [0; 16];
+        args[0] = exclusive;
+        PicobootCmd::new(PicobootCmdId::ExclusiveAccess, 1, 0, args)
+    }
+
+    /// Creates a REBOOT command
+    pub fn reboot(pc: u32, sp: u32, delay: u32) -> Self {
+        let args = PicobootRebootCmd::ser(pc, sp, delay);
+        PicobootCmd::new(PicobootCmdId::Reboot, 12, 0, args)
+    }
+
+    /// Creates a REBOOT2 command (normal boot)
+    pub fn reboot2_normal(delay: u32) -> Self {
+        Self::reboot2(Reboot2Options::new(Reboot2Type::Normal), delay)
+    }
+
+    /// Creates a REBOOT2 command
+    pub fn reboot2(options: Reboot2Options, delay: u32) -> Self {
+        let (flags, p0, p1) = options.to_args();
+        let args = PicobootReboot2Cmd::ser(flags, delay, p0, p1);
+        PicobootCmd::new(PicobootCmdId::Reboot2, 0x10, 0, args)
+    }
+
+    /// Creates a FLASH_ERASE command
+    pub fn flash_erase(addr: u32, size: u32) -> Self {
+        let args = PicobootRangeCmd::ser(addr, size);
+        PicobootCmd::new(PicobootCmdId::FlashErase, 8, 0, args)
+    }
+
+    /// Creates a WRITE command
+    pub fn flash_write(addr: u32, size: u32) -> Self {
+        let args = PicobootRangeCmd::ser(addr, size);
+        PicobootCmd::new(PicobootCmdId::Write, 8, size, args)
+    }
+
+    /// Creates a READ command
+    pub fn flash_read(addr: u32, size: u32) -> Self {
+        let args = PicobootRangeCmd::ser(addr, size);
+        PicobootCmd::new(PicobootCmdId::Read, 8, size, args)
+    }
+
+    /// Creates a GET_INFO command
+    ///
+    /// - `info_type` - Type of information requested, such as [`GET_INFO_SYS`].
+    /// - `params` - Type specific parameters, such as the INFO_SYS flags.
+    /// - `size` - Size of the response buffer, at most [`GET_INFO_MAX_SIZE`].
+    pub fn get_info(info_type: u8, params: [u32; 3], size: u32) -> Self {
+        let args = PicobootGetInfoCmd::ser(info_type, params);
+        PicobootCmd::new(PicobootCmdId::GetInfo, 0x10, size, args)
+    }
+
+    /// Creates a GET_INFO command for system information (INFO_SYS)
+    ///
+    /// - `flags` - `SYS_INFO_*` flags of the information requested.
+    pub fn get_info_sys(flags: u32) -> Self {
+        Self::get_info(GET_INFO_SYS, [flags, 0, 0], GET_INFO_MAX_SIZE)
+    }
+
+    /// Creates a GET_INFO command for partition table information
+    /// (INFO_PARTITION_TABLE)
+    ///
+    /// - `flags` - `PT_INFO_*` flags of the information requested.
+    /// - `partition` - Partition to return if `flags` has
+    ///   [`PT_INFO_SINGLE_PARTITION`], otherwise ignored.
+    pub fn get_info_partition_table(flags: u32, partition: u8) -> Self {
+        let flags = match flags & PT_INFO_SINGLE_PARTITION {
+            0 => flags,
+            _ => (flags & 0x00FFFFFF) | u32::from(partition) << 24,
+        };
+        Self::get_info(GET_INFO_PARTITION_TABLE, [flags, 0, 0], GET_INFO_MAX_SIZE)
+    }
+
+    /// Creates a GET_INFO command for the partition a UF2 of a family would
+    /// be downloaded to (INFO_UF2_TARGET_PARTITION)
+    pub fn get_info_uf2_target_partition(family_id: u32) -> Self {
+        Self::get_info(
+            GET_INFO_UF2_TARGET_PARTITION,
+            [family_id, 0, 0],
+            GET_INFO_MAX_SIZE,
+        )
+    }
+
+    /// Creates a GET_INFO command for the status of the last UF2 download
+    /// (INFO_UF2_STATUS)
+    pub fn get_info_uf2_status() -> Self {
+        Self::get_info(GET_INFO_UF2_STATUS, [0, 0, 0], GET_INFO_MAX_SIZE)
+    }
+
+    /// Creates an EXEC command
+    pub fn exec(addr: u32) -> Self {
+        let args = PicobootRangeCmd::ser(addr, 0);
+        PicobootCmd::new(PicobootCmdId::Exec, 4, 0, args)
+    }
+
+    /// Creates a VECTORIZE_FLASH command
+    pub fn vectorize_flash(addr: u32) -> Self {
+        let args = PicobootRangeCmd::ser(addr, 0);
+        PicobootCmd::new(PicobootCmdId::VectorizeFlash, 4, 0, args)
+    }
+
+    /// Creates an OTP_READ command
+    pub fn otp_read(row: u16, row_count: u16, ecc: bool) -> Self {
+        let args = PicobootOtpCmd::ser(row, row_count, ecc);
+        let size = PicobootOtpCmd::from_args(&args).get_row_size() * u32::from(row_count);
+        PicobootCmd::new(PicobootCmdId::OtpRead, 5, size, args)
+    }
+
+    /// Creates an OTP_WRITE command
+    pub fn otp_write(row: u16, row_count: u16, ecc: bool) -> Self {
+        let args = PicobootOtpCmd::ser(row, row_count, ecc);
+        let size = PicobootOtpCmd::from_args(&args).get_row_size() * u32::from(row_count);
+        PicobootCmd::new(PicobootCmdId::OtpWrite, 5, size, args)
+    }
+
+    /// Creates an ENTER_XIP command
+    pub fn enter_xip() -> Self {
+        PicobootCmd::new(PicobootCmdId::EnterCmdXip, 0, 0, [0; 16])
+    }
+
+    /// Creates an EXIT_XIP command
+    pub fn exit_xip() -> Self {
+        PicobootCmd::new(PicobootCmdId::ExitXip, 0, 0, [0; 16])
+    }
+}
+impl fmt::Display for PicobootCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.get_cmd_id();
+        match id {
+            Ok(id) => write!(f, "{:?} token={}", id, self.token)?,
+            Err(_) => write!(f, "Cmd({:#04x}) token={}", self.cmd_id, self.token)?,
+        }
+
+        match id {
+            Ok(PicobootCmdId::ExclusiveAccess) => write!(f, " exclusive={}", self.args[0])?,
+            Ok(PicobootCmdId::Reboot) => {
+                write!(f, " {}", PicobootRebootCmd::from_args(&self.args))?
+            }
+            Ok(PicobootCmdId::Reboot2) => {
+                write!(f, " {}", PicobootReboot2Cmd::from_args(&self.args))?
+            }
+            Ok(PicobootCmdId::FlashErase | PicobootCmdId::Read | PicobootCmdId::Write) => {
+                write!(f, " {}", PicobootRangeCmd::from_args(&self.args))?
+            }
+            Ok(PicobootCmdId::Exec | PicobootCmdId::VectorizeFlash) => {
+                let addr = PicobootRangeCmd::from_args(&self.args).get_addr();
+                write!(f, " addr={:#010x}", addr)?
+            }
+            Ok(PicobootCmdId::OtpRead | PicobootCmdId::OtpWrite) => {
+                write!(f, " {}", PicobootOtpCmd::from_args(&self.args))?
+            }
+            Ok(PicobootCmdId::GetInfo) => {
+                write!(f, " {}", PicobootGetInfoCmd::from_args(&self.args))?
+            }
+            Ok(PicobootCmdId::ExitXip | PicobootCmdId::EnterCmdXip) => {}
+            _ => {
+                let size = usize::from(self.cmd_size).min(self.args.len());
+                write!(f, " args=")?;
+                for b in &self.args[..size] {
+                    write!(f, "{:02x}", b)?;
+                }
+            }
+        }
+
+        if self.transfer_len != 0 {
+            let dir = if self.is_in() { "in" } else { "out" };
+            write!(f, " transfer_len={:#x} {}", self.transfer_len, dir)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{cmd::PicobootError, hex::decode_hex, image::SparseImage};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// Number of data bytes written per data record
+const SREC_RECORD_SIZE: usize = 16;
+/// Largest header, filling an S0 record
+const SREC_MAX_HEADER_SIZE: usize = 252;
+
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Parsed Motorola S-record file.
+///
+/// Supports data records with 16-bit (S1), 24-bit (S2) and 32-bit (S3)
+/// addresses, record counts (S5, S6) and start addresses (S7, S8, S9).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SrecFile {
+    image: SparseImage,
+    header: Vec<u8>,
+    start_addr: Option<u32>,
+}
+impl SrecFile {
+    /// Parses a Motorola S-record file, checking the checksum of every record
+    /// and any record count.
+    ///
+    /// Blank lines are ignored. The file must end with a start address (S7,
+    /// S8 or S9) record.
+    ///
+    /// # Errors:
+    /// - [`Error::SrecFormatInvalid`]
+    /// - [`Error::SrecChecksumMismatch`]
+    /// - [`Error::SrecEndMissing`]
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut image = SparseImage::new();
+        let mut header = vec![];
+        let mut start_addr = None;
+        let mut records = 0u32;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if start_addr.is_some() {
+                return Err(Error::SrecFormatInvalid(line_no));
+            }
+
+            let kind = line
+                .strip_prefix('S')
+                .and_then(|l| l.chars().next())
+                .ok_or(Error::SrecFormatInvalid(line_no))?;
+            let addr_len = match kind {
+                '0' | '1' | '5' | '9' => 2,
+                '2' | '6' | '8' => 3,
+                '3' | '7' => 4,
+                _ => return Err(Error::SrecFormatInvalid(line_no)),
+            };
+            let bytes = decode_hex(&line[2..])
+                .filter(|b| b.len() >= addr_len + 2 && b.len() == 1 + b[0] as usize)
+                .ok_or(Error::SrecFormatInvalid(line_no))?;
+            let (sum, rest) = bytes.split_last().unwrap();
+            if checksum(rest) != *sum {
+                return Err(Error::SrecChecksumMismatch(line_no));
+            }
+
+            let addr = bytes[1..1 + addr_len]
+                .iter()
+                .fold(0u32, |addr, b| addr << 8 | u32::from(*b));
+            let data = &rest[1 + addr_len..];
+            match kind {
+                '0' => header = data.to_vec(),
+                '1' | '2' | '3' => {
+                    image.write(addr, data);
+                    records += 1;
+                }
+                '5' | '6' if data.is_empty() && addr == records => {}
+                '7' | '8' | '9' if data.is_empty() => start_addr = Some(addr),
+                _ => return Err(Error::SrecFormatInvalid(line_no)),
+            }
+        }
+
+        if start_addr.is_none() {
+            return Err(Error::SrecEndMissing);
+        }
+        Ok(SrecFile {
+            image,
+            header,
+            start_addr,
+        })
+    }
+
+    /// Creates a Motorola S-record file from an image.
+    pub fn from_image(image: &SparseImage) -> Self {
+        SrecFile {
+            image: image.clone(),
+            header: vec![],
+            start_addr: None,
+        }
+    }
+
+    /// Sets the data of the header (S0) record, usually a file or module
+    /// name. Only the first 252 bytes are kept.
+    pub fn with_header(mut self, header: &[u8]) -> Self {
+        self.header = header[..header.len().min(SREC_MAX_HEADER_SIZE)].to_vec();
+        self
+    }
+
+    /// Sets the start (entry point) address, written in the termination
+    /// record.
+    pub fn with_start_addr(mut self, addr: u32) -> Self {
+        self.start_addr = Some(addr);
+        self
+    }
+
+    /// Serializes the file, with 16 data bytes per record.
+    ///
+    /// Data records use the smallest address size that fits every address,
+    /// and are followed by a record count and a termination record, with a
+    /// start address of zero if the file has none.
+    pub fn ser(&self) -> String {
+        let start_addr = self.start_addr.unwrap_or(0);
+        let end = self.image.get_end().unwrap_or(0).max(start_addr.into());
+        let (addr_len, data_kind, end_kind) = if end <= 0x10000 {
+            (2, '1', '9')
+        } else if end <= 0x1000000 {
+            (3, '2', '8')
+        } else {
+            (4, '3', '7')
+        };
+
+        let mut out = String::new();
+        write_record(&mut out, '0', 2, 0, &self.header);
+        let mut records = 0u32;
+        for (addr, seg) in self.image.get_segments() {
+            for (i, chunk) in seg.chunks(SREC_RECORD_SIZE).enumerate() {
+                let at = addr + (i * SREC_RECORD_SIZE) as u32;
+                write_record(&mut out, data_kind, addr_len, at, chunk);
+                records += 1;
+            }
+        }
+        if records <= 0xFFFF {
+            write_record(&mut out, '5', 2, records, &[]);
+        } else if records <= 0xFFFFFF {
+            write_record(&mut out, '6', 3, records, &[]);
+        }
+        write_record(&mut out, end_kind, addr_len, start_addr, &[]);
+        out
+    }
+
+    pub fn get_image(&self) -> &SparseImage {
+        &self.image
+    }
+
+    pub fn into_image(self) -> SparseImage {
+        self.image
+    }
+
+    /// Returns the data of the header (S0) record.
+    pub fn get_header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Returns the start (entry point) address, if the file has one.
+    pub fn get_start_addr(&self) -> Option<u32> {
+        self.start_addr
+    }
+}
+
+fn write_record(out: &mut String, kind: char, addr_len: usize, addr: u32, data: &[u8]) {
+    let mut bytes = vec![(addr_len + data.len() + 1) as u8];
+    bytes.extend_from_slice(&addr.to_be_bytes()[4 - addr_len..]);
+    bytes.extend_from_slice(data);
+    bytes.push(checksum(&bytes));
+
+    out.push('S');
+    out.push(kind);
+    for b in bytes {
+        out.push_str(&format!("{:02X}", b));
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<&str> {
+        text.lines().collect()
+    }
+
+    #[test]
+    fn round_trip_s1() {
+        let image = SparseImage::new().with_data(0x1000, &[0, 1, 2, 3]);
+        let text = SrecFile::from_image(&image).with_header(b"blink").ser();
+        assert_eq!(
+            lines(&text),
+            [
+                "S0080000626C696E6BE7",
+                "S107100000010203E2",
+                "S5030001FB",
+                "S9030000FC"
+            ]
+        );
+
+        let file = SrecFile::parse(&text).unwrap();
+        assert_eq!(file.get_image(), &image);
+        assert_eq!(file.get_header(), b"blink");
+        assert_eq!(file.get_start_addr(), Some(0));
+    }
+
+    #[test]
+    fn round_trip_s2() {
+        let image = SparseImage::new().with_data(0x10000, &[0xAA]);
+        let text = SrecFile::from_image(&image).ser();
+        assert_eq!(
+            lines(&text),
+            ["S0030000FC", "S205010000AA4F", "S5030001FB", "S804000000FB"]
+        );
+        assert_eq!(SrecFile::parse(&text).unwrap().into_image(), image);
+    }
+
+    #[test]
+    fn round_trip_s3() {
+        let image = SparseImage::new().with_data(0x1000_0000, &[0xBB]);
+        let text = SrecFile::from_image(&image)
+            .with_start_addr(0x1000_00ED)
+            .ser();
+        assert_eq!(
+            lines(&text),
+            [
+                "S0030000FC",
+                "S30610000000BB2E",
+                "S5030001FB",
+                "S705100000EDFD"
+            ]
+        );
+
+        let file = SrecFile::parse(&text).unwrap();
+        assert_eq!(file.get_image(), &image);
+        assert_eq!(file.get_start_addr(), Some(0x1000_00ED));
+    }
+
+    #[test]
+    fn start_addr_selects_address_size() {
+        let image = SparseImage::new().with_data(0, &[1, 2]);
+        let text = SrecFile::from_image(&image)
+            .with_start_addr(0x2000_0000)
+            .ser();
+        assert_eq!(
+            lines(&text),
+            [
+                "S0030000FC",
+                "S307000000000102F5",
+                "S5030001FB",
+                "S70520000000DA"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_checksum_mismatch() {
+        let text = "S107100000010203E3\nS9030000FC\n";
+        assert!(matches!(
+            SrecFile::parse(text),
+            Err(Error::SrecChecksumMismatch(1))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_records() {
+        for text in [
+            // truncated record
+            "S1071000000102\nS9030000FC\n",
+            // sign in place of a digit
+            "S1+7100000010203E2\nS9030000FC\n",
+            // unknown record type
+            "SA030000FC\nS9030000FC\n",
+            // data in a termination record
+            "S9040000AA51\n",
+        ] {
+            assert!(matches!(
+                SrecFile::parse(text),
+                Err(Error::SrecFormatInvalid(1))
+            ));
+        }
+
+        // wrong record count
+        let text = "S107100000010203E2\nS5030002FA\nS9030000FC\n";
+        assert!(matches!(
+            SrecFile::parse(text),
+            Err(Error::SrecFormatInvalid(2))
+        ));
+
+        // records after the termination record
+        let text = "S9030000FC\nS107100000010203E2\n";
+        assert!(matches!(
+            SrecFile::parse(text),
+            Err(Error::SrecFormatInvalid(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_end() {
+        assert!(matches!(
+            SrecFile::parse("S107100000010203E2\nS5030001FB\n"),
+            Err(Error::SrecEndMissing)
+        ));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    device::{PicobootHandler, PicobootResponder, ResponderError},
+    transport::{Endpoint, PicobootTransport, TransportError},
+};
+
+use std::time::Duration;
+
+type Result<T> = ::std::result::Result<T, TransportError>;
+
+impl From<ResponderError> for TransportError {
+    fn from(e: ResponderError) -> Self {
+        match e {
+            ResponderError::Stall => TransportError::Stall,
+            // the host would wait for a packet until its timeout
+            ResponderError::Nak => TransportError::Timeout,
+        }
+    }
+}
+
+/// An in-memory transport connected to a [`PicobootResponder`]
+///
+/// Every transfer is handed directly to the responder, so a device-side
+/// [`PicobootHandler`] can be exercised against a
+/// [`crate::PicobootConnection`] on the host.
+#[derive(Debug)]
+pub struct LoopbackTransport<H: PicobootHandler> {
+    responder: PicobootResponder<H>,
+    iface_num: u8,
+}
+impl<H: PicobootHandler> LoopbackTransport<H> {
+    /// Creates a new loopback transport to a responder.
+    ///
+    /// The PICOBOOT interface number defaults to 1, as on RP2040 and RP2350.
+    pub fn new(responder: PicobootResponder<H>) -> Self {
+        LoopbackTransport {
+            responder,
+            iface_num: 1,
+        }
+    }
+
+    /// Sets the interface number reported to the connection.
+    pub fn with_interface_number(mut self, iface_num: u8) -> Self {
+        self.iface_num = iface_num;
+        self
+    }
+
+    /// Returns a reference to the responder.
+    pub fn get_responder(&self) -> &PicobootResponder<H> {
+        &self.responder
+    }
+
+    /// Returns a mutable reference to the responder.
+    pub fn get_responder_mut(&mut self) -> &mut PicobootResponder<H> {
+        &mut self.responder
+    }
+
+    /// Consumes the transport, returning the responder.
+    pub fn into_responder(self) -> PicobootResponder<H> {
+        self.responder
+    }
+}
+impl<H: PicobootHandler> PicobootTransport for LoopbackTransport<H> {
+    fn read_bulk(&mut self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        Ok(self.responder.bulk_in(buf)?)
+    }
+
+    fn write_bulk(&mut self, buf: &[u8], _timeout: Duration) -> Result<usize> {
+        Ok(self.responder.bulk_out(buf)?)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        Ok(self
+            .responder
+            .control_in(request_type, request, value, index, buf)?)
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        Ok(self
+            .responder
+            .control_out(request_type, request, value, index, buf)?)
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> Result<()> {
+        match endpoint {
+            Endpoint::In => self.responder.clear_halt_in(),
+            Endpoint::Out => self.responder.clear_halt_out(),
+        }
+        Ok(())
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.iface_num
+    }
+}
@@ -0,0 +1,269 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cmd::{PicobootError, TargetID};
+
+/// rusb (libusb) Transport Module
+#[cfg(feature = "rusb")]
+pub mod rusb;
+#[cfg(feature = "rusb")]
+pub use self::rusb::{DeviceWatcher, PicobootDevice, RusbTransport};
+
+/// Record and Replay Transport Module
+pub mod record;
+pub use self::record::{Recording, RecordingTransport, ReplayTransport};
+
+/// Loopback Transport Module
+pub mod loopback;
+pub use self::loopback::LoopbackTransport;
+
+/// nusb (pure Rust) Transport Module
+#[cfg(feature = "nusb")]
+pub mod nusb;
+#[cfg(feature = "nusb")]
+pub use self::nusb::{wait_for_device as nusb_wait_for_device, NusbDeviceWatcher, NusbTransport};
+
+/// Error type for transport operations.
+///
+/// This is independent of any particular USB library so that in-memory or
+/// recorded transports can report the same failures a real device would.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransportError {
+    /// Operation timed out.
+    #[error("operation timed out")]
+    Timeout,
+    /// Endpoint stalled.
+    #[error("endpoint stalled")]
+    Stall,
+    /// Device is no longer connected.
+    #[error("device disconnected")]
+    Disconnected,
+    /// Insufficient permissions to access the device.
+    #[error("access denied")]
+    AccessDenied,
+    /// Device or interface is in use.
+    #[error("resource busy")]
+    Busy,
+    /// Device sent more data than was requested.
+    #[error("transfer overflow")]
+    Overflow,
+    /// Requested entity was not found.
+    #[error("entity not found")]
+    NotFound,
+    /// Operation is not supported by the transport.
+    #[error("operation not supported")]
+    Unsupported,
+    /// Any other input/output error.
+    #[error("input/output error")]
+    Io,
+    /// A replayed transfer differs from the recorded transfer at this index.
+    #[error("transfer {0} diverged from the recording")]
+    ReplayDiverged(usize),
+}
+
+/// Description of an attached PICOBOOT device.
+///
+/// Produced when enumerating devices, before any PICOBOOT interface is
+/// claimed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub(crate) bus_number: u8,
+    pub(crate) address: u8,
+    pub(crate) port_numbers: Vec<u8>,
+    pub(crate) vendor_id: u16,
+    pub(crate) product_id: u16,
+    pub(crate) serial_number: Option<String>,
+    pub(crate) target_id: Option<TargetID>,
+    pub(crate) openable: bool,
+}
+impl DeviceInfo {
+    /// Returns the number of the USB bus the device is attached to.
+    pub fn get_bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// Returns the address of the device on its USB bus.
+    pub fn get_address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the chain of hub port numbers leading from the root hub to the
+    /// device.
+    pub fn get_port_numbers(&self) -> &[u8] {
+        &self.port_numbers
+    }
+
+    /// Returns the USB Vendor ID of the device.
+    pub fn get_vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// Returns the USB Product ID of the device.
+    pub fn get_product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// Returns the USB serial number string of the device, if it could be
+    /// read.
+    pub fn get_serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    /// Returns the device type guessed from the VID/PID pair, or `None` for
+    /// pairs other than the Raspberry Pi ones.
+    ///
+    /// The guess is not confirmed until the device is opened, which reads the
+    /// actual type from the device.
+    pub fn get_device_type(&self) -> Option<TargetID> {
+        self.target_id
+    }
+
+    /// Returns `true` if the device could be opened by this process.
+    pub fn is_openable(&self) -> bool {
+        self.openable
+    }
+}
+
+/// Selects which PICOBOOT device to open.
+///
+/// `D` is the device descriptor type of the USB backend, which the
+/// [`Self::Descriptor`] predicate is applied to.
+pub enum DeviceSelector<D> {
+    /// First device with a known PICOBOOT VID/PID pair.
+    Any,
+    /// First device with the given USB VID/PID pair.
+    VidPid(u16, u16),
+    /// Device with a known PICOBOOT VID/PID pair and the given USB serial
+    /// number string (iSerial).
+    Serial(String),
+    /// Device on the given bus, behind the given chain of hub port numbers.
+    Port {
+        /// USB bus number.
+        bus: u8,
+        /// Hub port numbers, starting at the root hub.
+        ports: Vec<u8>,
+    },
+    /// First device for which the predicate over its device descriptor
+    /// returns `true`.
+    Descriptor(Box<dyn Fn(&D) -> bool + Send + Sync>),
+}
+impl<D> DeviceSelector<D> {
+    /// Creates a selector from a device descriptor predicate.
+    pub fn descriptor(f: impl Fn(&D) -> bool + Send + Sync + 'static) -> Self {
+        DeviceSelector::Descriptor(Box::new(f))
+    }
+
+    /// Checks whether a device could match the selector without opening it.
+    ///
+    /// A device that passes may still fail [`Self::matches`], for example on
+    /// its serial number.
+    pub fn may_match(&self, bus: u8, ports: &[u8], vid: u16, pid: u16, desc: &D) -> bool {
+        match self {
+            DeviceSelector::Any | DeviceSelector::Serial(_) => {
+                TargetID::from_usb_ids(vid, pid).is_some()
+            }
+            DeviceSelector::VidPid(v, p) => (*v, *p) == (vid, pid),
+            DeviceSelector::Port { bus: b, ports: p } => *b == bus && p.as_slice() == ports,
+            DeviceSelector::Descriptor(f) => f(desc),
+        }
+    }
+
+    /// Checks whether a probed device matches the selector.
+    pub fn matches(&self, info: &DeviceInfo, desc: &D) -> bool {
+        match self {
+            DeviceSelector::Serial(s) if info.get_serial_number() != Some(s.as_str()) => false,
+            _ => self.may_match(
+                info.bus_number,
+                &info.port_numbers,
+                info.vendor_id,
+                info.product_id,
+                desc,
+            ),
+        }
+    }
+}
+impl<D> std::fmt::Debug for DeviceSelector<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceSelector::Any => write!(f, "Any"),
+            DeviceSelector::VidPid(vid, pid) => write!(f, "VidPid({:04x}:{:04x})", vid, pid),
+            DeviceSelector::Serial(s) => f.debug_tuple("Serial").field(s).finish(),
+            DeviceSelector::Port { bus, ports } => f
+                .debug_struct("Port")
+                .field("bus", bus)
+                .field("ports", ports)
+                .finish(),
+            DeviceSelector::Descriptor(_) => write!(f, "Descriptor(..)"),
+        }
+    }
+}
+
+/// Bulk endpoints of a PICOBOOT interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Endpoint {
+    /// Bulk IN endpoint (device to host).
+    In,
+    /// Bulk OUT endpoint (host to device).
+    Out,
+}
+
+/// Transfer layer underneath a [`crate::PicobootConnection`].
+///
+/// A transport moves raw bytes between the host and a claimed PICOBOOT
+/// interface. It does not know anything about the PICOBOOT command set, which
+/// lets the connection logic run against real hardware (`RusbTransport` or
+/// `NusbTransport`) as well as in-memory or recorded devices.
+pub trait PicobootTransport {
+    /// Reads from the bulk IN endpoint into `buf`, returning the number of
+    /// bytes read.
+    fn read_bulk(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, TransportError>;
+
+    /// Writes `buf` to the bulk OUT endpoint, returning the number of bytes
+    /// written.
+    fn write_bulk(&mut self, buf: &[u8], timeout: Duration) -> Result<usize, TransportError>;
+
+    /// Performs a control IN transfer, returning the number of bytes read.
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> Result<usize, TransportError>;
+
+    /// Performs a control OUT transfer, returning the number of bytes written.
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> Result<usize, TransportError>;
+
+    /// Clears a halt/stall condition on one of the bulk endpoints.
+    fn clear_halt(&mut self, endpoint: Endpoint) -> Result<(), TransportError>;
+
+    /// Returns the interface number of the PICOBOOT interface, used as the
+    /// `wIndex` of interface-directed control transfers.
+    fn interface_number(&self) -> u8;
+
+    /// Releases the PICOBOOT interface and restores any kernel driver that
+    /// was detached from it.
+    ///
+    /// No transfers may be made after the transport is closed. Transports
+    /// that hold no system resources can rely on the default, which does
+    /// nothing.
+    ///
+    /// # Errors
+    /// - [`PicobootError::UsbReleaseInterfaceFailure`]
+    /// - [`PicobootError::UsbAttachKernelDriverFailure`]
+    fn close(&mut self) -> Result<(), PicobootError> {
+        Ok(())
+    }
+}
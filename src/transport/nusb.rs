@@ -0,0 +1,736 @@
+use crate::{
+    cmd::{PicobootError, TargetID},
+    transport::{DeviceInfo, DeviceSelector, Endpoint, PicobootTransport, TransportError},
+    usb::PicobootConnection,
+    PICOBOOT_PID_RP2040, PICOBOOT_PID_RP2350, PICOBOOT_VID,
+};
+
+use ::nusb::{
+    descriptors::TransferType,
+    hotplug::HotplugWatch,
+    transfer::{Buffer, Bulk, ControlIn, ControlOut, ControlType, Direction, In, Out, Recipient},
+    Device, DeviceId, ErrorKind, Interface, MaybeFuture,
+};
+use futures_core::Stream;
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+impl From<::nusb::Error> for TransportError {
+    fn from(e: ::nusb::Error) -> Self {
+        match e.kind() {
+            ErrorKind::Disconnected => TransportError::Disconnected,
+            ErrorKind::Busy => TransportError::Busy,
+            ErrorKind::PermissionDenied => TransportError::AccessDenied,
+            ErrorKind::NotFound => TransportError::NotFound,
+            ErrorKind::Unsupported => TransportError::Unsupported,
+            _ => TransportError::Io,
+        }
+    }
+}
+
+impl From<::nusb::transfer::TransferError> for TransportError {
+    fn from(e: ::nusb::transfer::TransferError) -> Self {
+        use ::nusb::transfer::TransferError as E;
+        match e {
+            // transfers are only cancelled by us when they time out
+            E::Cancelled => TransportError::Timeout,
+            E::Stall => TransportError::Stall,
+            E::Disconnected => TransportError::Disconnected,
+            E::InvalidArgument => TransportError::Unsupported,
+            _ => TransportError::Io,
+        }
+    }
+}
+
+/// A PICOBOOT device found by [`list_devices`]
+///
+/// Holds the unopened nusb device information along with its [`DeviceInfo`],
+/// and can be turned into a connection with [`Self::open`].
+#[derive(Debug)]
+pub struct PicobootDevice {
+    device: ::nusb::DeviceInfo,
+    info: DeviceInfo,
+}
+impl PicobootDevice {
+    /// Inspects a USB device, returning `None` if it does not expose a
+    /// PICOBOOT interface.
+    ///
+    /// The device is opened briefly to check that it is accessible, but no
+    /// interface is claimed.
+    pub fn probe(device: ::nusb::DeviceInfo) -> Option<Self> {
+        device
+            .interfaces()
+            .find(|i| (i.class(), i.subclass(), i.protocol()) == (255, 0, 0))?;
+
+        let openable = device.open().wait().is_ok();
+        let target_id = TargetID::from_usb_ids(device.vendor_id(), device.product_id());
+
+        let info = DeviceInfo {
+            bus_number: bus_number(&device),
+            address: device.device_address(),
+            port_numbers: device.port_chain().to_vec(),
+            vendor_id: device.vendor_id(),
+            product_id: device.product_id(),
+            serial_number: device.serial_number().map(str::to_owned),
+            target_id,
+            openable,
+        };
+
+        Some(PicobootDevice { device, info })
+    }
+
+    /// Returns the description of the device.
+    pub fn get_info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// Returns the underlying nusb device information.
+    pub fn get_device(&self) -> &::nusb::DeviceInfo {
+        &self.device
+    }
+
+    /// Opens the device, claims its PICOBOOT interface and confirms its
+    /// target with [`PicobootConnection::identify`].
+    ///
+    /// # Errors
+    /// - [`Error::UsbDeviceFailedToOpen`]
+    /// - Any produced by [`NusbTransport::claim`]
+    /// - Any produced by [`PicobootConnection::reset_interface`]
+    /// - Any produced by [`PicobootConnection::identify`]
+    pub fn open(&self) -> Result<PicobootConnection<NusbTransport>> {
+        let device = self
+            .device
+            .open()
+            .wait()
+            .map_err(|e| Error::UsbDeviceFailedToOpen(e.into()))?;
+        let transport = NusbTransport::claim(device)?;
+
+        // the actual target is read from the device
+        let target_id = self.info.target_id.unwrap_or(TargetID::Rp2350);
+        PicobootConnection::from_transport(transport, target_id).confirm_target()
+    }
+}
+
+/// Returns the number of the bus a device is attached to.
+///
+/// nusb only reports a numeric bus on some platforms, `0` is used where the
+/// bus identifier is not a number.
+fn bus_number(device: &::nusb::DeviceInfo) -> u8 {
+    device.bus_id().parse().unwrap_or(0)
+}
+
+/// Lists all attached devices with a PICOBOOT interface
+///
+/// Devices with any VID/PID pair are listed, including boards with
+/// white-labelled IDs. Devices are not claimed, so they can be listed while
+/// another process is connected to them.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+pub fn list_devices() -> Result<Vec<PicobootDevice>> {
+    let devices = ::nusb::list_devices()
+        .wait()
+        .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+
+    Ok(devices.filter_map(PicobootDevice::probe).collect())
+}
+
+/// Finds the first attached PICOBOOT device matching a selector
+///
+/// Devices are checked in the order they are enumerated by the operating
+/// system.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+/// - [`Error::UsbDeviceNotFound`]
+pub fn find_device(selector: &DeviceSelector<::nusb::DeviceInfo>) -> Result<PicobootDevice> {
+    let devices = ::nusb::list_devices()
+        .wait()
+        .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+
+    for device in devices {
+        if !selector.may_match(
+            bus_number(&device),
+            device.port_chain(),
+            device.vendor_id(),
+            device.product_id(),
+            &device,
+        ) {
+            continue;
+        }
+
+        if let Some(dev) = PicobootDevice::probe(device) {
+            if selector.matches(&dev.info, &dev.device) {
+                return Ok(dev);
+            }
+        }
+    }
+
+    Err(Error::UsbDeviceNotFound)
+}
+
+/// Waits for a PICOBOOT device matching a selector to be attached
+///
+/// Returns as soon as a matching device is attached and can be opened by this
+/// process, including a device that is already attached when this is called.
+/// Waits forever if `timeout` is `None`.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+/// - [`Error::UsbDeviceNotFound`], if no device appeared before the timeout
+pub fn wait_for_device(
+    selector: DeviceSelector<::nusb::DeviceInfo>,
+    timeout: Option<Duration>,
+) -> Result<PicobootDevice> {
+    let mut watcher = NusbDeviceWatcher::new(selector);
+    if let Some(timeout) = timeout {
+        watcher = watcher.with_timeout(timeout);
+    }
+
+    watcher.next().unwrap_or(Err(Error::UsbDeviceNotFound))
+}
+
+/// Wakes up a [`NusbDeviceWatcher`] parked waiting for a hotplug event.
+struct ThreadWaker(Thread);
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Starts watching hotplug events, where nusb supports it.
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn watch_devices() -> Option<Pin<Box<HotplugWatch>>> {
+    ::nusb::watch_devices().ok().map(Box::pin)
+}
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn watch_devices() -> Option<Pin<Box<HotplugWatch>>> {
+    None
+}
+
+/// An iterator over PICOBOOT devices as they are attached
+///
+/// Each call to [`Iterator::next`] blocks until a device matching the selector
+/// is attached and can be opened by this process, and yields it once. A device
+/// that is detached and attached again, for example after rebooting into
+/// BOOTSEL mode, is yielded again. The iterator ends when the timeout set with
+/// [`Self::with_timeout`] expires.
+///
+/// Attach events are received through [`::nusb::watch_devices`] where the
+/// platform supports it. The attached devices are also polled, as a device
+/// may only become accessible some time after its attach event.
+pub struct NusbDeviceWatcher {
+    selector: DeviceSelector<::nusb::DeviceInfo>,
+    seen: HashSet<DeviceId>,
+    deadline: Option<Instant>,
+    poll_interval: Duration,
+    hotplug: Option<Pin<Box<HotplugWatch>>>,
+}
+impl NusbDeviceWatcher {
+    /// Creates a watcher for devices matching a selector, including devices
+    /// that are already attached.
+    pub fn new(selector: DeviceSelector<::nusb::DeviceInfo>) -> Self {
+        NusbDeviceWatcher {
+            selector,
+            seen: HashSet::new(),
+            deadline: None,
+            poll_interval: Duration::from_millis(250),
+            hotplug: watch_devices(),
+        }
+    }
+
+    /// Stops the watcher once `timeout` has passed from now.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Sets how often attached devices are checked when no hotplug event
+    /// arrives. Defaults to 250 milliseconds.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Skips matching devices that are already attached, so only devices
+    /// attached from now on are yielded.
+    ///
+    /// # Errors
+    /// - [`Error::UsbListDevicesFailure`]
+    pub fn skip_attached(mut self) -> Result<Self> {
+        let devices = ::nusb::list_devices()
+            .wait()
+            .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+        self.seen = devices.map(|d| d.id()).collect();
+        Ok(self)
+    }
+
+    /// Checks the attached devices once, returning the first new matching
+    /// device.
+    fn scan(&mut self) -> Result<Option<PicobootDevice>> {
+        let devices: Vec<_> = ::nusb::list_devices()
+            .wait()
+            .map_err(|e| Error::UsbListDevicesFailure(e.into()))?
+            .collect();
+
+        // forget detached devices, so they are yielded again when reattached
+        let attached: HashSet<_> = devices.iter().map(|d| d.id()).collect();
+        self.seen.retain(|id| attached.contains(id));
+
+        for device in devices {
+            let id = device.id();
+            if self.seen.contains(&id)
+                || !self.selector.may_match(
+                    bus_number(&device),
+                    device.port_chain(),
+                    device.vendor_id(),
+                    device.product_id(),
+                    &device,
+                )
+            {
+                continue;
+            }
+
+            // a new device may not be accessible until the OS has set it up,
+            // so it is checked again on the next scan
+            if let Some(dev) = PicobootDevice::probe(device) {
+                if dev.info.openable && self.selector.matches(&dev.info, &dev.device) {
+                    self.seen.insert(id);
+                    return Ok(Some(dev));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Blocks until a hotplug event arrives or `timeout` passes.
+    fn wait(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        while let Some(hotplug) = self.hotplug.as_mut() {
+            match hotplug.as_mut().poll_next(&mut cx) {
+                Poll::Ready(Some(_)) => return,
+                Poll::Ready(None) => self.hotplug = None,
+                Poll::Pending => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return;
+                    }
+                    std::thread::park_timeout(remaining);
+                }
+            }
+        }
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+}
+impl Iterator for NusbDeviceWatcher {
+    type Item = Result<PicobootDevice>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.scan() {
+                Ok(Some(dev)) => return Some(Ok(dev)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            let mut wait = self.poll_interval;
+            if let Some(deadline) = self.deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                wait = wait.min(remaining);
+            }
+            self.wait(wait);
+        }
+    }
+}
+impl std::fmt::Debug for NusbDeviceWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NusbDeviceWatcher")
+            .field("selector", &self.selector)
+            .field("deadline", &self.deadline)
+            .field("poll_interval", &self.poll_interval)
+            .field("hotplug", &self.hotplug.is_some())
+            .finish()
+    }
+}
+
+/// A PICOBOOT transport backed by nusb (pure Rust)
+///
+/// Owns the claimed PICOBOOT interface of a USB device. The interface is
+/// released, and any detached kernel driver reattached, when this is dropped.
+#[derive(Debug)]
+pub struct NusbTransport {
+    device: Device,
+    iface: Option<Interface>,
+    ep_in: Option<::nusb::Endpoint<Bulk, In>>,
+    ep_out: Option<::nusb::Endpoint<Bulk, Out>>,
+
+    iface_num: u8,
+    has_kernel_driver: bool,
+}
+impl Drop for NusbTransport {
+    fn drop(&mut self) {
+        // best effort, the device may already be gone after a reboot
+        let _ = self.release();
+    }
+}
+impl NusbTransport {
+    /// Opens the first USB device matching a VID/PID pair and claims its
+    /// PICOBOOT interface.
+    ///
+    /// # Errors
+    /// - [`Error::UsbDeviceNotFound`]
+    /// - [`Error::UsbDeviceFailedToOpen`]
+    /// - Any produced by [`Self::claim`]
+    pub fn open(vid: u16, pid: u16) -> Result<Self> {
+        let info = ::nusb::list_devices()
+            .wait()
+            .map_err(|_| Error::UsbDeviceNotFound)?
+            .find(|d| d.vendor_id() == vid && d.product_id() == pid)
+            .ok_or(Error::UsbDeviceNotFound)?;
+        let device = info
+            .open()
+            .wait()
+            .map_err(|e| Error::UsbDeviceFailedToOpen(e.into()))?;
+
+        Self::claim(device)
+    }
+
+    /// Claims the PICOBOOT interface of an already opened USB device.
+    ///
+    /// # Errors
+    /// - [`Error::UsbEndpointsNotFound`]
+    /// - [`Error::UsbEndpointsUnexpected`]
+    /// - [`Error::UsbDetachKernelDriverFailure`]
+    /// - [`Error::UsbClaimInterfaceFailure`]
+    /// - [`Error::UsbSetAltSettingFailure`]
+    pub fn claim(device: Device) -> Result<Self> {
+        let (cfg, iface_num, setting, in_addr, out_addr) = Self::find_interface(&device)?;
+
+        // nusb reports a missing kernel driver as an uncategorized error
+        let has_kernel_driver = match device.detach_kernel_driver(iface_num) {
+            Ok(()) => true,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::Disconnected | ErrorKind::PermissionDenied
+                ) =>
+            {
+                return Err(Error::UsbDetachKernelDriverFailure(e.into()))
+            }
+            Err(_) => false,
+        };
+
+        let active = device
+            .active_configuration()
+            .map(|c| c.configuration_value());
+        if active.ok() != Some(cfg) && device.set_configuration(cfg).wait().is_err() {
+            // println!("Warning: could not set USB active configuration");
+        }
+
+        let iface = device
+            .claim_interface(iface_num)
+            .wait()
+            .map_err(|e| Error::UsbClaimInterfaceFailure(e.into()))?;
+        iface
+            .set_alt_setting(setting)
+            .wait()
+            .map_err(|e| Error::UsbSetAltSettingFailure(e.into()))?;
+
+        let ep_in = iface
+            .endpoint::<Bulk, In>(in_addr)
+            .map_err(|_| Error::UsbEndpointsNotFound)?;
+        let ep_out = iface
+            .endpoint::<Bulk, Out>(out_addr)
+            .map_err(|_| Error::UsbEndpointsNotFound)?;
+
+        Ok(NusbTransport {
+            device,
+            iface: Some(iface),
+            ep_in: Some(ep_in),
+            ep_out: Some(ep_out),
+
+            iface_num,
+            has_kernel_driver,
+        })
+    }
+
+    /// Finds the configuration, interface, alternate setting and bulk
+    /// endpoint addresses of the PICOBOOT interface.
+    fn find_interface(device: &Device) -> Result<(u8, u8, u8, u8, u8)> {
+        let e1 = Self::get_endpoint(device, 255, 0, 0, Direction::In);
+        let e2 = Self::get_endpoint(device, 255, 0, 0, Direction::Out);
+
+        match (e1, e2) {
+            (None, _) | (_, None) => Err(Error::UsbEndpointsNotFound),
+            (Some((c1, i1, s1, in_addr)), Some((c2, i2, s2, out_addr))) => {
+                if (c1, i1, s1) == (c2, i2, s2) {
+                    Ok((c2, i2, s2, in_addr, out_addr))
+                } else {
+                    Err(Error::UsbEndpointsUnexpected)
+                }
+            }
+        }
+    }
+
+    fn get_endpoint(
+        device: &Device,
+        class: u8,
+        subclass: u8,
+        protocol: u8,
+        direction: Direction,
+    ) -> Option<(u8, u8, u8, u8)> {
+        for config_desc in device.configurations() {
+            for iface_desc in config_desc.interface_alt_settings() {
+                if (
+                    iface_desc.class(),
+                    iface_desc.subclass(),
+                    iface_desc.protocol(),
+                ) != (class, subclass, protocol)
+                {
+                    continue;
+                }
+
+                for endpoint_desc in iface_desc.endpoints() {
+                    if endpoint_desc.direction() == direction
+                        && endpoint_desc.transfer_type() == TransferType::Bulk
+                    {
+                        return Some((
+                            config_desc.configuration_value(),
+                            iface_desc.interface_number(),
+                            iface_desc.alternate_setting(),
+                            endpoint_desc.address(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Releases the interface and reattaches any detached kernel driver.
+    fn release(&mut self) -> Result<()> {
+        // the interface is only released once the endpoints are gone
+        self.ep_in = None;
+        self.ep_out = None;
+        let iface = match self.iface.take() {
+            Some(iface) => iface,
+            None => return Ok(()),
+        };
+
+        let released = iface
+            .release()
+            .wait()
+            .map_err(|e| Error::UsbReleaseInterfaceFailure(e.into()));
+
+        // the release error comes first, as it is the likely cause of both
+        let attached = match self.has_kernel_driver {
+            true => self
+                .device
+                .attach_kernel_driver(self.iface_num)
+                .map_err(|e| Error::UsbAttachKernelDriverFailure(e.into())),
+            false => Ok(()),
+        };
+
+        released.and(attached)
+    }
+
+    fn interface(&self) -> ::std::result::Result<&Interface, TransportError> {
+        self.iface.as_ref().ok_or(TransportError::NotFound)
+    }
+}
+
+/// Splits a `bmRequestType` into the nusb control type and recipient.
+fn control_setup(request_type: u8) -> (ControlType, Recipient) {
+    let control_type = match (request_type >> 5) & 0x03 {
+        0 => ControlType::Standard,
+        1 => ControlType::Class,
+        _ => ControlType::Vendor,
+    };
+    let recipient = match request_type & 0x1f {
+        0 => Recipient::Device,
+        1 => Recipient::Interface,
+        2 => Recipient::Endpoint,
+        _ => Recipient::Other,
+    };
+    (control_type, recipient)
+}
+
+impl PicobootTransport for NusbTransport {
+    fn read_bulk(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let ep = self.ep_in.as_mut().ok_or(TransportError::NotFound)?;
+
+        // IN transfers must request a whole number of packets
+        let mps = ep.max_packet_size();
+        let len = buf.len().max(1).saturating_add(mps - 1) / mps * mps;
+        let completion = ep.transfer_blocking(Buffer::new(len), timeout);
+        completion.status?;
+
+        let data = &completion.buffer[..completion.actual_len];
+        if data.len() > buf.len() {
+            return Err(TransportError::Overflow);
+        }
+        buf[..data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn write_bulk(
+        &mut self,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let ep = self.ep_out.as_mut().ok_or(TransportError::NotFound)?;
+        let completion = ep.transfer_blocking(Buffer::from(buf), timeout);
+        completion.status?;
+        Ok(completion.actual_len)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let (control_type, recipient) = control_setup(request_type);
+        let data = self
+            .interface()?
+            .control_in(
+                ControlIn {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    length: buf.len().try_into().unwrap_or(u16::MAX),
+                },
+                timeout,
+            )
+            .wait()?;
+
+        if data.len() > buf.len() {
+            return Err(TransportError::Overflow);
+        }
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let (control_type, recipient) = control_setup(request_type);
+        self.interface()?
+            .control_out(
+                ControlOut {
+                    control_type,
+                    recipient,
+                    request,
+                    value,
+                    index,
+                    data: buf,
+                },
+                timeout,
+            )
+            .wait()?;
+        Ok(buf.len())
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> ::std::result::Result<(), TransportError> {
+        match endpoint {
+            Endpoint::In => self
+                .ep_in
+                .as_mut()
+                .ok_or(TransportError::NotFound)?
+                .clear_halt()
+                .wait()?,
+            Endpoint::Out => self
+                .ep_out
+                .as_mut()
+                .ok_or(TransportError::NotFound)?
+                .clear_halt()
+                .wait()?,
+        }
+        Ok(())
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.iface_num
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.release()
+    }
+}
+
+impl PicobootConnection<NusbTransport> {
+    /// Creates a new PICOBOOT connection using the nusb backend
+    ///
+    /// Behaves like [`PicobootConnection::new`], without a rusb context.
+    ///
+    /// # Errors
+    /// - [`Error::UsbDeviceNotFound`]
+    /// - [`Error::UsbDeviceFailedToOpen`]
+    /// - Any produced by [`NusbTransport::claim`]
+    /// - Any produced by [`PicobootConnection::reset_interface`]
+    /// - Any produced by [`PicobootConnection::identify`]
+    pub fn new_nusb(vidpid: Option<(u16, u16)>) -> Result<Self> {
+        // initial guess of the target type, confirmed once connected
+        let (transport, target_id) = match vidpid {
+            Some((vid, pid)) => {
+                let id = TargetID::from_usb_ids(vid, pid).unwrap_or(TargetID::Rp2350);
+                (NusbTransport::open(vid, pid)?, id)
+            }
+            None => match NusbTransport::open(PICOBOOT_VID, PICOBOOT_PID_RP2040) {
+                Ok(t) => (t, TargetID::Rp2040),
+                Err(PicobootError::UsbDeviceNotFound) => (
+                    NusbTransport::open(PICOBOOT_VID, PICOBOOT_PID_RP2350)?,
+                    TargetID::Rp2350,
+                ),
+                Err(e) => return Err(e),
+            },
+        };
+
+        Self::from_transport(transport, target_id).confirm_target()
+    }
+
+    /// Creates a new PICOBOOT connection to a selected device using the nusb
+    /// backend
+    ///
+    /// The [`DeviceSelector::Descriptor`] predicate is applied to the nusb
+    /// [`::nusb::DeviceInfo`] of each device.
+    ///
+    /// # Errors
+    /// - Any produced by [`find_device`]
+    /// - Any produced by [`PicobootDevice::open`]
+    pub fn open_nusb(selector: &DeviceSelector<::nusb::DeviceInfo>) -> Result<Self> {
+        find_device(selector)?.open()
+    }
+}
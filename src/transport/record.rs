@@ -0,0 +1,599 @@
+use crate::{
+    cmd::PicobootError,
+    transport::{Endpoint, PicobootTransport, TransportError},
+};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    time::{Duration, Instant},
+};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// Magic bytes at the start of a saved recording.
+const RECORDING_MAGIC: [u8; 4] = *b"PBRC";
+/// Version of the saved recording format.
+const RECORDING_VERSION: u32 = 1;
+
+/// A single transfer made on a [`PicobootTransport`], with its result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Transfer {
+    /// Bulk IN transfer of up to `len` bytes, returning the bytes read.
+    ReadBulk {
+        len: usize,
+        result: ::std::result::Result<Vec<u8>, TransportError>,
+    },
+    /// Bulk OUT transfer of `data`, returning the number of bytes written.
+    WriteBulk {
+        data: Vec<u8>,
+        result: ::std::result::Result<usize, TransportError>,
+    },
+    /// Control IN transfer of up to `len` bytes, returning the bytes read.
+    ReadControl {
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+        result: ::std::result::Result<Vec<u8>, TransportError>,
+    },
+    /// Control OUT transfer of `data`, returning the number of bytes written.
+    WriteControl {
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: Vec<u8>,
+        result: ::std::result::Result<usize, TransportError>,
+    },
+    /// Clearing the halt condition of a bulk endpoint.
+    ClearHalt {
+        endpoint: Endpoint,
+        result: ::std::result::Result<(), TransportError>,
+    },
+}
+impl Transfer {
+    /// Checks whether another transfer was requested with the same
+    /// arguments, ignoring results.
+    fn same_request(&self, other: &Transfer) -> bool {
+        use Transfer::*;
+        match (self, other) {
+            (ReadBulk { len: a, .. }, ReadBulk { len: b, .. }) => a == b,
+            (WriteBulk { data: a, .. }, WriteBulk { data: b, .. }) => a == b,
+            (
+                ReadControl {
+                    request_type: t1,
+                    request: r1,
+                    value: v1,
+                    index: i1,
+                    len: l1,
+                    ..
+                },
+                ReadControl {
+                    request_type: t2,
+                    request: r2,
+                    value: v2,
+                    index: i2,
+                    len: l2,
+                    ..
+                },
+            ) => (t1, r1, v1, i1, l1) == (t2, r2, v2, i2, l2),
+            (
+                WriteControl {
+                    request_type: t1,
+                    request: r1,
+                    value: v1,
+                    index: i1,
+                    data: d1,
+                    ..
+                },
+                WriteControl {
+                    request_type: t2,
+                    request: r2,
+                    value: v2,
+                    index: i2,
+                    data: d2,
+                    ..
+                },
+            ) => (t1, r1, v1, i1, d1) == (t2, r2, v2, i2, d2),
+            (ClearHalt { endpoint: a, .. }, ClearHalt { endpoint: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A [`Transfer`] with the time it was started at.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    elapsed: Duration,
+    transfer: Transfer,
+}
+impl TransferRecord {
+    /// Returns the time the transfer was started at, relative to the start of
+    /// the recording.
+    pub fn get_elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the transfer.
+    pub fn get_transfer(&self) -> &Transfer {
+        &self.transfer
+    }
+}
+
+/// A recorded sequence of transfers on a PICOBOOT interface
+///
+/// Produced by a [`RecordingTransport`], and played back as a fake device by a
+/// [`ReplayTransport`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    interface_number: u8,
+    transfers: Vec<TransferRecord>,
+}
+impl Recording {
+    /// Creates an empty recording of an interface.
+    pub fn new(interface_number: u8) -> Self {
+        Recording {
+            interface_number,
+            transfers: vec![],
+        }
+    }
+
+    /// Returns the interface number of the recorded PICOBOOT interface.
+    pub fn get_interface_number(&self) -> u8 {
+        self.interface_number
+    }
+
+    /// Returns the recorded transfers, in the order they were made.
+    pub fn get_transfers(&self) -> &[TransferRecord] {
+        &self.transfers
+    }
+
+    /// Writes the recording to `writer`, for example a file.
+    ///
+    /// # Errors
+    /// - [`Error::RecordingSerializeFailure`]
+    pub fn save<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer
+            .write_all(&RECORDING_MAGIC)
+            .map_err(|e| Error::RecordingSerializeFailure(e.into()))?;
+        bincode::serialize_into(&mut writer, &RECORDING_VERSION)
+            .map_err(Error::RecordingSerializeFailure)?;
+        bincode::serialize_into(&mut writer, self).map_err(Error::RecordingSerializeFailure)
+    }
+
+    /// Reads a recording written by [`Self::save`] from `reader`.
+    ///
+    /// # Errors
+    /// - [`Error::RecordingFormatInvalid`]
+    /// - [`Error::RecordingDeserializeFailure`]
+    pub fn load<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|e| Error::RecordingDeserializeFailure(e.into()))?;
+        let version: u32 =
+            bincode::deserialize_from(&mut reader).map_err(Error::RecordingDeserializeFailure)?;
+        if magic != RECORDING_MAGIC || version != RECORDING_VERSION {
+            return Err(Error::RecordingFormatInvalid);
+        }
+
+        bincode::deserialize_from(reader).map_err(Error::RecordingDeserializeFailure)
+    }
+}
+
+/// A transport that records every transfer made on another transport
+///
+/// All transfers are passed through to the wrapped transport unchanged, and
+/// logged with their results and the time they were started at.
+#[derive(Debug)]
+pub struct RecordingTransport<T: PicobootTransport> {
+    inner: T,
+    start: Instant,
+    recording: Recording,
+}
+impl<T: PicobootTransport> RecordingTransport<T> {
+    /// Starts recording the transfers made on `inner`.
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            recording: Recording::new(inner.interface_number()),
+            start: Instant::now(),
+            inner,
+        }
+    }
+
+    /// Returns the transfers recorded so far.
+    pub fn get_recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Returns a reference to the wrapped transport.
+    pub fn get_inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Stops recording, returning the wrapped transport and the recording.
+    pub fn into_parts(self) -> (T, Recording) {
+        (self.inner, self.recording)
+    }
+
+    fn record(&mut self, elapsed: Duration, transfer: Transfer) {
+        self.recording
+            .transfers
+            .push(TransferRecord { elapsed, transfer });
+    }
+}
+impl<T: PicobootTransport> PicobootTransport for RecordingTransport<T> {
+    fn read_bulk(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let elapsed = self.start.elapsed();
+        let res = self.inner.read_bulk(buf, timeout);
+        let result = res.map(|n| buf[..n].to_vec());
+        self.record(
+            elapsed,
+            Transfer::ReadBulk {
+                len: buf.len(),
+                result,
+            },
+        );
+        res
+    }
+
+    fn write_bulk(
+        &mut self,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let elapsed = self.start.elapsed();
+        let result = self.inner.write_bulk(buf, timeout);
+        self.record(
+            elapsed,
+            Transfer::WriteBulk {
+                data: buf.to_vec(),
+                result,
+            },
+        );
+        result
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let elapsed = self.start.elapsed();
+        let res = self
+            .inner
+            .read_control(request_type, request, value, index, buf, timeout);
+        let result = res.map(|n| buf[..n].to_vec());
+        self.record(
+            elapsed,
+            Transfer::ReadControl {
+                request_type,
+                request,
+                value,
+                index,
+                len: buf.len(),
+                result,
+            },
+        );
+        res
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let elapsed = self.start.elapsed();
+        let result = self
+            .inner
+            .write_control(request_type, request, value, index, buf, timeout);
+        self.record(
+            elapsed,
+            Transfer::WriteControl {
+                request_type,
+                request,
+                value,
+                index,
+                data: buf.to_vec(),
+                result,
+            },
+        );
+        result
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> ::std::result::Result<(), TransportError> {
+        let elapsed = self.start.elapsed();
+        let result = self.inner.clear_halt(endpoint);
+        self.record(elapsed, Transfer::ClearHalt { endpoint, result });
+        result
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.inner.interface_number()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+}
+
+/// A fake device that plays back a [`Recording`]
+///
+/// Each transfer must be requested with exactly the same arguments and data
+/// as the next transfer in the recording, and then returns the recorded
+/// result. Any other transfer fails with [`TransportError::ReplayDiverged`]
+/// holding the index of the expected transfer, as does any transfer after the
+/// end of the recording. Recorded timestamps are not reproduced.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    recording: Recording,
+    position: usize,
+}
+impl ReplayTransport {
+    /// Creates a fake device playing back `recording` from the start.
+    pub fn new(recording: Recording) -> Self {
+        ReplayTransport {
+            recording,
+            position: 0,
+        }
+    }
+
+    /// Returns the index of the next expected transfer.
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns `true` once every recorded transfer has been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.recording.transfers.len()
+    }
+
+    /// Returns the recording being played back.
+    pub fn get_recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Checks a requested transfer against the next recorded transfer,
+    /// returning the recorded one if they match.
+    fn next(&mut self, request: &Transfer) -> ::std::result::Result<&Transfer, TransportError> {
+        let index = self.position;
+        match self.recording.transfers.get(index) {
+            Some(r) if r.transfer.same_request(request) => {
+                self.position += 1;
+                Ok(&r.transfer)
+            }
+            _ => Err(TransportError::ReplayDiverged(index)),
+        }
+    }
+}
+
+/// Copies recorded IN data into a transfer buffer.
+fn copy_in(
+    result: &::std::result::Result<Vec<u8>, TransportError>,
+    buf: &mut [u8],
+) -> ::std::result::Result<usize, TransportError> {
+    let data = result.as_ref().map_err(|e| *e)?;
+    buf.get_mut(..data.len())
+        .ok_or(TransportError::Overflow)?
+        .copy_from_slice(data);
+    Ok(data.len())
+}
+
+impl PicobootTransport for ReplayTransport {
+    fn read_bulk(
+        &mut self,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let request = Transfer::ReadBulk {
+            len: buf.len(),
+            result: Ok(vec![]),
+        };
+        match self.next(&request)? {
+            Transfer::ReadBulk { result, .. } => copy_in(result, buf),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_bulk(
+        &mut self,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let request = Transfer::WriteBulk {
+            data: buf.to_vec(),
+            result: Ok(0),
+        };
+        match self.next(&request)? {
+            Transfer::WriteBulk { result, .. } => *result,
+            _ => unreachable!(),
+        }
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let req = Transfer::ReadControl {
+            request_type,
+            request,
+            value,
+            index,
+            len: buf.len(),
+            result: Ok(vec![]),
+        };
+        match self.next(&req)? {
+            Transfer::ReadControl { result, .. } => copy_in(result, buf),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        let req = Transfer::WriteControl {
+            request_type,
+            request,
+            value,
+            index,
+            data: buf.to_vec(),
+            result: Ok(0),
+        };
+        match self.next(&req)? {
+            Transfer::WriteControl { result, .. } => *result,
+            _ => unreachable!(),
+        }
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> ::std::result::Result<(), TransportError> {
+        let request = Transfer::ClearHalt {
+            endpoint,
+            result: Ok(()),
+        };
+        match self.next(&request)? {
+            Transfer::ClearHalt { result, .. } => *result,
+            _ => unreachable!(),
+        }
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.recording.interface_number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emu::PicobootEmulator, PicobootCmd, PicobootConnection, TargetID, FLASH_START, SECTOR_SIZE,
+        STACK_POINTER_RP2040,
+    };
+
+    const DATA: [u8; 256] = [0x5A; 256];
+
+    fn session<T: PicobootTransport>(conn: &mut PicobootConnection<T>) -> Result<()> {
+        conn.flash_erase(FLASH_START, SECTOR_SIZE)?;
+        conn.flash_write(FLASH_START, &DATA)?;
+        conn.reboot(0, STACK_POINTER_RP2040, 100)
+    }
+
+    fn record_session() -> Recording {
+        let transport = RecordingTransport::new(PicobootEmulator::new(TargetID::Rp2040));
+        let mut conn = PicobootConnection::from_transport(transport, TargetID::Rp2040);
+        session(&mut conn).unwrap();
+        conn.into_transport().into_parts().1
+    }
+
+    fn replay(recording: Recording) -> PicobootConnection<ReplayTransport> {
+        PicobootConnection::from_transport(ReplayTransport::new(recording), TargetID::Rp2040)
+    }
+
+    #[test]
+    fn records_cmd_bytes() {
+        let recording = record_session();
+        let cmds: Vec<Vec<u8>> = recording
+            .get_transfers()
+            .iter()
+            .filter_map(|t| match t.get_transfer() {
+                Transfer::WriteBulk { data, .. } if data.len() == 32 => Some(data.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let expected = [
+            PicobootCmd::flash_erase(FLASH_START, SECTOR_SIZE).set_token(1),
+            PicobootCmd::flash_write(FLASH_START, DATA.len() as u32).set_token(2),
+            PicobootCmd::reboot(0, STACK_POINTER_RP2040, 100).set_token(3),
+        ];
+        let expected: Vec<Vec<u8>> = expected.iter().map(|c| c.to_bytes().to_vec()).collect();
+        assert_eq!(cmds, expected);
+
+        // the data phase of the write follows its command
+        assert!(recording.get_transfers().iter().any(|t| matches!(
+            t.get_transfer(),
+            Transfer::WriteBulk { data, result: Ok(256) } if data[..] == DATA[..]
+        )));
+    }
+
+    #[test]
+    fn replays_session() {
+        let mut recording = vec![];
+        record_session().save(&mut recording).unwrap();
+
+        let mut conn = replay(Recording::load(&recording[..]).unwrap());
+        session(&mut conn).unwrap();
+        assert!(conn.transport().is_finished());
+    }
+
+    #[test]
+    fn replay_diverges() {
+        // a different command is rejected before reaching the recording
+        let mut conn = replay(record_session());
+        let err = conn.flash_erase(FLASH_START + SECTOR_SIZE, SECTOR_SIZE);
+        assert!(matches!(
+            err,
+            Err(Error::UsbWriteBulkFailure(TransportError::ReplayDiverged(
+                0
+            )))
+        ));
+
+        // as is different data for the same command
+        let recording = record_session();
+        let data_phase = recording
+            .get_transfers()
+            .iter()
+            .position(|t| matches!(t.get_transfer(), Transfer::WriteBulk { data, .. } if data[..] == DATA[..]))
+            .unwrap();
+        let mut conn = replay(recording);
+        conn.flash_erase(FLASH_START, SECTOR_SIZE).unwrap();
+        let err = conn.flash_write(FLASH_START, &[0xA5; 256]);
+        match err {
+            Err(Error::UsbWriteBulkFailure(TransportError::ReplayDiverged(i))) => {
+                assert_eq!(i, data_phase)
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn replay_read_overflow() {
+        let mut recording = Recording::new(0);
+        recording.transfers.push(TransferRecord {
+            elapsed: Duration::ZERO,
+            transfer: Transfer::ReadBulk {
+                len: 4,
+                result: Ok(vec![0; 8]),
+            },
+        });
+
+        let mut replay = ReplayTransport::new(recording);
+        let res = replay.read_bulk(&mut [0; 4], Duration::ZERO);
+        assert_eq!(res, Err(TransportError::Overflow));
+    }
+}
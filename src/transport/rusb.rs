@@ -0,0 +1,651 @@
+use crate::{
+    cmd::{PicobootError, TargetID},
+    transport::{DeviceInfo, DeviceSelector, Endpoint, PicobootTransport, TransportError},
+    usb::PicobootConnection,
+};
+
+use ::rusb::{
+    Device, DeviceDescriptor, DeviceHandle, Direction, Hotplug, HotplugBuilder, Registration,
+    TransferType, UsbContext,
+};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+impl From<::rusb::Error> for TransportError {
+    fn from(e: ::rusb::Error) -> Self {
+        match e {
+            ::rusb::Error::Timeout => TransportError::Timeout,
+            ::rusb::Error::Pipe => TransportError::Stall,
+            ::rusb::Error::NoDevice => TransportError::Disconnected,
+            ::rusb::Error::Access => TransportError::AccessDenied,
+            ::rusb::Error::Busy => TransportError::Busy,
+            ::rusb::Error::Overflow => TransportError::Overflow,
+            ::rusb::Error::NotFound => TransportError::NotFound,
+            ::rusb::Error::NotSupported => TransportError::Unsupported,
+            _ => TransportError::Io,
+        }
+    }
+}
+
+/// A PICOBOOT device found by [`list_devices`]
+///
+/// Holds the unopened USB device along with its [`DeviceInfo`], and can be
+/// turned into a connection with [`Self::open`].
+#[derive(Debug)]
+pub struct PicobootDevice<T: UsbContext> {
+    device: Device<T>,
+    desc: DeviceDescriptor,
+    info: DeviceInfo,
+}
+impl<T: UsbContext> PicobootDevice<T> {
+    /// Inspects a USB device, returning `None` if it does not expose a
+    /// PICOBOOT interface.
+    ///
+    /// The device is opened briefly to read its serial number, but no
+    /// interface is claimed.
+    pub fn probe(device: Device<T>) -> Option<Self> {
+        let desc = device.device_descriptor().ok()?;
+        RusbTransport::<T>::get_interface(&device)?;
+
+        let (serial_number, openable) = match device.open() {
+            Ok(handle) => (handle.read_serial_number_string_ascii(&desc).ok(), true),
+            Err(_) => (None, false),
+        };
+        let target_id = TargetID::from_usb_ids(desc.vendor_id(), desc.product_id());
+
+        let info = DeviceInfo {
+            bus_number: device.bus_number(),
+            address: device.address(),
+            port_numbers: device.port_numbers().unwrap_or_default(),
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            serial_number,
+            target_id,
+            openable,
+        };
+
+        Some(PicobootDevice { device, desc, info })
+    }
+
+    /// Returns the description of the device.
+    pub fn get_info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// Returns the underlying rusb device.
+    pub fn get_device(&self) -> &Device<T> {
+        &self.device
+    }
+
+    /// Returns the USB device descriptor of the device.
+    pub fn get_descriptor(&self) -> &DeviceDescriptor {
+        &self.desc
+    }
+
+    /// Opens the device, claims its PICOBOOT interface and confirms its
+    /// target with [`PicobootConnection::identify`].
+    ///
+    /// # Errors
+    /// - [`Error::UsbDeviceFailedToOpen`]
+    /// - Any produced by [`RusbTransport::claim`]
+    /// - Any produced by [`PicobootConnection::reset_interface`]
+    /// - Any produced by [`PicobootConnection::identify`]
+    pub fn open(&self) -> Result<PicobootConnection<RusbTransport<T>>> {
+        let desc = self
+            .device
+            .device_descriptor()
+            .map_err(|e| Error::UsbDeviceFailedToOpen(e.into()))?;
+        let handle = self
+            .device
+            .open()
+            .map_err(|e| Error::UsbDeviceFailedToOpen(e.into()))?;
+        let transport = RusbTransport::claim(
+            self.device.context().clone(),
+            self.device.clone(),
+            desc,
+            handle,
+        )?;
+
+        // the actual target is read from the device
+        let target_id = self.info.target_id.unwrap_or(TargetID::Rp2350);
+        PicobootConnection::from_transport(transport, target_id).confirm_target()
+    }
+}
+
+/// Lists all attached devices with a PICOBOOT interface
+///
+/// Devices with any VID/PID pair are listed, including boards with
+/// white-labelled IDs. Devices are not claimed, so they can be listed while
+/// another process is connected to them.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+pub fn list_devices<T: UsbContext>(ctx: &T) -> Result<Vec<PicobootDevice<T>>> {
+    let devices = ctx
+        .devices()
+        .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+
+    Ok(devices.iter().filter_map(PicobootDevice::probe).collect())
+}
+
+/// Finds the first attached PICOBOOT device matching a selector
+///
+/// Devices are checked in the order they are enumerated by libusb.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+/// - [`Error::UsbDeviceNotFound`]
+pub fn find_device<T: UsbContext>(
+    ctx: &T,
+    selector: &DeviceSelector<DeviceDescriptor>,
+) -> Result<PicobootDevice<T>> {
+    let devices = ctx
+        .devices()
+        .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+
+    for device in devices.iter() {
+        let desc = match device.device_descriptor() {
+            Ok(d) => d,
+            Err(_) => continue,
+        };
+        let ports = device.port_numbers().unwrap_or_default();
+        if !selector.may_match(
+            device.bus_number(),
+            &ports,
+            desc.vendor_id(),
+            desc.product_id(),
+            &desc,
+        ) {
+            continue;
+        }
+
+        if let Some(dev) = PicobootDevice::probe(device) {
+            if selector.matches(&dev.info, &dev.desc) {
+                return Ok(dev);
+            }
+        }
+    }
+
+    Err(Error::UsbDeviceNotFound)
+}
+
+/// Waits for a PICOBOOT device matching a selector to be attached
+///
+/// Returns as soon as a matching device is attached and can be opened by this
+/// process, including a device that is already attached when this is called.
+/// Waits forever if `timeout` is `None`.
+///
+/// # Errors
+/// - [`Error::UsbListDevicesFailure`]
+/// - [`Error::UsbDeviceNotFound`], if no device appeared before the timeout
+pub fn wait_for_device<T: UsbContext + 'static>(
+    ctx: &T,
+    selector: DeviceSelector<DeviceDescriptor>,
+    timeout: Option<Duration>,
+) -> Result<PicobootDevice<T>> {
+    let mut watcher = DeviceWatcher::new(ctx.clone(), selector);
+    if let Some(timeout) = timeout {
+        watcher = watcher.with_timeout(timeout);
+    }
+
+    watcher.next().unwrap_or(Err(Error::UsbDeviceNotFound))
+}
+
+/// Wakes up a [`DeviceWatcher`] waiting in libusb event handling.
+struct HotplugWakeup;
+impl<T: UsbContext> Hotplug<T> for HotplugWakeup {
+    fn device_arrived(&mut self, _device: Device<T>) {}
+    fn device_left(&mut self, _device: Device<T>) {}
+}
+
+/// An iterator over PICOBOOT devices as they are attached
+///
+/// Each call to [`Iterator::next`] blocks until a device matching the selector
+/// is attached and can be opened by this process, and yields it once. A device
+/// that is detached and attached again, for example after rebooting into
+/// BOOTSEL mode, is yielded again. The iterator ends when the timeout set with
+/// [`Self::with_timeout`] expires.
+///
+/// Attach events are received through libusb hotplug where supported, and
+/// otherwise the attached devices are polled.
+///
+/// Being an iterator, it can also be driven with a callback through
+/// [`Iterator::for_each`] or [`Iterator::try_for_each`].
+pub struct DeviceWatcher<T: UsbContext> {
+    ctx: T,
+    selector: DeviceSelector<DeviceDescriptor>,
+    seen: HashSet<(u8, Vec<u8>, u8)>,
+    deadline: Option<Instant>,
+    poll_interval: Duration,
+    registration: Option<Registration<T>>,
+}
+impl<T: UsbContext + 'static> DeviceWatcher<T> {
+    /// Creates a watcher for devices matching a selector, including devices
+    /// that are already attached.
+    pub fn new(ctx: T, selector: DeviceSelector<DeviceDescriptor>) -> Self {
+        let registration = if ::rusb::has_hotplug() {
+            HotplugBuilder::new()
+                .register(ctx.clone(), Box::new(HotplugWakeup))
+                .ok()
+        } else {
+            None
+        };
+
+        DeviceWatcher {
+            ctx,
+            selector,
+            seen: HashSet::new(),
+            deadline: None,
+            poll_interval: Duration::from_millis(250),
+            registration,
+        }
+    }
+
+    /// Stops the watcher once `timeout` has passed from now.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Sets how often attached devices are checked when no hotplug event
+    /// arrives. Defaults to 250 milliseconds.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Skips matching devices that are already attached, so only devices
+    /// attached from now on are yielded.
+    ///
+    /// # Errors
+    /// - [`Error::UsbListDevicesFailure`]
+    pub fn skip_attached(mut self) -> Result<Self> {
+        let devices = self
+            .ctx
+            .devices()
+            .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+        self.seen = devices.iter().map(|d| Self::device_key(&d)).collect();
+        Ok(self)
+    }
+
+    fn device_key(device: &Device<T>) -> (u8, Vec<u8>, u8) {
+        (
+            device.bus_number(),
+            device.port_numbers().unwrap_or_default(),
+            device.address(),
+        )
+    }
+
+    /// Checks the attached devices once, returning the first new matching
+    /// device.
+    fn scan(&mut self) -> Result<Option<PicobootDevice<T>>> {
+        let devices = self
+            .ctx
+            .devices()
+            .map_err(|e| Error::UsbListDevicesFailure(e.into()))?;
+
+        // forget detached devices, so they are yielded again when reattached
+        let attached: HashSet<_> = devices.iter().map(|d| Self::device_key(&d)).collect();
+        self.seen.retain(|k| attached.contains(k));
+
+        for device in devices.iter() {
+            let key = Self::device_key(&device);
+            if self.seen.contains(&key) {
+                continue;
+            }
+            let desc = match device.device_descriptor() {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+            if !self
+                .selector
+                .may_match(key.0, &key.1, desc.vendor_id(), desc.product_id(), &desc)
+            {
+                continue;
+            }
+
+            // a new device may not be accessible until the OS has set it up,
+            // so it is checked again on the next scan
+            if let Some(dev) = PicobootDevice::probe(device) {
+                if dev.info.openable && self.selector.matches(&dev.info, &dev.desc) {
+                    self.seen.insert(key);
+                    return Ok(Some(dev));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Blocks until a hotplug event arrives or `timeout` passes.
+    fn wait(&self, timeout: Duration) {
+        match self.registration {
+            Some(_) if self.ctx.handle_events(Some(timeout)).is_ok() => {}
+            _ => std::thread::sleep(timeout),
+        }
+    }
+}
+impl<T: UsbContext + 'static> Iterator for DeviceWatcher<T> {
+    type Item = Result<PicobootDevice<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.scan() {
+                Ok(Some(dev)) => return Some(Ok(dev)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+
+            let mut wait = self.poll_interval;
+            if let Some(deadline) = self.deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                wait = wait.min(remaining);
+            }
+            self.wait(wait);
+        }
+    }
+}
+impl<T: UsbContext> std::fmt::Debug for DeviceWatcher<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceWatcher")
+            .field("selector", &self.selector)
+            .field("deadline", &self.deadline)
+            .field("poll_interval", &self.poll_interval)
+            .field("hotplug", &self.registration.is_some())
+            .finish()
+    }
+}
+
+/// A PICOBOOT transport backed by rusb (libusb)
+///
+/// Owns the claimed PICOBOOT interface of a USB device. The interface is
+/// released, and any detached kernel driver reattached, when this is dropped.
+#[derive(Debug)]
+pub struct RusbTransport<T: UsbContext> {
+    _context: T,
+    _device: Device<T>,
+    _desc: DeviceDescriptor,
+    handle: DeviceHandle<T>,
+
+    _cfg: u8,
+    iface: u8,
+    _setting: u8,
+    in_addr: u8,
+    out_addr: u8,
+
+    has_kernel_driver: bool,
+    closed: bool,
+}
+impl<T: UsbContext> Drop for RusbTransport<T> {
+    fn drop(&mut self) {
+        // best effort, the device may already be gone after a reboot
+        if !self.closed {
+            let _ = self.release();
+        }
+    }
+}
+impl<T: UsbContext> RusbTransport<T> {
+    /// Opens the first USB device matching a VID/PID pair and claims its
+    /// PICOBOOT interface.
+    ///
+    /// # Errors
+    /// - [`Error::UsbDeviceNotFound`]
+    /// - [`Error::UsbDeviceFailedToOpen`]
+    /// - Any produced by [`Self::claim`]
+    pub fn open(mut ctx: T, vid: u16, pid: u16) -> Result<Self> {
+        let (device, desc, handle) = Self::open_device(&mut ctx, vid, pid)?;
+        Self::claim(ctx, device, desc, handle)
+    }
+
+    /// Claims the PICOBOOT interface of an already opened USB device.
+    ///
+    /// # Errors
+    /// - [`Error::UsbEndpointsNotFound`]
+    /// - [`Error::UsbEndpointsUnexpected`]
+    /// - [`Error::UsbDetachKernelDriverFailure`]
+    /// - [`Error::UsbClaimInterfaceFailure`]
+    /// - [`Error::UsbSetAltSettingFailure`]
+    pub fn claim(
+        ctx: T,
+        device: Device<T>,
+        desc: DeviceDescriptor,
+        handle: DeviceHandle<T>,
+    ) -> Result<Self> {
+        let (cfg, iface, setting, in_addr, out_addr) = Self::find_interface(&device)?;
+
+        let has_kernel_driver = if let Ok(true) = handle.kernel_driver_active(iface) {
+            handle
+                .detach_kernel_driver(iface)
+                .map_err(|e| Error::UsbDetachKernelDriverFailure(e.into()))?;
+            true
+        } else {
+            false
+        };
+
+        if handle.set_active_configuration(cfg).is_err() {
+            // println!("Warning: could not set USB active configuration");
+        }
+
+        handle
+            .claim_interface(iface)
+            .map_err(|e| Error::UsbClaimInterfaceFailure(e.into()))?;
+        handle
+            .set_alternate_setting(iface, setting)
+            .map_err(|e| Error::UsbSetAltSettingFailure(e.into()))?;
+
+        Ok(RusbTransport {
+            _context: ctx,
+            _device: device,
+            _desc: desc,
+            handle,
+
+            _cfg: cfg,
+            iface,
+            _setting: setting,
+            in_addr,
+            out_addr,
+
+            has_kernel_driver,
+            closed: false,
+        })
+    }
+
+    /// Fails transfers once the interface has been released.
+    fn check_open(&self) -> ::std::result::Result<(), TransportError> {
+        match self.closed {
+            true => Err(TransportError::NotFound),
+            false => Ok(()),
+        }
+    }
+
+    /// Releases the interface and reattaches any detached kernel driver.
+    fn release(&mut self) -> Result<()> {
+        self.closed = true;
+
+        let released = self
+            .handle
+            .release_interface(self.iface)
+            .map_err(|e| Error::UsbReleaseInterfaceFailure(e.into()));
+
+        // the release error comes first, as it is the likely cause of both
+        let attached = match self.has_kernel_driver {
+            true => self
+                .handle
+                .attach_kernel_driver(self.iface)
+                .map_err(|e| Error::UsbAttachKernelDriverFailure(e.into())),
+            false => Ok(()),
+        };
+
+        released.and(attached)
+    }
+
+    /// Finds the configuration, interface, alternate setting and bulk
+    /// endpoint addresses of the PICOBOOT interface.
+    fn find_interface(device: &Device<T>) -> Result<(u8, u8, u8, u8, u8)> {
+        let e1 = Self::get_endpoint(device, 255, 0, 0, Direction::In, TransferType::Bulk);
+        let e2 = Self::get_endpoint(device, 255, 0, 0, Direction::Out, TransferType::Bulk);
+
+        match (e1, e2) {
+            (None, _) | (_, None) => Err(Error::UsbEndpointsNotFound),
+            (Some((c1, i1, s1, in_addr)), Some((c2, i2, s2, out_addr))) => {
+                if (c1, i1, s1) == (c2, i2, s2) {
+                    Ok((c2, i2, s2, in_addr, out_addr))
+                } else {
+                    Err(Error::UsbEndpointsUnexpected)
+                }
+            }
+        }
+    }
+
+    fn get_interface(device: &Device<T>) -> Option<(u8, u8, u8, u8, u8)> {
+        Self::find_interface(device).ok()
+    }
+
+    fn open_device(
+        ctx: &mut T,
+        vid: u16,
+        pid: u16,
+    ) -> Result<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
+        let devices = ctx
+            .devices()
+            .map_err(|_| PicobootError::UsbDeviceNotFound)?;
+        for device in devices.iter() {
+            let device_desc = match device.device_descriptor() {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+
+            if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
+                match device.open() {
+                    Ok(handle) => return Ok((device, device_desc, handle)),
+                    Err(e) => return Err(PicobootError::UsbDeviceFailedToOpen(e.into())),
+                }
+            }
+        }
+
+        Err(PicobootError::UsbDeviceNotFound)
+    }
+
+    fn get_endpoint(
+        device: &Device<T>,
+        class: u8,
+        subclass: u8,
+        protocol: u8,
+        direction: Direction,
+        transfer_type: TransferType,
+    ) -> Option<(u8, u8, u8, u8)> {
+        let desc = device.device_descriptor().unwrap();
+        for n in 0..desc.num_configurations() {
+            let config_desc = match device.config_descriptor(n) {
+                Ok(c) => c,
+                Err(_) => continue,
+            };
+
+            for iface in config_desc.interfaces() {
+                for iface_desc in iface.descriptors() {
+                    let iface_class = iface_desc.class_code();
+                    let iface_subclass = iface_desc.sub_class_code();
+                    let iface_protocol = iface_desc.protocol_code();
+                    if !(iface_class == class
+                        && iface_subclass == subclass
+                        && iface_protocol == protocol)
+                    {
+                        continue;
+                    }
+
+                    for endpoint_desc in iface_desc.endpoint_descriptors() {
+                        if endpoint_desc.direction() == direction
+                            && endpoint_desc.transfer_type() == transfer_type
+                        {
+                            return Some((
+                                config_desc.number(),
+                                iface_desc.interface_number(),
+                                iface_desc.setting_number(),
+                                endpoint_desc.address(),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+impl<T: UsbContext> PicobootTransport for RusbTransport<T> {
+    fn read_bulk(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        self.check_open()?;
+        Ok(self.handle.read_bulk(self.in_addr, buf, timeout)?)
+    }
+
+    fn write_bulk(
+        &mut self,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        self.check_open()?;
+        Ok(self.handle.write_bulk(self.out_addr, buf, timeout)?)
+    }
+
+    fn read_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        self.check_open()?;
+        Ok(self
+            .handle
+            .read_control(request_type, request, value, index, buf, timeout)?)
+    }
+
+    fn write_control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> ::std::result::Result<usize, TransportError> {
+        self.check_open()?;
+        Ok(self
+            .handle
+            .write_control(request_type, request, value, index, buf, timeout)?)
+    }
+
+    fn clear_halt(&mut self, endpoint: Endpoint) -> ::std::result::Result<(), TransportError> {
+        self.check_open()?;
+        let addr = match endpoint {
+            Endpoint::In => self.in_addr,
+            Endpoint::Out => self.out_addr,
+        };
+        Ok(self.handle.clear_halt(addr)?)
+    }
+
+    fn interface_number(&self) -> u8 {
+        self.iface
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.release()
+    }
+}
@@ -0,0 +1,439 @@
+use crate::{cmd::PicobootError, image::SparseImage, PAGE_SIZE};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+type Error = PicobootError;
+type Result<T> = ::std::result::Result<T, Error>;
+
+/// First magic number at the start of a UF2 block
+pub const UF2_MAGIC_START0: u32 = 0x0A324655;
+/// Second magic number at the start of a UF2 block
+pub const UF2_MAGIC_START1: u32 = 0x9E5D5157;
+/// Magic number at the end of a UF2 block
+pub const UF2_MAGIC_END: u32 = 0x0AB16F30;
+
+/// UF2 block flag for data not to be written to main flash
+pub const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x00000001;
+/// UF2 block flag for a block of a file container
+pub const UF2_FLAG_FILE_CONTAINER: u32 = 0x00001000;
+/// UF2 block flag for a family ID in place of the file size
+pub const UF2_FLAG_FAMILY_ID_PRESENT: u32 = 0x00002000;
+/// UF2 block flag for an MD5 checksum after the payload
+pub const UF2_FLAG_MD5_PRESENT: u32 = 0x00004000;
+/// UF2 block flag for extension tags after the payload
+pub const UF2_FLAG_EXTENSION_TAGS_PRESENT: u32 = 0x00008000;
+
+/// Size of a UF2 block
+pub const UF2_BLOCK_SIZE: usize = 512;
+/// Maximum size of the payload of a UF2 block
+pub const UF2_MAX_PAYLOAD_SIZE: usize = 476;
+/// Size of the MD5 checksum area at the end of the data of a UF2 block
+const UF2_MD5_SIZE: usize = 24;
+
+const UF2_KNOWN_FLAGS: u32 = UF2_FLAG_NOT_MAIN_FLASH
+    | UF2_FLAG_FILE_CONTAINER
+    | UF2_FLAG_FAMILY_ID_PRESENT
+    | UF2_FLAG_MD5_PRESENT
+    | UF2_FLAG_EXTENSION_TAGS_PRESENT;
+
+fn word(block: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        block[offset],
+        block[offset + 1],
+        block[offset + 2],
+        block[offset + 3],
+    ])
+}
+
+/// Single 512 byte block of a UF2 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uf2Block {
+    flags: u32,
+    target_addr: u32,
+    block_no: u32,
+    num_blocks: u32,
+    file_size_or_family_id: u32,
+    payload: Vec<u8>,
+}
+impl Uf2Block {
+    /// Creates a block of main flash data for a family.
+    ///
+    /// # Panics
+    /// Panics if the payload is larger than [`UF2_MAX_PAYLOAD_SIZE`].
+    pub fn new(
+        target_addr: u32,
+        payload: &[u8],
+        block_no: u32,
+        num_blocks: u32,
+        family_id: u32,
+    ) -> Self {
+        assert!(
+            payload.len() <= UF2_MAX_PAYLOAD_SIZE,
+            "uf2 payload too large"
+        );
+        Uf2Block {
+            flags: UF2_FLAG_FAMILY_ID_PRESENT,
+            target_addr,
+            block_no,
+            num_blocks,
+            file_size_or_family_id: family_id,
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Parses a single UF2 block, checking its magic numbers, flags, payload
+    /// size and block number.
+    ///
+    /// # Errors:
+    /// - [`Error::Uf2SizeInvalid`]
+    /// - [`Error::Uf2MagicInvalid`]
+    /// - [`Error::Uf2FlagsUnsupported`]
+    /// - [`Error::Uf2PayloadSizeInvalid`]
+    /// - [`Error::Uf2BlockNumberInvalid`]
+    pub fn parse(block: &[u8]) -> Result<Self> {
+        Self::parse_at(block, 0)
+    }
+
+    /// Parses the block at position `index` of a file, used in errors.
+    fn parse_at(block: &[u8], index: usize) -> Result<Self> {
+        if block.len() != UF2_BLOCK_SIZE {
+            return Err(Error::Uf2SizeInvalid);
+        }
+        if word(block, 0) != UF2_MAGIC_START0
+            || word(block, 4) != UF2_MAGIC_START1
+            || word(block, UF2_BLOCK_SIZE - 4) != UF2_MAGIC_END
+        {
+            return Err(Error::Uf2MagicInvalid(index));
+        }
+
+        let flags = word(block, 8);
+        if flags & !UF2_KNOWN_FLAGS != 0 || flags & UF2_FLAG_FILE_CONTAINER != 0 {
+            return Err(Error::Uf2FlagsUnsupported(index));
+        }
+
+        let payload_size = word(block, 16) as usize;
+        let max_payload_size = if flags & UF2_FLAG_MD5_PRESENT != 0 {
+            UF2_MAX_PAYLOAD_SIZE - UF2_MD5_SIZE
+        } else {
+            UF2_MAX_PAYLOAD_SIZE
+        };
+        if payload_size > max_payload_size {
+            return Err(Error::Uf2PayloadSizeInvalid(index));
+        }
+
+        let block_no = word(block, 20);
+        let num_blocks = word(block, 24);
+        if block_no >= num_blocks {
+            return Err(Error::Uf2BlockNumberInvalid(index));
+        }
+
+        Ok(Uf2Block {
+            flags,
+            target_addr: word(block, 12),
+            block_no,
+            num_blocks,
+            file_size_or_family_id: word(block, 28),
+            payload: block[32..32 + payload_size].to_vec(),
+        })
+    }
+
+    /// Serializes the block. Data area bytes past the payload are zero.
+    pub fn ser(&self) -> [u8; UF2_BLOCK_SIZE] {
+        let mut block = [0; UF2_BLOCK_SIZE];
+        let words = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            self.flags,
+            self.target_addr,
+            self.payload.len() as u32,
+            self.block_no,
+            self.num_blocks,
+            self.file_size_or_family_id,
+        ];
+        for (i, w) in words.iter().enumerate() {
+            block[i * 4..i * 4 + 4].copy_from_slice(&w.to_le_bytes());
+        }
+        block[32..32 + self.payload.len()].copy_from_slice(&self.payload);
+        block[UF2_BLOCK_SIZE - 4..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+        block
+    }
+
+    pub fn get_flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn get_target_addr(&self) -> u32 {
+        self.target_addr
+    }
+
+    pub fn get_block_no(&self) -> u32 {
+        self.block_no
+    }
+
+    pub fn get_num_blocks(&self) -> u32 {
+        self.num_blocks
+    }
+
+    /// Returns the family ID of the block, if it has one.
+    pub fn get_family_id(&self) -> Option<u32> {
+        if self.flags & UF2_FLAG_FAMILY_ID_PRESENT != 0 {
+            Some(self.file_size_or_family_id)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the total file size, if the block has one instead of a family
+    /// ID.
+    pub fn get_file_size(&self) -> Option<u32> {
+        if self.flags & UF2_FLAG_FAMILY_ID_PRESENT != 0 {
+            None
+        } else {
+            Some(self.file_size_or_family_id)
+        }
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Returns `true` if the block is data for main flash (or RAM), rather
+    /// than a comment or other data to be ignored when flashing.
+    pub fn is_main_flash(&self) -> bool {
+        self.flags & UF2_FLAG_NOT_MAIN_FLASH == 0
+    }
+}
+impl fmt::Display for Uf2Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block {}/{} addr={:#010x} size={:#x}",
+            self.block_no,
+            self.num_blocks,
+            self.target_addr,
+            self.payload.len()
+        )?;
+        if let Some(family_id) = self.get_family_id() {
+            write!(f, " family={:#010x}", family_id)?;
+        }
+        if !self.is_main_flash() {
+            write!(f, " not-main-flash")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parsed UF2 file.
+///
+/// A file is a sequence of blocks, numbered from zero up to the total number
+/// of blocks. Files made for several families (or several images) are
+/// concatenations of such sequences, each starting again at block zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Uf2File {
+    blocks: Vec<Uf2Block>,
+}
+impl Uf2File {
+    /// Parses a UF2 file, checking every block as well as the numbering of
+    /// the blocks.
+    ///
+    /// # Errors:
+    /// - [`Error::Uf2SizeInvalid`]
+    /// - [`Error::Uf2MagicInvalid`]
+    /// - [`Error::Uf2FlagsUnsupported`]
+    /// - [`Error::Uf2PayloadSizeInvalid`]
+    /// - [`Error::Uf2BlockNumberInvalid`]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.is_empty() || data.len() % UF2_BLOCK_SIZE != 0 {
+            return Err(Error::Uf2SizeInvalid);
+        }
+
+        let mut blocks: Vec<Uf2Block> = Vec::with_capacity(data.len() / UF2_BLOCK_SIZE);
+        for (index, raw) in data.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+            let block = Uf2Block::parse_at(raw, index)?;
+
+            // each block either continues the current sequence or starts a
+            // new one, after the last block of the current sequence or for
+            // another family (picotool writes a lone absolute family block
+            // numbered as the first of two, as a workaround for RP2350-E10)
+            let in_sequence = match blocks.last() {
+                Some(prev) if prev.block_no + 1 < prev.num_blocks => {
+                    (block.block_no == prev.block_no + 1 && block.num_blocks == prev.num_blocks)
+                        || (block.block_no == 0 && block.get_family_id() != prev.get_family_id())
+                }
+                _ => block.block_no == 0,
+            };
+            if !in_sequence {
+                return Err(Error::Uf2BlockNumberInvalid(index));
+            }
+            blocks.push(block);
+        }
+
+        let last = blocks.last().unwrap();
+        if last.block_no + 1 != last.num_blocks {
+            return Err(Error::Uf2BlockNumberInvalid(blocks.len() - 1));
+        }
+        Ok(Uf2File { blocks })
+    }
+
+    /// Creates a UF2 file for a family from an image.
+    ///
+    /// Like picotool, the image is split into 256 byte blocks aligned to
+    /// flash pages, with bytes that have no data set to zero.
+    pub fn from_image(image: &SparseImage, family_id: u32) -> Self {
+        let pages = image.get_pages(PAGE_SIZE, 0);
+        let num_blocks = pages.len() as u32;
+        let blocks = pages
+            .iter()
+            .enumerate()
+            .map(|(i, (addr, page))| Uf2Block::new(*addr, page, i as u32, num_blocks, family_id))
+            .collect();
+        Uf2File { blocks }
+    }
+
+    /// Serializes the file.
+    pub fn ser(&self) -> Vec<u8> {
+        self.blocks.iter().flat_map(|b| b.ser()).collect()
+    }
+
+    pub fn get_blocks(&self) -> &[Uf2Block] {
+        &self.blocks
+    }
+
+    /// Returns the family IDs of the main flash blocks in the file, in
+    /// ascending order.
+    pub fn get_family_ids(&self) -> Vec<u32> {
+        let ids: BTreeSet<u32> = self
+            .blocks
+            .iter()
+            .filter(|b| b.is_main_flash())
+            .filter_map(Uf2Block::get_family_id)
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    /// Collects the main flash blocks of the file into an image.
+    ///
+    /// If `family_id` is given, only blocks of that family are used, which
+    /// is how the bootrom treats files holding several families. Otherwise
+    /// every main flash block is used. Blocks that are not for main flash
+    /// are always skipped.
+    pub fn to_image(&self, family_id: Option<u32>) -> SparseImage {
+        let mut image = SparseImage::new();
+        for block in self.blocks.iter().filter(|b| b.is_main_flash()) {
+            if family_id.map_or(true, |id| block.get_family_id() == Some(id)) {
+                image.write(block.target_addr, &block.payload);
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{UF2_ABSOLUTE_FAMILY_ID, UF2_RP2040_FAMILY_ID, UF2_RP2350_ARM_S_FAMILY_ID};
+
+    fn image() -> SparseImage {
+        let data: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+        SparseImage::new()
+            .with_data(0x1000_0010, &data)
+            .with_data(0x1000_1000, &[0xAA; 4])
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = Uf2File::from_image(&image(), UF2_RP2040_FAMILY_ID);
+        let data = file.ser();
+        assert_eq!(data.len(), 4 * UF2_BLOCK_SIZE);
+
+        let parsed = Uf2File::parse(&data).unwrap();
+        assert_eq!(parsed, file);
+        assert_eq!(parsed.get_family_ids(), vec![UF2_RP2040_FAMILY_ID]);
+
+        let blocks = parsed.get_blocks();
+        let addrs: Vec<u32> = blocks.iter().map(Uf2Block::get_target_addr).collect();
+        assert_eq!(addrs, [0x1000_0000, 0x1000_0100, 0x1000_0200, 0x1000_1000]);
+        assert!(blocks.iter().all(|b| b.get_num_blocks() == 4));
+
+        // pages are padded with zeros
+        let out = parsed.to_image(Some(UF2_RP2040_FAMILY_ID));
+        assert_eq!(out.read(0x1000_0000, 16), Some(&[0; 16][..]));
+        assert_eq!(out.read(0x1000_0010, 600), image().read(0x1000_0010, 600));
+        assert!(parsed.to_image(Some(UF2_RP2350_ARM_S_FAMILY_ID)).is_empty());
+    }
+
+    #[test]
+    fn rp2350_e10_sequence() {
+        // picotool prepends a lone absolute family block, numbered as the
+        // first of two, to RP2350 files
+        let mut data = Uf2Block::new(0x10FF_FF00, &[0xEF; 256], 0, 2, UF2_ABSOLUTE_FAMILY_ID)
+            .ser()
+            .to_vec();
+        data.extend(Uf2File::from_image(&image(), UF2_RP2350_ARM_S_FAMILY_ID).ser());
+
+        let file = Uf2File::parse(&data).unwrap();
+        assert_eq!(file.get_blocks().len(), 5);
+        assert_eq!(
+            file.get_family_ids(),
+            vec![UF2_ABSOLUTE_FAMILY_ID, UF2_RP2350_ARM_S_FAMILY_ID]
+        );
+        let rp2350 = file.to_image(Some(UF2_RP2350_ARM_S_FAMILY_ID));
+        assert_eq!(rp2350.get_start(), Some(0x1000_0000));
+        assert_eq!(rp2350.read(0x10FF_FF00, 1), None);
+    }
+
+    #[test]
+    fn rejects_broken_sequence() {
+        let blocks = Uf2File::from_image(&image(), UF2_RP2040_FAMILY_ID).ser();
+
+        // restarting the same family before the end of its sequence
+        let mut data = blocks[..UF2_BLOCK_SIZE].to_vec();
+        data.extend_from_slice(&blocks);
+        assert!(matches!(
+            Uf2File::parse(&data),
+            Err(Error::Uf2BlockNumberInvalid(1))
+        ));
+
+        // missing the last block
+        let data = &blocks[..3 * UF2_BLOCK_SIZE];
+        assert!(matches!(
+            Uf2File::parse(data),
+            Err(Error::Uf2BlockNumberInvalid(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let mut data = Uf2File::from_image(&image(), UF2_RP2040_FAMILY_ID).ser();
+        assert!(matches!(
+            Uf2File::parse(&data[..data.len() - 1]),
+            Err(Error::Uf2SizeInvalid)
+        ));
+        assert!(matches!(Uf2File::parse(&[]), Err(Error::Uf2SizeInvalid)));
+
+        data[UF2_BLOCK_SIZE + 4] ^= 1;
+        assert!(matches!(
+            Uf2File::parse(&data),
+            Err(Error::Uf2MagicInvalid(1))
+        ));
+        data[UF2_BLOCK_SIZE + 4] ^= 1;
+
+        let flags = UF2_FLAG_FAMILY_ID_PRESENT | UF2_FLAG_FILE_CONTAINER;
+        data[2 * UF2_BLOCK_SIZE + 8..2 * UF2_BLOCK_SIZE + 12].copy_from_slice(&flags.to_le_bytes());
+        assert!(matches!(
+            Uf2File::parse(&data),
+            Err(Error::Uf2FlagsUnsupported(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_payload() {
+        let mut block = Uf2Block::new(0x1000_0000, &[0; 256], 0, 1, UF2_RP2040_FAMILY_ID).ser();
+        block[16..20].copy_from_slice(&(UF2_MAX_PAYLOAD_SIZE as u32 + 1).to_le_bytes());
+        assert!(matches!(
+            Uf2Block::parse(&block),
+            Err(Error::Uf2PayloadSizeInvalid(0))
+        ));
+    }
+}
@@ -1,10 +1,28 @@
 use crate::{
-    cmd::{PicobootCmd, PicobootError, PicobootStatusCmd, TargetID},
-    PAGE_SIZE, PICOBOOT_PID_RP2040, PICOBOOT_PID_RP2350, PICOBOOT_VID, SECTOR_SIZE,
+    cmd::{PicobootCmd, PicobootCmdId, PicobootError, PicobootStatusCmd, TargetID},
+    elf::ElfFile,
+    image::SparseImage,
+    info::{ChipInfo, PartitionInfo, PartitionTableInfo, SysInfo, Uf2Status},
+    progress::{CancelToken, ProgressObserver, ProgressPhase},
+    transport::{Endpoint, PicobootTransport, TransportError},
+    Reboot2Options, Reboot2Type, FLASH_END_RP2040, FLASH_END_RP2350, FLASH_START, OTP_ROW_COUNT,
+    PAGE_SIZE, PT_INFO_PARTITION_FAMILY_IDS, PT_INFO_PARTITION_ID,
+    PT_INFO_PARTITION_LOCATION_AND_FLAGS, PT_INFO_PARTITION_NAME, PT_INFO_SINGLE_PARTITION,
+    ROM_IDENTITY_ADDR, SECTOR_SIZE, SRAM_END_RP2040, SRAM_END_RP2350, SRAM_START_RP2040,
+    STACK_POINTER_RP2040, SYS_INFO_CHIP_INFO, SYS_INFO_CPU_INFO, SYS_INFO_CRITICAL,
+    SYS_INFO_FLASH_DEV_INFO, XIP_SRAM_END_RP2040, XIP_SRAM_END_RP2350, XIP_SRAM_START_RP2040,
+    XIP_SRAM_START_RP2350,
+};
+#[cfg(feature = "rusb")]
+use crate::{
+    transport::{self, DeviceSelector, RusbTransport},
+    PICOBOOT_PID_RP2040, PICOBOOT_PID_RP2350, PICOBOOT_VID,
 };
 
-use bincode;
-use rusb::{Device, DeviceDescriptor, DeviceHandle, Direction, TransferType, UsbContext};
+#[cfg(feature = "rusb")]
+use rusb::{DeviceDescriptor, UsbContext};
+use std::fmt;
+use std::time::Duration;
 
 // see https://github.com/raspberrypi/picotool/blob/master/main.cpp#L4173
 // for loading firmware over a connection
@@ -12,221 +30,397 @@ use rusb::{Device, DeviceDescriptor, DeviceHandle, Direction, TransferType, UsbC
 type Error = PicobootError;
 type Result<T> = ::std::result::Result<T, Error>;
 
+/// Retry policy of a [`PicobootConnection`]
+///
+/// When an idempotent command (see [`PicobootCmdId::is_idempotent`]) fails
+/// because of a USB transfer problem, such as a stall or a timeout, the
+/// connection waits for `delay`, resets the PICOBOOT interface with
+/// [`PicobootConnection::reset_interface`] and sends the command again, up to
+/// `max_retries` times. Commands rejected by the device are not retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    delay: Duration,
+}
+impl RetryPolicy {
+    /// Creates a retry policy that retries up to `max_retries` times, waiting
+    /// `delay` before each retry.
+    pub fn new(max_retries: u32, delay: Duration) -> Self {
+        RetryPolicy { max_retries, delay }
+    }
+
+    /// Creates a retry policy that never retries.
+    pub fn never() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+
+    /// Returns the maximum number of retries of a command.
+    pub fn get_max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Returns the time waited before each retry.
+    pub fn get_delay(&self) -> Duration {
+        self.delay
+    }
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::never()
+    }
+}
+
+/// Configuration of a [`PicobootConnection`]
+///
+/// Sets the timeouts of each class of USB transfer made by the connection, and
+/// its [`RetryPolicy`].
+///
+/// - Command timeout: sending a command and its acknowledgement.
+/// - Data timeout: the data phase of commands that read or write memory.
+/// - Erase timeout: waiting for a flash erase to complete.
+/// - Status timeout: control transfers that query the command status or reset
+///   the interface.
+///
+/// It also sets the chunk size of multi-command operations, such as
+/// [`PicobootConnection::load`], which erase, write or read memory in chunks
+/// of up to that size, each aligned to its own size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    cmd_timeout: Duration,
+    data_timeout: Duration,
+    erase_timeout: Duration,
+    status_timeout: Duration,
+    retry: RetryPolicy,
+    chunk_size: u32,
+}
+impl ConnectionConfig {
+    /// Creates a configuration with the default timeouts, no retries and
+    /// 32 KiB chunks.
+    pub fn new() -> Self {
+        ConnectionConfig {
+            cmd_timeout: Duration::from_secs(5),
+            data_timeout: Duration::from_secs(5),
+            erase_timeout: Duration::from_secs(10),
+            status_timeout: Duration::from_secs(1),
+            retry: RetryPolicy::never(),
+            chunk_size: 0x8000,
+        }
+    }
+
+    /// Sets the timeout for sending commands and their acknowledgements.
+    pub fn with_cmd_timeout(mut self, timeout: Duration) -> Self {
+        self.cmd_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for the data phase of commands.
+    pub fn with_data_timeout(mut self, timeout: Duration) -> Self {
+        self.data_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for waiting on a flash erase to complete.
+    pub fn with_erase_timeout(mut self, timeout: Duration) -> Self {
+        self.erase_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for command status and interface reset control
+    /// transfers.
+    pub fn with_status_timeout(mut self, timeout: Duration) -> Self {
+        self.status_timeout = timeout;
+        self
+    }
+
+    /// Sets the retry policy for failed commands.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the largest number of bytes erased, written or read by one
+    /// command of a multi-command operation. The size is rounded down to a
+    /// multiple of [`SECTOR_SIZE`], and is at least one sector.
+    pub fn with_chunk_size(mut self, size: u32) -> Self {
+        self.chunk_size = (size - size % SECTOR_SIZE).max(SECTOR_SIZE);
+        self
+    }
+
+    /// Returns the timeout for sending commands and their acknowledgements.
+    pub fn get_cmd_timeout(&self) -> Duration {
+        self.cmd_timeout
+    }
+
+    /// Returns the timeout for the data phase of commands.
+    pub fn get_data_timeout(&self) -> Duration {
+        self.data_timeout
+    }
+
+    /// Returns the timeout for waiting on a flash erase to complete.
+    pub fn get_erase_timeout(&self) -> Duration {
+        self.erase_timeout
+    }
+
+    /// Returns the timeout for command status and interface reset control
+    /// transfers.
+    pub fn get_status_timeout(&self) -> Duration {
+        self.status_timeout
+    }
+
+    /// Returns the retry policy for failed commands.
+    pub fn get_retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Returns the largest number of bytes erased, written or read by one
+    /// command of a multi-command operation.
+    pub fn get_chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+}
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Options of [`PicobootConnection::load`]
+///
+/// By default, loaded data is verified and the device is not rebooted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    verify: bool,
+    reboot: Option<u32>,
+}
+impl LoadOptions {
+    /// Creates the default load options.
+    pub fn new() -> Self {
+        LoadOptions {
+            verify: true,
+            reboot: None,
+        }
+    }
+
+    /// Sets whether loaded data is read back and compared with the image.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Reboots the device into the loaded firmware after a delay in
+    /// milliseconds, once loading succeeds.
+    pub fn with_reboot(mut self, delay: u32) -> Self {
+        self.reboot = Some(delay);
+        self
+    }
+
+    /// Returns `true` if loaded data is verified.
+    pub fn get_verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Returns the delay in milliseconds before rebooting the device, if it
+    /// is rebooted after loading.
+    pub fn get_reboot(&self) -> Option<u32> {
+        self.reboot
+    }
+}
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A connection to a PICOBOOT device
 ///
 /// This structure contains shorthand functions for send commands with checks to
-/// ensure safety with use of PICOBOOT interface commands.
+/// ensure safety with use of PICOBOOT interface commands. The underlying USB
+/// transfers are performed by a [`PicobootTransport`], which is a
+/// `RusbTransport` when the connection is created with `Self::new`, or a
+/// `NusbTransport` when created with `Self::new_nusb`.
 #[derive(Debug)]
-pub struct PicobootConnection<T: UsbContext> {
-    _context: T,
-    _device: Device<T>,
-    _desc: DeviceDescriptor,
-    handle: DeviceHandle<T>,
-
-    _cfg: u8,
-    iface: u8,
-    _setting: u8,
-    in_addr: u8,
-    out_addr: u8,
+pub struct PicobootConnection<T: PicobootTransport> {
+    transport: T,
+    config: ConnectionConfig,
+    observer: Option<Observer>,
+    cancel: CancelToken,
 
     cmd_token: u32,
-    has_kernel_driver: bool,
     target_id: TargetID,
+    chip_info: Option<ChipInfo>,
 }
-impl<T: UsbContext> Drop for PicobootConnection<T> {
-    fn drop(&mut self) {
-        self.handle
-            .release_interface(self.iface)
-            .expect("could not release interface");
-
-        if self.has_kernel_driver {
-            self.handle
-                .attach_kernel_driver(self.iface)
-                .expect("could not retach kernel driver");
-        }
-    }
-}
-impl<T: UsbContext> PicobootConnection<T> {
+#[cfg(feature = "rusb")]
+impl<T: UsbContext> PicobootConnection<RusbTransport<T>> {
     /// Creates a new PICOBOOT connection
     ///
-    /// Takes a rusb context and a USB VID/PID pair tuple. The VID/PID pair
-    /// dictates how the connection determines the target. If `None` is
+    /// Takes a rusb context and a USB VID/PID pair tuple. If `None` is
     /// provided, the connection attempts both RP2040 and RP2350 VID/PID pairs.
-    /// If a VID/PID pair is provided, and if the pair belongs to the RP2040,
-    /// the target will be considered an RP2040. Otherwise, the target will be
-    /// considered an RP2350.
+    /// After opening, the PICOBOOT interface is reset and the target is
+    /// confirmed with [`Self::identify`], so devices with other VID/PID pairs
+    /// are detected correctly.
     ///
     /// # Errors
     /// - [`Error::UsbDeviceNotFound`]
+    /// - [`Error::UsbDeviceFailedToOpen`]
     /// - [`Error::UsbEndpointsNotFound`]
     /// - [`Error::UsbEndpointsUnexpected`]
     /// - [`Error::UsbDetachKernelDriverFailure`]
     /// - [`Error::UsbClaimInterfaceFailure`]
     /// - [`Error::UsbSetAltSettingFailure`]
-    ///
-    /// # Panics
-    /// - If the appropriate USB device is found but cannot be opened, this function will panic.
-    pub fn new(mut ctx: T, vidpid: Option<(u16, u16)>) -> Result<Self> {
-        // simple heuristic for determining target type
-        let dev = match vidpid {
+    /// - Any produced by [`Self::reset_interface`]
+    /// - Any produced by [`Self::identify`]
+    pub fn new(ctx: T, vidpid: Option<(u16, u16)>) -> Result<Self> {
+        // initial guess of the target type, confirmed once connected
+        let (transport, target_id) = match vidpid {
             Some((vid, pid)) => {
-                let id = match (vid, pid) {
-                    (PICOBOOT_VID, PICOBOOT_PID_RP2040) => TargetID::Rp2040,
-                    _ => TargetID::Rp2350,
-                };
-                Self::open_device(&mut ctx, vid, pid).map(|d| (d, id))
+                let id = TargetID::from_usb_ids(vid, pid).unwrap_or(TargetID::Rp2350);
+                (RusbTransport::open(ctx, vid, pid)?, id)
             }
-            None => match Self::open_device(&mut ctx, PICOBOOT_VID, PICOBOOT_PID_RP2040) {
-                Ok(d) => Ok((d, TargetID::Rp2040)),
-                Err(e) => match e {
-                    PicobootError::UsbDeviceNotFound => {
-                        match Self::open_device(&mut ctx, PICOBOOT_VID, PICOBOOT_PID_RP2350) {
-                            Ok(d) => Ok((d, TargetID::Rp2350)),
-                            Err(e) => Err(e),
-                        }
-                    }
-                    _ => Err(e),
-                },
+            None => match RusbTransport::open(ctx.clone(), PICOBOOT_VID, PICOBOOT_PID_RP2040) {
+                Ok(t) => (t, TargetID::Rp2040),
+                Err(PicobootError::UsbDeviceNotFound) => (
+                    RusbTransport::open(ctx, PICOBOOT_VID, PICOBOOT_PID_RP2350)?,
+                    TargetID::Rp2350,
+                ),
+                Err(e) => return Err(e),
             },
         };
 
-        let (device, desc, handle, target_id) = match dev {
-            Ok(((device, desc, handle), target_id)) => (device, desc, handle, target_id),
-            Err(e) => {
-                return Err(e);
-            }
-        };
+        Self::from_transport(transport, target_id).confirm_target()
+    }
 
-        let e1 = Self::get_endpoint(&device, 255, 0, 0, Direction::In, TransferType::Bulk);
-        let e2 = Self::get_endpoint(&device, 255, 0, 0, Direction::Out, TransferType::Bulk);
+    /// Creates a new PICOBOOT connection to a selected device
+    ///
+    /// Takes a rusb context and a [`DeviceSelector`] choosing the device by
+    /// VID/PID pair, USB serial number, bus and port chain, or a predicate over
+    /// its [`DeviceDescriptor`]. The first matching device is opened. The
+    /// target is confirmed as in [`Self::new`].
+    ///
+    /// # Errors
+    /// - Any produced by [`transport::rusb::find_device`]
+    /// - Any produced by [`transport::rusb::PicobootDevice::open`]
+    pub fn open(ctx: T, selector: &DeviceSelector<DeviceDescriptor>) -> Result<Self> {
+        transport::rusb::find_device(&ctx, selector)?.open()
+    }
+}
+impl<T: PicobootTransport> PicobootConnection<T> {
+    /// Creates a new PICOBOOT connection over an existing transport
+    ///
+    /// The transport is expected to already have the PICOBOOT interface of a
+    /// device of type `target_id` ready for transfers. The target is not
+    /// confirmed, use [`Self::identify`] to query it from the device.
+    pub fn from_transport(transport: T, target_id: TargetID) -> Self {
+        PicobootConnection {
+            transport,
+            config: ConnectionConfig::new(),
+            observer: None,
+            cancel: CancelToken::new(),
 
-        let (cfg, iface, setting, in_addr, out_addr) = match (e1, e2) {
-            (None, _) | (_, None) => return Err(Error::UsbEndpointsNotFound),
-            (Some((c1, i1, s1, in_addr)), Some((c2, i2, s2, out_addr))) => {
-                if (c1, i1, s1) == (c2, i2, s2) {
-                    (c2, i2, s2, in_addr, out_addr)
-                } else {
-                    return Err(Error::UsbEndpointsUnexpected);
-                }
-            }
-        };
+            cmd_token: 1,
+            target_id,
+            chip_info: None,
+        }
+    }
 
-        let has_kernel_driver = if let Ok(true) = handle.kernel_driver_active(iface) {
-            handle
-                .detach_kernel_driver(iface)
-                .map_err(Error::UsbDetachKernelDriverFailure)?;
-            true
-        } else {
-            false
-        };
+    /// Resets the PICOBOOT interface of a newly opened connection and
+    /// confirms its target.
+    #[cfg(any(feature = "rusb", feature = "nusb"))]
+    pub(crate) fn confirm_target(mut self) -> Result<Self> {
+        self.reset_interface()?;
+        self.identify()?;
+        Ok(self)
+    }
 
-        if handle.set_active_configuration(cfg).is_err() {
-            // println!("Warning: could not set USB active configuration");
-        }
+    /// Sets the timeouts and retry policy of the connection.
+    pub fn with_config(mut self, config: ConnectionConfig) -> Self {
+        self.config = config;
+        self
+    }
 
-        handle
-            .claim_interface(iface)
-            .map_err(Error::UsbClaimInterfaceFailure)?;
-        handle
-            .set_alternate_setting(iface, setting)
-            .map_err(Error::UsbSetAltSettingFailure)?;
+    /// Sets the timeouts and retry policy of the connection.
+    pub fn set_config(&mut self, config: ConnectionConfig) {
+        self.config = config;
+    }
 
-        Ok(PicobootConnection {
-            _context: ctx,
-            _device: device,
-            _desc: desc,
-            handle,
+    /// Sets the observer of the progress of multi-command operations.
+    pub fn with_observer(mut self, observer: impl ProgressObserver + Send + 'static) -> Self {
+        self.set_observer(observer);
+        self
+    }
 
-            _cfg: cfg,
-            iface,
-            _setting: setting,
-            in_addr,
-            out_addr,
+    /// Sets the observer of the progress of multi-command operations.
+    pub fn set_observer(&mut self, observer: impl ProgressObserver + Send + 'static) {
+        self.observer = Some(Observer(Box::new(observer)));
+    }
 
-            cmd_token: 1,
-            has_kernel_driver,
-            target_id,
-        })
-    }
-
-    fn open_device(
-        ctx: &mut T,
-        vid: u16,
-        pid: u16,
-    ) -> Result<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
-        // ) -> Option<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
-        let devices = ctx
-            .devices()
-            .map_err(|_| PicobootError::UsbDeviceNotFound)?;
-        for device in devices.iter() {
-            let device_desc = match device.device_descriptor() {
-                Ok(d) => d,
-                Err(_) => continue,
-            };
-
-            if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
-                match device.open() {
-                    Ok(handle) => return Ok((device, device_desc, handle)),
-                    Err(e) => return Err(PicobootError::UsbDeviceFailedToOpen(e)),
-                }
-            }
-        }
+    /// Removes the observer of the progress of multi-command operations.
+    pub fn remove_observer(&mut self) {
+        self.observer = None;
+    }
 
-        Err(PicobootError::UsbDeviceNotFound)
-    }
-
-    fn get_endpoint(
-        device: &Device<T>,
-        class: u8,
-        subclass: u8,
-        protocol: u8,
-        direction: Direction,
-        transfer_type: TransferType,
-    ) -> Option<(u8, u8, u8, u8)> {
-        let desc = device.device_descriptor().unwrap();
-        for n in 0..desc.num_configurations() {
-            let config_desc = match device.config_descriptor(n) {
-                Ok(c) => c,
-                Err(_) => continue,
-            };
-
-            for iface in config_desc.interfaces() {
-                for iface_desc in iface.descriptors() {
-                    let iface_class = iface_desc.class_code();
-                    let iface_subclass = iface_desc.sub_class_code();
-                    let iface_protocol = iface_desc.protocol_code();
-                    if !(iface_class == class
-                        && iface_subclass == subclass
-                        && iface_protocol == protocol)
-                    {
-                        continue;
-                    }
+    /// Sets the token checked between the commands of multi-command
+    /// operations.
+    pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
 
-                    for endpoint_desc in iface_desc.endpoint_descriptors() {
-                        if endpoint_desc.direction() == direction
-                            && endpoint_desc.transfer_type() == transfer_type
-                        {
-                            return Some((
-                                config_desc.number(),
-                                iface_desc.interface_number(),
-                                iface_desc.setting_number(),
-                                endpoint_desc.address(),
-                            ));
-                        }
-                    }
-                }
-            }
-        }
+    /// Sets the token checked between the commands of multi-command
+    /// operations.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        self.cancel = token;
+    }
 
-        None
+    /// Returns the token checked between the commands of multi-command
+    /// operations. Cancelling it, for example from another thread, stops the
+    /// current operation before its next command.
+    pub fn get_cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Returns the timeouts and retry policy of the connection.
+    pub fn get_config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// Returns a reference to the underlying transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns a mutable reference to the underlying transport.
+    ///
+    /// Transfers made directly on the transport bypass the connection and may
+    /// leave the PICOBOOT interface in an unexpected state.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Closes the connection
+    ///
+    /// Releases the PICOBOOT interface and reattaches any kernel driver that
+    /// was detached when the connection was opened. Dropping a connection does
+    /// the same, but silently ignores any failure. After a reboot the device
+    /// is usually gone, in which case releasing the interface fails with
+    /// [`crate::TransportError::Disconnected`].
+    ///
+    /// # Errors
+    /// - [`Error::UsbReleaseInterfaceFailure`]
+    /// - [`Error::UsbAttachKernelDriverFailure`]
+    pub fn close(mut self) -> Result<()> {
+        self.transport.close()
     }
 
-    fn bulk_read(&mut self, buf_size: usize, check: bool) -> Result<Vec<u8>> {
+    /// Consumes the connection, returning the underlying transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn bulk_read(&mut self, buf_size: usize, check: bool, timeout: Duration) -> Result<Vec<u8>> {
         let mut buf = vec![0; buf_size];
-        let timeout = std::time::Duration::from_secs(3);
         let len = self
-            .handle
-            .read_bulk(self.in_addr, &mut buf, timeout)
+            .transport
+            .read_bulk(&mut buf, timeout)
             .map_err(Error::UsbReadBulkFailure)?;
 
         if check && len != buf_size {
@@ -237,11 +431,10 @@ impl<T: UsbContext> PicobootConnection<T> {
         Ok(buf)
     }
 
-    fn bulk_write(&mut self, buf: &[u8], check: bool) -> Result<()> {
-        let timeout = std::time::Duration::from_secs(5);
+    fn bulk_write(&mut self, buf: &[u8], check: bool, timeout: Duration) -> Result<()> {
         let len = self
-            .handle
-            .write_bulk(self.out_addr, buf, timeout)
+            .transport
+            .write_bulk(buf, timeout)
             .map_err(Error::UsbWriteBulkFailure)?;
 
         if check && len != buf.len() {
@@ -257,43 +450,135 @@ impl<T: UsbContext> PicobootConnection<T> {
     /// buffer argument may be used to send data to the device. Depending on the
     /// command, the returned Vec will contain data from the device.
     ///
+    /// The command status is checked after the command and after any data
+    /// transfer. If the device rejects the command, the matching `Status*`
+    /// error is returned with the memory range of the command.
+    ///
     /// # Errors
-    /// - [`Error::CmdSerializeFailure`]
     /// - [`Error::UsbWriteBulkFailure`]
     /// - [`Error::UsbWriteBulkMismatch`]
     /// - [`Error::UsbReadBulkFailure`]
     /// - [`Error::UsbReadBulkMismatch`]
+    /// - [`Error::UsbGetCommandStatusFailure`]
+    /// - [`Error::StatusTokenMismatch`]
+    /// - [`Error::StatusCmdIdMismatch`]
+    /// - [`Error::StatusCodeUnknown`]
+    /// - Any `Status*` error created by [`Error::from_status`]
+    ///
+    /// Idempotent commands that fail because of a USB transfer problem are
+    /// retried according to the [`RetryPolicy`] of the connection.
     pub fn cmd(&mut self, cmd: PicobootCmd, buf: &[u8]) -> Result<Vec<u8>> {
+        let retry = self.config.retry;
+        let idempotent = cmd.get_cmd_id().map_or(false, |id| id.is_idempotent());
+
+        let mut attempt = 0;
+        loop {
+            match self.cmd_once(cmd.clone(), buf) {
+                Err(e) if idempotent && attempt < retry.max_retries && Self::is_retryable(&e) => {
+                    attempt += 1;
+                    std::thread::sleep(retry.delay);
+                    if self.reset_interface().is_err() {
+                        return Err(e);
+                    }
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Returns `true` if an error is a USB transfer problem that may not occur
+    /// again when the command is retried.
+    fn is_retryable(e: &Error) -> bool {
+        match e {
+            Error::UsbReadBulkFailure(t)
+            | Error::UsbWriteBulkFailure(t)
+            | Error::UsbGetCommandStatusFailure(t) => *t != TransportError::Disconnected,
+            Error::UsbReadBulkMismatch
+            | Error::UsbWriteBulkMismatch
+            | Error::StatusTokenMismatch { .. }
+            | Error::StatusCmdIdMismatch { .. } => true,
+            _ => false,
+        }
+    }
+
+    fn cmd_once(&mut self, cmd: PicobootCmd, buf: &[u8]) -> Result<Vec<u8>> {
         let cmd = cmd.set_token(self.cmd_token);
         self.cmd_token += 1;
 
+        let cmd_timeout = self.config.cmd_timeout;
+        let data_timeout = self.config.data_timeout;
+        let ack_timeout = match cmd.get_cmd_id() {
+            Ok(PicobootCmdId::FlashErase) => self.config.erase_timeout,
+            _ => cmd_timeout,
+        };
+
         // write command
-        let cmdu8 = bincode::serialize(&cmd).map_err(Error::CmdSerializeFailure)?;
-        self.bulk_write(cmdu8.as_slice(), true)?;
-        let _stat = self.get_command_status();
+        self.bulk_write(&cmd.to_bytes(), true, cmd_timeout)
+            .map_err(|e| self.cmd_failure(&cmd, e))?;
+        self.check_command_status(&cmd)?;
 
         // if we're reading or writing a buffer
         let l = cmd.get_transfer_len().try_into().unwrap();
         let mut res = vec![];
         if l != 0 {
-            if ((cmd.get_cmd_id() as u8) & 0x80) != 0 {
-                res = self.bulk_read(l, true)?;
+            if cmd.is_in() {
+                res = self
+                    .bulk_read(l, true, data_timeout)
+                    .map_err(|e| self.cmd_failure(&cmd, e))?;
             } else {
-                self.bulk_write(buf, true)?;
+                self.bulk_write(buf, true, data_timeout)
+                    .map_err(|e| self.cmd_failure(&cmd, e))?;
             }
-            let _stat = self.get_command_status();
+            self.check_command_status(&cmd)?;
         }
 
         // do ack
-        if ((cmd.get_cmd_id() as u8) & 0x80) != 0 {
-            self.bulk_write(&[0u8; 1], false)?;
+        if cmd.is_in() {
+            self.bulk_write(&[0u8; 1], false, ack_timeout)
+                .map_err(|e| self.cmd_failure(&cmd, e))?;
         } else {
-            self.bulk_read(1, false)?;
+            self.bulk_read(1, false, ack_timeout)
+                .map_err(|e| self.cmd_failure(&cmd, e))?;
         }
 
         Ok(res)
     }
 
+    /// Checks the command status reported by the device against a command
+    /// that was sent.
+    fn check_command_status(&mut self, cmd: &PicobootCmd) -> Result<()> {
+        let stat = self.get_command_status()?;
+
+        if stat.get_token() != cmd.get_token() {
+            return Err(Error::StatusTokenMismatch {
+                expected: cmd.get_token(),
+                got: stat.get_token(),
+            });
+        }
+        if stat.get_cmd_id() != cmd.get_raw_cmd_id() {
+            return Err(Error::StatusCmdIdMismatch {
+                expected: cmd.get_raw_cmd_id(),
+                got: stat.get_cmd_id(),
+            });
+        }
+
+        let (addr, size) = cmd.get_range();
+        match Error::from_status(stat.get_status_code()?, addr, size) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Converts a failed transfer of a command into the error reported by the
+    /// device, if the device rejected the command. Otherwise the transfer
+    /// error is returned unchanged.
+    fn cmd_failure(&mut self, cmd: &PicobootCmd, err: Error) -> Error {
+        match self.check_command_status(cmd) {
+            Err(e) if e.status().is_some() => e,
+            _ => err,
+        }
+    }
+
     /// Requests non-exclusive access with the device, and does not close the
     /// USB Mass Storage interface.
     ///
@@ -349,11 +634,25 @@ impl<T: UsbContext> PicobootConnection<T> {
     /// - [`Error::CmdNotAllowedForTarget`]
     /// - Any produced by [`Self::cmd`]
     pub fn reboot2_normal(&mut self, delay: u32) -> Result<()> {
+        self.reboot2(Reboot2Options::new(Reboot2Type::Normal), delay)
+    }
+
+    /// Reboots the device into a boot type with options, after a delay in
+    /// milliseconds. (Only for RP2350)
+    ///
+    /// - `options` - Boot type, such as BOOTSEL or a RAM image, and options
+    ///   such as the architecture to switch to.
+    /// - `delay` - Time in milliseconds to start the device after.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn reboot2(&mut self, options: Reboot2Options, delay: u32) -> Result<()> {
         if let TargetID::Rp2040 = self.target_id {
             return Err(Error::CmdNotAllowedForTarget);
         }
 
-        self.cmd(PicobootCmd::reboot2_normal(delay), &[0u8; 0])?;
+        self.cmd(PicobootCmd::reboot2(options, delay), &[0u8; 0])?;
         Ok(())
     }
 
@@ -406,6 +705,434 @@ impl<T: UsbContext> PicobootConnection<T> {
         self.cmd(PicobootCmd::flash_read(addr, size), &[0u8; 0])
     }
 
+    /// Erases a range of flash memory, one chunk at a time.
+    ///
+    /// Reports the [`ProgressPhase::Erase`] phase, and stops before the next
+    /// chunk if the operation is cancelled.
+    ///
+    /// - `addr` - Address to start the erase. Must be on a multiple of [`SECTOR_SIZE`].
+    /// - `size` - Number of bytes to erase. Must be a multiple of [`SECTOR_SIZE`].
+    ///
+    /// # Errors:
+    /// - [`Error::EraseInvalidAddr`]
+    /// - [`Error::EraseInvalidSize`]
+    /// - [`Error::CmdCancelled`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn erase_range(&mut self, addr: u32, size: u32) -> Result<()> {
+        if addr % SECTOR_SIZE != 0 {
+            return Err(Error::EraseInvalidAddr);
+        }
+        if size % SECTOR_SIZE != 0 {
+            return Err(Error::EraseInvalidSize);
+        }
+
+        let ranges = chunk_ranges(addr, size, self.config.chunk_size);
+        self.erase_chunks(&ranges)
+    }
+
+    fn erase_chunks(&mut self, ranges: &[(u32, u32)]) -> Result<()> {
+        let total = ranges.iter().map(|(_, size)| size).sum();
+        let mut done = 0;
+        self.report(ProgressPhase::Erase, done, total);
+        for &(addr, size) in ranges {
+            self.check_cancelled()?;
+            self.flash_erase(addr, size)?;
+            done += size;
+            self.report(ProgressPhase::Erase, done, total);
+        }
+        Ok(())
+    }
+
+    /// Reads a range of memory, one chunk at a time.
+    ///
+    /// Reports the [`ProgressPhase::Read`] phase, and stops before the next
+    /// chunk if the operation is cancelled.
+    ///
+    /// - `addr` - Address to start the read.
+    /// - `size` - Number of bytes to read.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdCancelled`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn dump(&mut self, addr: u32, size: u32) -> Result<Vec<u8>> {
+        let total = size;
+        let mut data = Vec::with_capacity(total as usize);
+        self.report(ProgressPhase::Read, 0, total);
+        for (addr, size) in chunk_ranges(addr, total, self.config.chunk_size) {
+            self.check_cancelled()?;
+            data.extend(self.flash_read(addr, size)?);
+            self.report(ProgressPhase::Read, data.len() as u32, total);
+        }
+        Ok(data)
+    }
+
+    /// Reads the memory covered by an image back, one chunk at a time, and
+    /// compares it with the image.
+    ///
+    /// Reports the [`ProgressPhase::Verify`] phase, and stops before the next
+    /// chunk if the operation is cancelled.
+    ///
+    /// # Errors:
+    /// - [`Error::VerifyMismatch`]
+    /// - [`Error::CmdCancelled`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn verify(&mut self, image: &SparseImage) -> Result<()> {
+        let total = image.len() as u32;
+        let mut done = 0;
+        self.report(ProgressPhase::Verify, done, total);
+        for (start, seg) in image.get_segments() {
+            for (addr, size) in chunk_ranges(start, seg.len() as u32, self.config.chunk_size) {
+                self.check_cancelled()?;
+                let read = self.flash_read(addr, size)?;
+                let offset = (addr - start) as usize;
+                let expected = &seg[offset..offset + size as usize];
+                if let Some(i) = expected.iter().zip(&read).position(|(a, b)| a != b) {
+                    return Err(Error::VerifyMismatch(addr + i as u32));
+                }
+                if read.len() != expected.len() {
+                    return Err(Error::VerifyMismatch(addr + read.len() as u32));
+                }
+                done += size;
+                self.report(ProgressPhase::Verify, done, total);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads an image into the flash memory and RAM of the device.
+    ///
+    /// Takes exclusive access of the device (ejecting the USB Mass Storage
+    /// interface) and exits XIP mode, then erases every flash sector holding
+    /// data of the image and writes its pages. Bytes of a written page that
+    /// have no data in the image are written as zero, and the rest of an
+    /// erased sector is left erased. Data in SRAM or XIP SRAM is written as
+    /// is. Erases and writes are split into aligned chunks, see
+    /// [`ConnectionConfig::with_chunk_size`].
+    ///
+    /// If the image has data in flash, rebooting starts the firmware in
+    /// flash. Otherwise, an RP2040 is started at the lowest address of the
+    /// image, and an RP2350 boots the image in RAM, searching the SRAM or XIP
+    /// SRAM holding the lowest address of the image up to the end of its data
+    /// there.
+    ///
+    /// Reports the [`ProgressPhase::Erase`], [`ProgressPhase::Write`] and
+    /// [`ProgressPhase::Verify`] phases. If the operation is cancelled, it
+    /// stops before the next command or the reboot, re-enters XIP mode and
+    /// gives up exclusive access, leaving the device as it was before loading except
+    /// for the partially loaded memory.
+    ///
+    /// - `image` - Image to load. Each of its segments must be in the flash,
+    ///   SRAM or XIP SRAM of the device.
+    /// - `options` - Whether to verify the written data and reboot the
+    ///   device into it afterwards.
+    ///
+    /// # Errors:
+    /// - [`Error::LoadInvalidAddr`]
+    /// - [`Error::VerifyMismatch`]
+    /// - [`Error::CmdCancelled`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn load(&mut self, image: &SparseImage, options: LoadOptions) -> Result<()> {
+        self.load_image(image, None, options)
+    }
+
+    /// Loads the loadable segments of an ELF file into the flash memory and
+    /// RAM of the device, at their physical addresses.
+    ///
+    /// Works like [`Self::load`], except that an RP2040 loaded only with RAM
+    /// data is started at the entry point of the file. Overlapping segments
+    /// are not an error, the data of the later segment is loaded. Use
+    /// [`ElfFile::get_overlaps`] to warn about them.
+    ///
+    /// # Errors:
+    /// - [`Error::LoadInvalidAddr`]
+    /// - [`Error::VerifyMismatch`]
+    /// - [`Error::CmdCancelled`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn load_elf(&mut self, elf: &ElfFile, options: LoadOptions) -> Result<()> {
+        self.load_image(&elf.to_image(), Some(elf.get_entry()), options)
+    }
+
+    fn load_image(
+        &mut self,
+        image: &SparseImage,
+        entry: Option<u32>,
+        options: LoadOptions,
+    ) -> Result<()> {
+        let mut flash = SparseImage::new();
+        let mut ram = SparseImage::new();
+        for (addr, seg) in image.get_segments() {
+            match load_region(self.target_id, addr, seg.len() as u32) {
+                Some(LoadRegion::Flash) => flash.write(addr, seg),
+                Some(LoadRegion::Sram | LoadRegion::XipSram) => ram.write(addr, seg),
+                None => return Err(Error::LoadInvalidAddr(addr)),
+            }
+        }
+
+        // group flash pages into chunks that do not cross a chunk boundary
+        let chunk_size = self.config.chunk_size;
+        let mut chunks: Vec<(u32, Vec<u8>)> = vec![];
+        for (addr, page) in flash.get_pages(PAGE_SIZE, 0) {
+            match chunks.last_mut() {
+                Some((start, data))
+                    if *start + data.len() as u32 == addr
+                        && *start / chunk_size == addr / chunk_size =>
+                {
+                    data.extend_from_slice(&page)
+                }
+                _ => chunks.push((addr, page)),
+            }
+        }
+
+        // sectors to erase, in ranges that do not cross a chunk boundary
+        let mut erases: Vec<(u32, u32)> = vec![];
+        for (addr, data) in chunks.iter() {
+            let start = addr - addr % SECTOR_SIZE;
+            let end = addr + data.len() as u32;
+            let end = end + (SECTOR_SIZE - end % SECTOR_SIZE) % SECTOR_SIZE;
+            match erases.last_mut() {
+                Some((s, e)) if start <= *s + *e && *s / chunk_size == start / chunk_size => {
+                    *e = end - *s
+                }
+                _ => erases.push((start, end - start)),
+            }
+        }
+
+        // ram is written as is, in the same chunks
+        for (start, seg) in ram.get_segments() {
+            for (addr, size) in chunk_ranges(start, seg.len() as u32, chunk_size) {
+                let offset = (addr - start) as usize;
+                chunks.push((addr, seg[offset..offset + size as usize].to_vec()));
+            }
+        }
+
+        self.check_cancelled()?;
+        self.access_exclusive_eject()?;
+        self.exit_xip()?;
+
+        match self.load_chunks(&erases, &chunks, image, options) {
+            Err(Error::CmdCancelled) => {
+                self.enter_xip()?;
+                self.access_not_exclusive()?;
+                return Err(Error::CmdCancelled);
+            }
+            res => res?,
+        }
+
+        if let Some(delay) = options.reboot {
+            match (self.target_id, ram.get_start()) {
+                (TargetID::Rp2040, Some(start)) if flash.is_empty() => {
+                    let pc = entry.unwrap_or(start);
+                    self.reboot(pc, STACK_POINTER_RP2040, delay)?
+                }
+                (TargetID::Rp2350, Some(base)) if flash.is_empty() => {
+                    // the image is searched for in the memory holding its
+                    // lowest address only
+                    let region = load_region(self.target_id, base, 1);
+                    let end = ram
+                        .get_segments()
+                        .filter(|(addr, _)| load_region(self.target_id, *addr, 1) == region)
+                        .map(|(addr, seg)| addr + seg.len() as u32)
+                        .max()
+                        .unwrap_or(base);
+                    let size = end - base;
+                    let ram_image = Reboot2Type::RamImage { base, size };
+                    self.reboot2(Reboot2Options::new(ram_image), delay)?
+                }
+                (TargetID::Rp2040, _) => self.reboot(0, STACK_POINTER_RP2040, delay)?,
+                (TargetID::Rp2350, _) => self.reboot2_normal(delay)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_chunks(
+        &mut self,
+        erases: &[(u32, u32)],
+        chunks: &[(u32, Vec<u8>)],
+        image: &SparseImage,
+        options: LoadOptions,
+    ) -> Result<()> {
+        self.erase_chunks(erases)?;
+
+        let total = chunks.iter().map(|(_, data)| data.len() as u32).sum();
+        let mut done = 0;
+        self.report(ProgressPhase::Write, done, total);
+        for (addr, data) in chunks.iter() {
+            self.check_cancelled()?;
+            self.cmd(PicobootCmd::flash_write(*addr, data.len() as u32), data)?;
+            done += data.len() as u32;
+            self.report(ProgressPhase::Write, done, total);
+        }
+
+        if options.verify {
+            self.verify(image)?;
+        }
+
+        // a cancelled load is not rebooted into
+        if options.reboot.is_some() {
+            self.check_cancelled()?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, phase: ProgressPhase, done: u32, total: u32) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.0.progress(phase, done, total);
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(Error::CmdCancelled);
+        }
+        Ok(())
+    }
+
+    /// Calls a function in RAM on the device, returning once it has
+    /// returned. (Only for RP2040)
+    ///
+    /// - `addr` - Address of the function, in SRAM or XIP SRAM. The Thumb bit
+    ///   may be set.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::ExecInvalidAddr`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn exec(&mut self, addr: u32) -> Result<()> {
+        if let TargetID::Rp2350 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+        if !is_rp2040_ram(addr & !1) {
+            return Err(Error::ExecInvalidAddr);
+        }
+
+        self.cmd(PicobootCmd::exec(addr), &[0u8; 0])?;
+        Ok(())
+    }
+
+    /// Copies the table of flash functions used by the bootrom into RAM, so
+    /// they can be replaced before calling [`Self::exec`]. (Only for RP2040)
+    ///
+    /// - `addr` - Address to copy the table to, word aligned in SRAM or XIP
+    ///   SRAM.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::VectorizeInvalidAddr`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn vectorize_flash(&mut self, addr: u32) -> Result<()> {
+        if let TargetID::Rp2350 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+        if addr % 4 != 0 || !is_rp2040_ram(addr) {
+            return Err(Error::VectorizeInvalidAddr);
+        }
+
+        self.cmd(PicobootCmd::vectorize_flash(addr), &[0u8; 0])?;
+        Ok(())
+    }
+
+    /// Reads OTP rows in raw mode, returning the 24 data bits of each row.
+    /// (Only for RP2350)
+    ///
+    /// - `row` - First row to read.
+    /// - `row_count` - Number of rows to read.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::OtpInvalidRow`]
+    /// - [`Error::OtpInvalidRowCount`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn otp_read_raw(&mut self, row: u16, row_count: u16) -> Result<Vec<u32>> {
+        self.check_otp_rows(row, row_count.into())?;
+
+        let data = self.cmd(PicobootCmd::otp_read(row, row_count, false), &[0u8; 0])?;
+        Ok(data
+            .chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect())
+    }
+
+    /// Reads OTP rows in ECC mode, returning the 16 error-corrected data
+    /// bits of each row. (Only for RP2350)
+    ///
+    /// - `row` - First row to read.
+    /// - `row_count` - Number of rows to read.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::OtpInvalidRow`]
+    /// - [`Error::OtpInvalidRowCount`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn otp_read_ecc(&mut self, row: u16, row_count: u16) -> Result<Vec<u16>> {
+        self.check_otp_rows(row, row_count.into())?;
+
+        let data = self.cmd(PicobootCmd::otp_read(row, row_count, true), &[0u8; 0])?;
+        Ok(data
+            .chunks_exact(2)
+            .map(|w| u16::from_le_bytes([w[0], w[1]]))
+            .collect())
+    }
+
+    /// Writes OTP rows in raw mode, 24 data bits per row. (Only for RP2350)
+    ///
+    /// OTP bits can only be set, never cleared.
+    ///
+    /// - `row` - First row to write.
+    /// - `values` - Values of the rows to write, at most 24 bits wide.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::OtpInvalidRow`]
+    /// - [`Error::OtpInvalidRowCount`]
+    /// - [`Error::OtpInvalidValue`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn otp_write_raw(&mut self, row: u16, values: &[u32]) -> Result<()> {
+        self.check_otp_rows(row, values.len())?;
+        if values.iter().any(|v| *v > 0xFFFFFF) {
+            return Err(Error::OtpInvalidValue);
+        }
+
+        let buf: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let cmd = PicobootCmd::otp_write(row, values.len() as u16, false);
+        self.cmd(cmd, &buf)?;
+        Ok(())
+    }
+
+    /// Writes OTP rows in ECC mode, 16 data bits per row protected by ECC
+    /// bits generated by the device. (Only for RP2350)
+    ///
+    /// - `row` - First row to write.
+    /// - `values` - Values of the rows to write.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::OtpInvalidRow`]
+    /// - [`Error::OtpInvalidRowCount`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn otp_write_ecc(&mut self, row: u16, values: &[u16]) -> Result<()> {
+        self.check_otp_rows(row, values.len())?;
+
+        let buf: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let cmd = PicobootCmd::otp_write(row, values.len() as u16, true);
+        self.cmd(cmd, &buf)?;
+        Ok(())
+    }
+
+    fn check_otp_rows(&self, row: u16, row_count: usize) -> Result<()> {
+        if let TargetID::Rp2040 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+        if row >= OTP_ROW_COUNT {
+            return Err(Error::OtpInvalidRow);
+        }
+        if row_count == 0 || usize::from(row) + row_count > usize::from(OTP_ROW_COUNT) {
+            return Err(Error::OtpInvalidRowCount);
+        }
+        Ok(())
+    }
+
     /// Enter Flash XIP (execute-in-place) mode.
     ///
     /// # Errors:
@@ -434,37 +1161,250 @@ impl<T: UsbContext> PicobootConnection<T> {
     /// - [`Error::UsbClearOutAddrHalt`]
     /// - [`Error::UsbResetInterfaceFailure`]
     pub fn reset_interface(&mut self) -> Result<()> {
-        self.handle
-            .clear_halt(self.in_addr)
+        self.transport
+            .clear_halt(Endpoint::In)
             .map_err(Error::UsbClearInAddrHalt)?;
-        self.handle
-            .clear_halt(self.out_addr)
+        self.transport
+            .clear_halt(Endpoint::Out)
             .map_err(Error::UsbClearOutAddrHalt)?;
 
-        let timeout = std::time::Duration::from_secs(1);
-        self.handle
-            .write_control(0x41, 0x41, 0, self.iface.into(), &[0u8; 0], timeout)
+        let timeout = self.config.status_timeout;
+        let iface = self.transport.interface_number();
+        self.transport
+            .write_control(0x41, 0x41, 0, iface.into(), &[0u8; 0], timeout)
             .map_err(Error::UsbResetInterfaceFailure)?;
 
         Ok(())
     }
 
+    /// Queries the identity of the device and updates the target of the
+    /// connection to match.
+    ///
+    /// The chip type and bootrom version are read from
+    /// [`ROM_IDENTITY_ADDR`]. On RP2350, the chip information, OTP critical
+    /// register, CPU architecture and flash device information are added
+    /// from a GET_INFO (INFO_SYS) command.
+    ///
+    /// # Errors:
+    /// - [`Error::ChipIdentityInvalid`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn identify(&mut self) -> Result<ChipInfo> {
+        let rom = self.flash_read(ROM_IDENTITY_ADDR, 4)?;
+        let mut info = ChipInfo::from_rom_identity(&rom)?;
+
+        self.target_id = info.get_target_id();
+        if let TargetID::Rp2350 = self.target_id {
+            let flags = SYS_INFO_CHIP_INFO
+                | SYS_INFO_CRITICAL
+                | SYS_INFO_CPU_INFO
+                | SYS_INFO_FLASH_DEV_INFO;
+            info = info.with_sys_info(&self.get_sys_info(flags)?);
+        }
+
+        self.chip_info = Some(info);
+        Ok(info)
+    }
+
+    /// Queries system information with GET_INFO (INFO_SYS). (Only for RP2350)
+    ///
+    /// - `flags` - `SYS_INFO_*` flags of the information requested.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn get_sys_info(&mut self, flags: u32) -> Result<SysInfo> {
+        if let TargetID::Rp2040 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+
+        let data = self.cmd(PicobootCmd::get_info_sys(flags), &[0u8; 0])?;
+        SysInfo::from_response(&data)
+    }
+
+    /// Queries the partition table with GET_INFO (INFO_PARTITION_TABLE).
+    /// (Only for RP2350)
+    ///
+    /// - `flags` - `PT_INFO_*` flags of the information requested. The
+    ///   location and flags of each partition are always requested along with
+    ///   their id, family ids or name.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn get_partition_table_info(&mut self, flags: u32) -> Result<PartitionTableInfo> {
+        let flags = flags & !PT_INFO_SINGLE_PARTITION;
+        self.partition_table_info(flags, None)
+    }
+
+    /// Queries a single partition with GET_INFO (INFO_PARTITION_TABLE).
+    /// (Only for RP2350)
+    ///
+    /// - `partition` - Index of the partition.
+    /// - `flags` - `PT_INFO_*` flags of the information requested, as in
+    ///   [`Self::get_partition_table_info`].
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn get_partition_info(&mut self, partition: u8, flags: u32) -> Result<PartitionInfo> {
+        let flags = flags | PT_INFO_SINGLE_PARTITION | PT_INFO_PARTITION_LOCATION_AND_FLAGS;
+        let info = self.partition_table_info(flags, Some(partition))?;
+        info.get_partitions()
+            .first()
+            .cloned()
+            .ok_or(Error::InfoResponseInvalid)
+    }
+
+    fn partition_table_info(
+        &mut self,
+        mut flags: u32,
+        partition: Option<u8>,
+    ) -> Result<PartitionTableInfo> {
+        if let TargetID::Rp2040 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+
+        // the partition data can only be decoded with its location and flags
+        let per_partition =
+            PT_INFO_PARTITION_ID | PT_INFO_PARTITION_FAMILY_IDS | PT_INFO_PARTITION_NAME;
+        if flags & per_partition != 0 {
+            flags |= PT_INFO_PARTITION_LOCATION_AND_FLAGS;
+        }
+
+        let cmd = PicobootCmd::get_info_partition_table(flags, partition.unwrap_or(0));
+        let data = self.cmd(cmd, &[0u8; 0])?;
+        PartitionTableInfo::from_response(&data, partition)
+    }
+
+    /// Queries the partition a UF2 download of a family would be written to
+    /// with GET_INFO (INFO_UF2_TARGET_PARTITION). (Only for RP2350)
+    ///
+    /// Returns `None` if no partition accepts the family.
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn get_uf2_target_partition(&mut self, family_id: u32) -> Result<Option<PartitionInfo>> {
+        if let TargetID::Rp2040 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+
+        let cmd = PicobootCmd::get_info_uf2_target_partition(family_id);
+        let data = self.cmd(cmd, &[0u8; 0])?;
+        PartitionInfo::from_uf2_target_response(&data)
+    }
+
+    /// Queries the status of the last UF2 download with GET_INFO
+    /// (INFO_UF2_STATUS). (Only for RP2350)
+    ///
+    /// # Errors:
+    /// - [`Error::CmdNotAllowedForTarget`]
+    /// - [`Error::InfoResponseInvalid`]
+    /// - Any produced by [`Self::cmd`]
+    pub fn get_uf2_status(&mut self) -> Result<Uf2Status> {
+        if let TargetID::Rp2040 = self.target_id {
+            return Err(Error::CmdNotAllowedForTarget);
+        }
+
+        let data = self.cmd(PicobootCmd::get_info_uf2_status(), &[0u8; 0])?;
+        Uf2Status::from_response(&data)
+    }
+
+    /// Returns the device identity found by the last call to
+    /// [`Self::identify`], if any.
+    pub fn get_chip_info(&self) -> Option<ChipInfo> {
+        self.chip_info
+    }
+
     fn get_command_status(&mut self) -> Result<PicobootStatusCmd> {
-        let timeout = std::time::Duration::from_secs(1);
+        let timeout = self.config.status_timeout;
         let mut buf = [0u8; 16];
+        let iface = self.transport.interface_number();
         let _res = self
-            .handle
-            .read_control(0xC1, 0x42, 0, self.iface.into(), &mut buf, timeout)
+            .transport
+            .read_control(0xC1, 0x42, 0, iface.into(), &mut buf, timeout)
             .map_err(Error::UsbGetCommandStatusFailure)?;
-        let buf = bincode::deserialize(&buf).map_err(Error::CmdDeserializeFailure)?;
-
-        Ok(buf)
+        Ok(PicobootStatusCmd::from_bytes(&buf)?)
     }
 
     /// Returns PICOBOOT device type.
     ///
-    /// Device type is determined by [`Self::new`].
+    /// Device type is confirmed by [`Self::identify`], which is called when
+    /// the connection is opened.
     pub fn get_device_type(&self) -> TargetID {
         self.target_id
     }
 }
+
+/// Memory a segment of a loaded image is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoadRegion {
+    Flash,
+    Sram,
+    XipSram,
+}
+
+/// Returns the memory a range of a target is in, if it is entirely in flash,
+/// SRAM or XIP SRAM.
+fn load_region(target_id: TargetID, addr: u32, size: u32) -> Option<LoadRegion> {
+    let (flash_end, sram_end, xip_sram_start, xip_sram_end) = match target_id {
+        TargetID::Rp2040 => (
+            FLASH_END_RP2040,
+            SRAM_END_RP2040,
+            XIP_SRAM_START_RP2040,
+            XIP_SRAM_END_RP2040,
+        ),
+        TargetID::Rp2350 => (
+            FLASH_END_RP2350,
+            SRAM_END_RP2350,
+            XIP_SRAM_START_RP2350,
+            XIP_SRAM_END_RP2350,
+        ),
+    };
+    let end = addr as u64 + size as u64;
+    let within = |start: u32, stop: u32| addr >= start && end <= stop.into();
+
+    if within(FLASH_START, flash_end) {
+        Some(LoadRegion::Flash)
+    } else if within(SRAM_START_RP2040, sram_end) {
+        Some(LoadRegion::Sram)
+    } else if within(xip_sram_start, xip_sram_end) {
+        Some(LoadRegion::XipSram)
+    } else {
+        None
+    }
+}
+
+/// Boxed [`ProgressObserver`] of a connection.
+struct Observer(Box<dyn ProgressObserver + Send>);
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProgressObserver(..)")
+    }
+}
+
+/// Splits a range into `(addr, size)` chunks that do not cross a multiple of
+/// `chunk_size`.
+fn chunk_ranges(addr: u32, size: u32, chunk_size: u32) -> Vec<(u32, u32)> {
+    let end = addr as u64 + size as u64;
+    let mut ranges = vec![];
+    let mut at = addr as u64;
+    while at < end {
+        let next = (at / chunk_size as u64 + 1) * chunk_size as u64;
+        let next = next.min(end);
+        ranges.push((at as u32, (next - at) as u32));
+        at = next;
+    }
+    ranges
+}
+
+/// Returns `true` if an address is in the SRAM or XIP SRAM of an RP2040.
+fn is_rp2040_ram(addr: u32) -> bool {
+    (SRAM_START_RP2040..SRAM_END_RP2040).contains(&addr)
+        || (XIP_SRAM_START_RP2040..XIP_SRAM_END_RP2040).contains(&addr)
+}